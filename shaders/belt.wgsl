@@ -0,0 +1,74 @@
+// Vertex shader
+
+struct CameraUniform {
+    view_projection_matrix: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct LightUniform {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> light: LightUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(5) position_size: vec4<f32>,
+    @location(6) rotation: vec4<f32>,
+    @location(7) shape: vec3<f32>,
+    @location(8) color: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) color: vec3<f32>,
+};
+
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    let t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let local_position = model.position * instance.shape * instance.position_size.w;
+    let world_position = instance.position_size.xyz + rotate(instance.rotation, local_position);
+
+    var out: VertexOutput;
+    out.world_position = world_position;
+    out.clip_position = camera.view_projection_matrix * vec4<f32>(world_position, 1.0);
+    // Inverse of the stretch keeps normals perpendicular to the surface
+    out.normal = rotate(instance.rotation, model.normal / instance.shape);
+    out.color = instance.color;
+    return out;
+}
+
+// Fragment shader
+
+const AMBIENT_STRENGHT: f32 = 0.02;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(in.normal);
+    let light_dir = normalize(light.position - in.world_position);
+
+    let ambient_color = light.color * AMBIENT_STRENGHT;
+    let diffuse_color = light.color * max(dot(normal, light_dir), 0.0);
+
+    return vec4<f32>((ambient_color + diffuse_color) * in.color, 1.0);
+}
@@ -0,0 +1,114 @@
+struct BeltUniform {
+    sun_position: vec3<f32>,
+    days: f32,
+    time: f32,
+    size_factor: f32,
+    count: u32,
+    _padding: f32,
+    distance_table_range_au: vec2<f32>,
+    _padding2: vec2<f32>,
+    units_per_au: array<vec4<f32>, 16>,
+};
+
+struct RockOrbit {
+    semi_major_axis: f32,
+    eccentricity: f32,
+    inclination: f32,
+    ascending_node: f32,
+    argument_of_perihelion: f32,
+    mean_anomaly: f32,
+    mean_motion: f32,
+    size: f32,
+    spin_axis: vec3<f32>,
+    spin_rate: f32,
+};
+
+struct RockInstance {
+    position: vec3<f32>,
+    size: f32,
+    rotation: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> belt: BeltUniform;
+@group(0) @binding(1)
+var<storage, read> orbits: array<RockOrbit>;
+@group(0) @binding(2)
+var<storage, read_write> instances: array<RockInstance>;
+
+const TAU: f32 = 6.283185307179586;
+const DISTANCE_TABLE_SIZE: u32 = 64u;
+
+// Solves Kepler's equation `M = E - e sin E` with Newton's method, eccentricities of the
+// belts are small, so a few iterations are enough
+fn eccentric_anomaly(mean_anomaly: f32, eccentricity: f32) -> f32 {
+    var anomaly = mean_anomaly + eccentricity * sin(mean_anomaly);
+    for (var i = 0; i < 4; i++) {
+        anomaly -= (anomaly - eccentricity * sin(anomaly) - mean_anomaly)
+            / (1.0 - eccentricity * cos(anomaly));
+    }
+    return anomaly;
+}
+
+fn units_per_au_at(index: u32) -> f32 {
+    return belt.units_per_au[index / 4u][index % 4u];
+}
+
+// Linear interpolation of the table filled by `Belts::update`
+fn units_per_au(distance_au: f32) -> f32 {
+    let range = belt.distance_table_range_au;
+    let t = clamp(log(distance_au / range.x) / log(range.y / range.x), 0.0, 1.0);
+    let position = t * f32(DISTANCE_TABLE_SIZE - 1u);
+    let index = min(u32(position), DISTANCE_TABLE_SIZE - 2u);
+    return mix(units_per_au_at(index), units_per_au_at(index + 1u), position - f32(index));
+}
+
+fn axis_angle(axis: vec3<f32>, angle: f32) -> vec4<f32> {
+    return vec4<f32>(axis * sin(angle * 0.5), cos(angle * 0.5));
+}
+
+fn rotate_z(v: vec3<f32>, angle: f32) -> vec3<f32> {
+    let c = cos(angle);
+    let s = sin(angle);
+    return vec3<f32>(c * v.x - s * v.y, s * v.x + c * v.y, v.z);
+}
+
+fn rotate_x(v: vec3<f32>, angle: f32) -> vec3<f32> {
+    let c = cos(angle);
+    let s = sin(angle);
+    return vec3<f32>(v.x, c * v.y - s * v.z, s * v.y + c * v.z);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= belt.count {
+        return;
+    }
+    let orbit = orbits[index];
+
+    let e = orbit.eccentricity;
+    let mean_anomaly = (orbit.mean_anomaly + orbit.mean_motion * belt.days) % TAU;
+    let anomaly = eccentric_anomaly(mean_anomaly, e);
+
+    // Position in the orbital plane, with `x` pointing to the perihelion, rotated into the
+    // ecliptic frame
+    var position = orbit.semi_major_axis * vec3<f32>(
+        cos(anomaly) - e,
+        sqrt(1.0 - e * e) * sin(anomaly),
+        0.0,
+    );
+    position = rotate_z(position, orbit.argument_of_perihelion);
+    position = rotate_x(position, orbit.inclination);
+    position = rotate_z(position, orbit.ascending_node);
+
+    let distance_au = length(position);
+    // From the ecliptic frame, where `z` is up, to the scene frame, where `y` is up
+    let heliocentric = vec3<f32>(position.x, position.z, -position.y) * units_per_au(distance_au);
+
+    var instance: RockInstance;
+    instance.position = belt.sun_position + heliocentric;
+    instance.size = orbit.size * belt.size_factor;
+    instance.rotation = axis_angle(orbit.spin_axis, orbit.spin_rate * belt.time);
+    instances[index] = instance;
+}
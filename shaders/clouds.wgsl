@@ -0,0 +1,138 @@
+// Vertex shader
+
+struct CameraUniform {
+    view_projection_matrix: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct LightUniform {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+
+@group(2) @binding(0)
+var<uniform> light: LightUniform;
+
+struct CloudsUniform {
+    world_to_local: mat4x4<f32>,
+    center: vec3<f32>,
+    planet_radius: f32,
+    shell_radius: f32,
+    coverage: f32,
+    shadow_strength: f32,
+    night_brightness: f32,
+    planet_texture_index: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> clouds: CloudsUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) texture_index: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) local_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    var out: VertexOutput;
+    out.local_position = model.position;
+    out.world_normal = normal_matrix * model.normal;
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_projection_matrix * world_position;
+    return out;
+}
+
+// Fragment shader
+
+// Must be kept in sync with `cloud_density` in `planet.wgsl`
+fn hash(p: vec3<f32>) -> f32 {
+    let q = fract(p * 0.3183099 + vec3<f32>(0.71, 0.113, 0.419));
+    let r = q * 17.0;
+    return fract(r.x * r.y * r.z * (r.x + r.y + r.z));
+}
+
+fn value_noise(p: vec3<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(
+            mix(hash(i + vec3<f32>(0.0, 0.0, 0.0)), hash(i + vec3<f32>(1.0, 0.0, 0.0)), u.x),
+            mix(hash(i + vec3<f32>(0.0, 1.0, 0.0)), hash(i + vec3<f32>(1.0, 1.0, 0.0)), u.x),
+            u.y,
+        ),
+        mix(
+            mix(hash(i + vec3<f32>(0.0, 0.0, 1.0)), hash(i + vec3<f32>(1.0, 0.0, 1.0)), u.x),
+            mix(hash(i + vec3<f32>(0.0, 1.0, 1.0)), hash(i + vec3<f32>(1.0, 1.0, 1.0)), u.x),
+            u.y,
+        ),
+        u.z,
+    );
+}
+
+fn cloud_density(local_direction: vec3<f32>) -> f32 {
+    var p = normalize(local_direction) * 4.5;
+    var value = 0.0;
+    var amplitude = 0.5;
+    for (var i = 0; i < 5; i++) {
+        value += amplitude * value_noise(p);
+        p = p * 2.03;
+        amplitude *= 0.5;
+    }
+    let threshold = 1.0 - clouds.coverage;
+    return smoothstep(threshold, threshold + 0.2, value);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let density = cloud_density(in.local_position);
+
+    let normal = normalize(in.world_normal);
+    let light_dir = normalize(light.position - in.world_position);
+    let dot_product = dot(normal, light_dir);
+
+    // Soft transition so the clouds fade through the terminator instead of switching off
+    let day_factor = smoothstep(-0.15, 0.25, dot_product);
+    let diffuse_strength = max(dot_product, 0.0) * day_factor;
+    let lighting = light.color * (diffuse_strength + clouds.night_brightness);
+
+    return vec4<f32>(lighting, density);
+}
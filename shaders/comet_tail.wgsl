@@ -0,0 +1,115 @@
+struct CameraUniform {
+    view_projection_matrix: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct TailUniform {
+    origin: vec3<f32>,
+    length: f32,
+    direction: vec3<f32>,
+    width: f32,
+    trailing: vec3<f32>,
+    curvature: f32,
+    color: vec3<f32>,
+    brightness: f32,
+}
+
+// Must match `CometTails::MAX_COMETS` (two tails per comet)
+const MAX_TAILS: u32 = 8u;
+// Must match `CometTails::PARTICLES_PER_TAIL`
+const PARTICLES_PER_TAIL: u32 = 4096u;
+
+struct CometTailsUniform {
+    tails: array<TailUniform, MAX_TAILS>,
+    time: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> comet_tails: CometTailsUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Position on the billboard, from -1.0 to 1.0
+    @location(0) offset: vec2<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+const TAU: f32 = 6.283185307179586;
+// Fraction of the tail travelled by a particle per second
+const FLOW_SPEED: f32 = 0.05;
+// Relative to the length of the tail
+const PARTICLE_SIZE: f32 = 0.012;
+// Particles overlap a lot, so each one is faint
+const PARTICLE_INTENSITY: f32 = 0.08;
+
+// Integer hash (PCG), returns a value in [0, 1)
+fn random(seed: u32) -> f32 {
+    let state = seed * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return f32((word >> 22u) ^ word) / 4294967296.0;
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+    @builtin(instance_index) ii: u32,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vi];
+    let tail = comet_tails.tails[ii / PARTICLES_PER_TAIL];
+
+    // Every particle keeps streaming from the nucleus to the end of the tail, at its own
+    // speed, so that the tail looks alive
+    let speed = FLOW_SPEED * (0.5 + random(ii * 4u + 1u));
+    let along = fract(random(ii * 4u) + comet_tails.time * speed);
+
+    // Frame perpendicular to the tail, the fallback covers motion along the tail direction
+    var side = cross(tail.direction, tail.trailing);
+    if dot(side, side) < 1e-6 {
+        side = cross(tail.direction, vec3<f32>(0.0, 1.0, 0.0));
+    }
+    side = normalize(side);
+    let normal = cross(side, tail.direction);
+
+    // The tail widens away from the nucleus, particles are denser close to the axis
+    let angle = random(ii * 4u + 2u) * TAU;
+    let radius = sqrt(random(ii * 4u + 3u)) * tail.width * (0.1 + along);
+    let spread = (side * cos(angle) + normal * sin(angle)) * radius;
+
+    // Dust is pushed away more slowly than ions, so it lags behind the comet and the tail
+    // curves towards the path already travelled
+    let axis = tail.direction * along + tail.trailing * (tail.curvature * along * along);
+    let center = tail.origin + (axis + spread) * tail.length;
+
+    // Camera right and up vectors, so the billboard always faces the camera
+    let right = camera.inv_view[0].xyz;
+    let up = camera.inv_view[1].xyz;
+    let size = tail.length * PARTICLE_SIZE * (0.5 + along);
+    let world_position = center + (right * corner.x + up * corner.y) * size;
+
+    // Fades in at the nucleus and out towards the end of the tail
+    let fade = smoothstep(0.0, 0.05, along) * (1.0 - along) * (1.0 - along);
+
+    var out: VertexOutput;
+    out.offset = corner;
+    out.color = tail.color * tail.brightness * fade * PARTICLE_INTENSITY;
+    out.clip_position = camera.view_projection_matrix * vec4<f32>(world_position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let falloff = exp(-dot(in.offset, in.offset) * 4.0);
+    return vec4<f32>(in.color * falloff, 0.0);
+}
@@ -0,0 +1,79 @@
+struct CameraUniform {
+    view_projection_matrix: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct StarfieldUniform {
+    viewport_size: vec2<f32>,
+    glyph_scale: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> starfield: StarfieldUniform;
+
+struct InstanceInput {
+    @location(0) direction: vec3<f32>,
+    @location(1) offset: vec2<f32>,
+    @location(2) bitmap: vec2<u32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Position in the glyph, in font pixels from the top left corner
+    @location(0) position: vec2<f32>,
+    @location(1) @interpolate(flat) bitmap: vec2<u32>,
+};
+
+// Must match `font::WIDTH` and `font::HEIGHT`
+const GLYPH_WIDTH: u32 = 5u;
+const GLYPH_HEIGHT: u32 = 7u;
+const LABEL_COLOR: vec3<f32> = vec3<f32>(0.12, 0.18, 0.35);
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let size = vec2<f32>(f32(GLYPH_WIDTH), f32(GLYPH_HEIGHT));
+    let corner = corners[vi] * size;
+
+    // Labels are drawn on the infinite far plane, with a constant size in pixels
+    var clip_position = camera.view_projection_matrix * vec4<f32>(instance.direction, 0.0);
+    let pixels = (instance.offset + corner) * starfield.glyph_scale;
+    clip_position.x += pixels.x * 2.0 / starfield.viewport_size.x * clip_position.w;
+    clip_position.y += pixels.y * 2.0 / starfield.viewport_size.y * clip_position.w;
+
+    var out: VertexOutput;
+    out.clip_position = clip_position;
+    out.position = vec2<f32>(corner.x, size.y - corner.y);
+    out.bitmap = instance.bitmap;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let cell = min(vec2<u32>(in.position), vec2<u32>(GLYPH_WIDTH - 1u, GLYPH_HEIGHT - 1u));
+    let bit = cell.y * GLYPH_WIDTH + cell.x;
+    var word = in.bitmap.x;
+    var shift = bit;
+    if bit >= 32u {
+        word = in.bitmap.y;
+        shift = bit - 32u;
+    }
+    if ((word >> shift) & 1u) == 0u {
+        discard;
+    }
+    return vec4<f32>(LABEL_COLOR, 0.0);
+}
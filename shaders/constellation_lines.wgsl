@@ -0,0 +1,27 @@
+struct CameraUniform {
+    view_projection_matrix: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+const LINE_COLOR: vec3<f32> = vec3<f32>(0.05, 0.09, 0.2);
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) direction: vec3<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    // On the infinite far plane, like the stars
+    out.clip_position = camera.view_projection_matrix * vec4<f32>(direction, 0.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(LINE_COLOR, 0.0);
+}
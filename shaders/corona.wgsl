@@ -0,0 +1,86 @@
+struct CameraUniform {
+    view_projection_matrix: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct SunUniform {
+    center: vec3<f32>,
+    radius: f32,
+    time: f32,
+    intensity: f32,
+    corona_scale: f32,
+    corona_intensity: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> sun: SunUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Position on the billboard, in units of the sun radius
+    @location(0) offset: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vi] * sun.corona_scale;
+
+    // Camera right and up vectors, so the billboard always faces the camera
+    let right = camera.inv_view[0].xyz;
+    let up = camera.inv_view[1].xyz;
+    let world_position = sun.center + (right * corner.x + up * corner.y) * sun.radius;
+
+    var out: VertexOutput;
+    out.offset = corner;
+    out.clip_position = camera.view_projection_matrix * vec4<f32>(world_position, 1.0);
+    return out;
+}
+
+const CORONA_COLOR: vec3<f32> = vec3<f32>(1.0, 0.78, 0.5);
+
+fn hash(p: vec2<f32>) -> f32 {
+    let q = fract(p * vec2<f32>(0.3183099, 0.3678794) + vec2<f32>(0.71, 0.113));
+    let r = q * 17.0;
+    return fract(r.x * r.y * (r.x + r.y));
+}
+
+fn value_noise(p: vec2<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(hash(i), hash(i + vec2<f32>(1.0, 0.0)), u.x),
+        mix(hash(i + vec2<f32>(0.0, 1.0)), hash(i + vec2<f32>(1.0, 1.0)), u.x),
+        u.y,
+    );
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let r = length(in.offset);
+    let height = max(r - 1.0, 0.0);
+
+    // Slowly changing streamers along the angle around the disk
+    let direction = in.offset / max(r, 0.0001);
+    let streamers = value_noise(direction * 4.0 + vec2<f32>(sun.time * 0.1)) * 0.6 + 0.4;
+
+    let glow = exp(-height * 3.0) * mix(1.0, streamers, smoothstep(0.0, 0.5, height));
+    // Fade out before reaching the edge of the billboard
+    let fade = 1.0 - smoothstep(sun.corona_scale * 0.6, sun.corona_scale, r);
+
+    return vec4<f32>(CORONA_COLOR * glow * fade * sun.corona_intensity, 0.0);
+}
@@ -0,0 +1,46 @@
+const PI: f32 = 3.1415926535897932384626433832795;
+const TAU: f32 = 6.283185307179586;
+
+@group(0) @binding(0)
+var src: texture_2d<f32>;
+@group(0) @binding(1)
+var src_sampler: sampler;
+// The six faces of one cubemap of the array
+@group(0) @binding(2)
+var dst: texture_storage_2d_array<rgba8unorm, write>;
+
+// Direction of a point of a face, `uv` goes from -1.0 to 1.0 with `v` pointing down, as
+// cubemaps are sampled
+fn face_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    switch face {
+        case 0u: { return vec3<f32>(1.0, -uv.y, -uv.x); }
+        case 1u: { return vec3<f32>(-1.0, -uv.y, uv.x); }
+        case 2u: { return vec3<f32>(uv.x, 1.0, uv.y); }
+        case 3u: { return vec3<f32>(uv.x, -1.0, -uv.y); }
+        case 4u: { return vec3<f32>(uv.x, -uv.y, 1.0); }
+        default: { return vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn compute_equirect_to_cube_faces(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let size = textureDimensions(dst);
+    if gid.x >= size.x || gid.y >= size.y {
+        return;
+    }
+
+    let uv = (vec2<f32>(gid.xy) + 0.5) / vec2<f32>(size) * 2.0 - 1.0;
+    let direction = normalize(face_direction(gid.z, uv));
+
+    // Same mapping as the texture coordinates of `Sphere`, the sampler repeats across the
+    // meridian
+    let u = fract(atan2(direction.z, direction.x) / TAU);
+    let v = acos(clamp(direction.y, -1.0, 1.0)) / PI;
+    let color = textureSampleLevel(src, src_sampler, vec2<f32>(u, v), 0.0);
+
+    textureStore(dst, gid.xy, gid.z, color);
+}
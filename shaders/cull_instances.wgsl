@@ -0,0 +1,83 @@
+// Must match `CullingUniform`
+struct CullingUniform {
+    // Inward facing planes of the view frustum, relative to the camera
+    planes: array<vec4<f32>, 6>,
+    focal_length: f32,
+    min_projected_radius: f32,
+    count: u32,
+    capacity: u32,
+};
+
+// Must match `InstanceRaw`, which is 8 vec4 long
+struct InstanceRaw {
+    data: array<vec4<f32>, 8>,
+};
+
+// Layout of `wgpu::util::DrawIndexedIndirectArgs`
+struct DrawIndexedIndirectArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> culling: CullingUniform;
+@group(0) @binding(1)
+var<storage, read> instances: array<InstanceRaw>;
+// One region of `capacity` instances for every level of detail
+@group(0) @binding(2)
+var<storage, read_write> visible_instances: array<InstanceRaw>;
+@group(0) @binding(3)
+var<storage, read_write> draws: array<DrawIndexedIndirectArgs>;
+
+const PI: f32 = 3.1415926535897932384626433832795;
+
+// Must match `Sphere::LOD_SEGMENTS`, every level halves the segments of the previous one
+const FINEST_SEGMENTS: f32 = 128.0;
+const LOD_COUNT: f32 = 5.0;
+// Must match `Sphere::MAX_ERROR_PIXELS`
+const MAX_ERROR_PIXELS: f32 = 0.25;
+
+// See `Frustum::projected_radius`
+fn projected_radius(distance: f32, radius: f32) -> f32 {
+    if distance <= radius {
+        return 1e30;
+    }
+    return radius / sqrt(distance * distance - radius * radius) * culling.focal_length;
+}
+
+// See `Sphere::lod`
+fn lod(projected_radius: f32) -> u32 {
+    let segments = PI * sqrt(projected_radius / (2.0 * MAX_ERROR_PIXELS));
+    return u32(clamp(floor(log2(FINEST_SEGMENTS / segments)), 0.0, LOD_COUNT - 1.0));
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= culling.count {
+        return;
+    }
+
+    let instance = instances[index];
+    // Bodies are unit spheres scaled uniformly by the model matrix
+    let center = instance.data[3].xyz;
+    let radius = length(instance.data[0].xyz);
+    for (var i = 0u; i < 6u; i++) {
+        let plane = culling.planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius {
+            return;
+        }
+    }
+
+    let size = projected_radius(length(center), radius);
+    if size < culling.min_projected_radius {
+        return;
+    }
+
+    let level = lod(size);
+    let slot = atomicAdd(&draws[level].instance_count, 1u);
+    visible_instances[level * culling.capacity + slot] = instance;
+}
@@ -0,0 +1,65 @@
+// Basis of a cubemap face, `down` points to the next row of texels
+struct Face {
+    forward: vec3<f32>,
+    right: vec3<f32>,
+    down: vec3<f32>,
+}
+
+@group(0)
+@binding(0)
+var src: texture_cube<f32>;
+
+@group(0)
+@binding(1)
+var src_sampler: sampler;
+
+@group(0)
+@binding(2)
+var dst: texture_storage_2d_array<rgba16float, write>;
+
+// Direction of the center of a texel of a face of the given size, must be kept in sync with
+// `texel_direction` in `environment.wgsl`
+fn texel_direction(texel: vec2<f32>, layer: u32, size: f32) -> vec3<f32> {
+    var FACES: array<Face, 6> = array(
+        Face(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, -1.0), vec3(0.0, -1.0, 0.0)),
+        Face(vec3(-1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), vec3(0.0, -1.0, 0.0)),
+        Face(vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0)),
+        Face(vec3(0.0, -1.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, -1.0)),
+        Face(vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 0.0), vec3(0.0, -1.0, 0.0)),
+        Face(vec3(0.0, 0.0, -1.0), vec3(-1.0, 0.0, 0.0), vec3(0.0, -1.0, 0.0)),
+    );
+    let cube_uv = (texel + 0.5) / size * 2.0 - 1.0;
+    let face = FACES[layer];
+    return normalize(face.forward + face.right * cube_uv.x + face.down * cube_uv.y);
+}
+
+// Every texel of `dst` is the average of the texels of `src` it covers. The source is
+// sampled as a cubemap (with nearest filtering, so every sample is exactly one texel), as
+// some backends can't view a cubemap as an array of layers.
+@compute
+@workgroup_size(8, 8, 1)
+fn compute_downsample(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let dst_size = textureDimensions(dst).x;
+    if gid.x >= dst_size || gid.y >= dst_size {
+        return;
+    }
+
+    let src_size = textureDimensions(src).x;
+    let ratio = f32(src_size) / f32(dst_size);
+    let start = vec2<u32>(floor(vec2<f32>(gid.xy) * ratio));
+    let end = max(vec2<u32>(floor(vec2<f32>(gid.xy + 1u) * ratio)), start + 1u);
+
+    var sum = vec3<f32>(0.0);
+    for (var y = start.y; y < end.y; y++) {
+        for (var x = start.x; x < end.x; x++) {
+            let direction = texel_direction(vec2<f32>(f32(x), f32(y)), gid.z, f32(src_size));
+            sum += textureSampleLevel(src, src_sampler, direction, 0.0).rgb;
+        }
+    }
+    let count = f32((end.x - start.x) * (end.y - start.y));
+
+    textureStore(dst, gid.xy, gid.z, vec4<f32>(sum / count, 1.0));
+}
@@ -0,0 +1,139 @@
+const PI: f32 = 3.1415926535897932384626433832795;
+
+// Basis of a cubemap face, `down` points to the next row of texels
+struct Face {
+    forward: vec3<f32>,
+    right: vec3<f32>,
+    down: vec3<f32>,
+}
+
+struct PrefilterUniform {
+    roughness: f32,
+}
+
+@group(0)
+@binding(0)
+var src: texture_cube<f32>;
+
+@group(0)
+@binding(1)
+var src_sampler: sampler;
+
+@group(0)
+@binding(2)
+var dst: texture_storage_2d_array<rgba16float, write>;
+
+// Only used by `compute_prefiltered`
+@group(0)
+@binding(3)
+var<uniform> prefilter: PrefilterUniform;
+
+// Direction sampled by the center of a texel. Unlike in `equirectangular.wgsl`, this
+// follows the layout used by the hardware, as the result is sampled as a cubemap. Must be
+// kept in sync with `texel_direction` in `downsample_cubemap.wgsl`.
+fn texel_direction(gid: vec3<u32>) -> vec3<f32> {
+    var FACES: array<Face, 6> = array(
+        Face(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, -1.0), vec3(0.0, -1.0, 0.0)),
+        Face(vec3(-1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), vec3(0.0, -1.0, 0.0)),
+        Face(vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0)),
+        Face(vec3(0.0, -1.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, -1.0)),
+        Face(vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 0.0), vec3(0.0, -1.0, 0.0)),
+        Face(vec3(0.0, 0.0, -1.0), vec3(-1.0, 0.0, 0.0), vec3(0.0, -1.0, 0.0)),
+    );
+    let dst_dimensions = vec2<f32>(textureDimensions(dst));
+    let cube_uv = (vec2<f32>(gid.xy) + 0.5) / dst_dimensions * 2.0 - 1.0;
+    let face = FACES[gid.z];
+    return normalize(face.forward + face.right * cube_uv.x + face.down * cube_uv.y);
+}
+
+// Any two vectors perpendicular to `normal` and to each other
+fn tangent_frame(normal: vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if abs(normal.y) > 0.999 {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return mat3x3<f32>(tangent, bitangent, normal);
+}
+
+// Radiance reflected by a white Lambertian surface facing each direction, i.e. the
+// irradiance divided by PI. Integrated over the hemisphere on a regular grid of angles.
+@compute
+@workgroup_size(8, 8, 1)
+fn compute_irradiance(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let size = textureDimensions(dst).x;
+    if gid.x >= size || gid.y >= size {
+        return;
+    }
+
+    let frame = tangent_frame(texel_direction(gid));
+    let step = 0.05;
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += step) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += step) {
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let radiance = textureSampleLevel(src, src_sampler, frame * local, 0.0).rgb;
+            sum += radiance * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    textureStore(dst, gid.xy, gid.z, vec4<f32>(PI * sum / count, 1.0));
+}
+
+fn radical_inverse(bits_in: u32) -> f32 {
+    var bits = bits_in;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+// Half vector of the GGX distribution, around the z axis
+fn importance_sample_ggx(xi: vec2<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+const PREFILTER_SAMPLES: u32 = 256u;
+
+// Environment seen in a mirror of the given roughness, assuming that the view direction is
+// the normal (split sum approximation)
+@compute
+@workgroup_size(8, 8, 1)
+fn compute_prefiltered(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let size = textureDimensions(dst).x;
+    if gid.x >= size || gid.y >= size {
+        return;
+    }
+
+    let normal = texel_direction(gid);
+    let frame = tangent_frame(normal);
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < PREFILTER_SAMPLES; i++) {
+        let xi = vec2<f32>(f32(i) / f32(PREFILTER_SAMPLES), radical_inverse(i));
+        let half_dir = frame * importance_sample_ggx(xi, prefilter.roughness);
+        let light_dir = normalize(2.0 * dot(normal, half_dir) * half_dir - normal);
+        let n_dot_l = dot(normal, light_dir);
+        if n_dot_l > 0.0 {
+            sum += textureSampleLevel(src, src_sampler, light_dir, 0.0).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    textureStore(dst, gid.xy, gid.z, vec4<f32>(sum / max(weight, 0.0001), 1.0));
+}
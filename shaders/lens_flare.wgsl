@@ -0,0 +1,116 @@
+struct LensFlareUniform {
+    sun_position: vec2<f32>,
+    sun_radius: f32,
+    sun_depth: f32,
+    color: vec3<f32>,
+    intensity: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> flare: LensFlareUniform;
+
+@group(0) @binding(1)
+var depth_texture: texture_2d<f32>;
+
+const PI: f32 = 3.1415926535897932384626433832795;
+// Relative, as the reversed depth of distant objects is very close to zero
+const DEPTH_EPSILON: f32 = 0.001;
+const OCCLUSION_SAMPLES: i32 = 2;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+    // Visible fraction of the sun, constant across the whole screen
+    @location(1) @interpolate(flat) visibility: f32,
+};
+
+// Samples the depth buffer over the sun disk. Samples outside of the screen count as
+// occluded, so the flare fades out when the sun leaves the screen.
+fn sun_visibility() -> f32 {
+    let dimensions = vec2<f32>(textureDimensions(depth_texture));
+    let center = (flare.sun_position * vec2<f32>(0.5, -0.5) + 0.5) * dimensions;
+    let radius = flare.sun_radius * 0.5 * dimensions.y;
+    let step = radius * 0.8 / f32(OCCLUSION_SAMPLES);
+
+    var visible = 0.0;
+    var total = 0.0;
+    for (var x = -OCCLUSION_SAMPLES; x <= OCCLUSION_SAMPLES; x++) {
+        for (var y = -OCCLUSION_SAMPLES; y <= OCCLUSION_SAMPLES; y++) {
+            total += 1.0;
+            let position = center + vec2<f32>(f32(x), f32(y)) * step;
+            if any(position < vec2<f32>(0.0)) || any(position >= dimensions) {
+                continue;
+            }
+            let depth = textureLoad(depth_texture, vec2<i32>(position), 0).r;
+            if depth <= flare.sun_depth * (1.0 + DEPTH_EPSILON) {
+                visible += 1.0;
+            }
+        }
+    }
+    return visible / total;
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // Generate a triangle that covers the whole screen
+    let uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.ndc = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(out.ndc, 0.0, 1.0);
+    out.visibility = select(0.0, sun_visibility(), flare.intensity > 0.0);
+    return out;
+}
+
+struct Ghost {
+    // Position along the line going from the sun through the center of the screen
+    offset: f32,
+    size: f32,
+    color: vec3<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if in.visibility <= 0.0 {
+        return vec4<f32>(0.0);
+    }
+
+    let dimensions = vec2<f32>(textureDimensions(depth_texture));
+    let aspect = vec2<f32>(dimensions.x / dimensions.y, 1.0);
+
+    let to_sun = (in.ndc - flare.sun_position) * aspect;
+    let distance = length(to_sun);
+    let radius = max(flare.sun_radius, 0.002);
+
+    // Glare around the sun
+    let glare = exp(-distance / (radius * 2.0)) * 0.5 + 0.004 / (distance + 0.04);
+
+    // Six pointed starburst
+    let angle = atan2(to_sun.y, to_sun.x);
+    let spikes = pow(abs(cos(angle * 3.0)), 80.0) + 0.5 * pow(abs(cos(angle * 3.0 + PI * 0.5)), 120.0);
+    let starburst = spikes * exp(-distance * 6.0) * 0.35;
+
+    // Ghosts are reflections between lens elements, mirrored around the screen center
+    var ghosts = array<Ghost, 5>(
+        Ghost(-0.4, 0.05, vec3<f32>(0.3, 0.5, 1.0)),
+        Ghost(-0.7, 0.12, vec3<f32>(0.4, 1.0, 0.5)),
+        Ghost(-1.1, 0.07, vec3<f32>(1.0, 0.6, 0.3)),
+        Ghost(-1.5, 0.2, vec3<f32>(0.5, 0.4, 1.0)),
+        Ghost(0.5, 0.04, vec3<f32>(1.0, 0.9, 0.6)),
+    );
+    var ghost_color = vec3<f32>(0.0);
+    for (var i = 0; i < 5; i++) {
+        let ghost = ghosts[i];
+        let center = flare.sun_position * ghost.offset;
+        let d = length((in.ndc - center) * aspect);
+        let disk = 1.0 - smoothstep(ghost.size * 0.6, ghost.size, d);
+        ghost_color += ghost.color * disk * 0.06;
+    }
+
+    let color = (glare + starburst) * flare.color + ghost_color;
+    return vec4<f32>(color * flare.intensity * in.visibility, 0.0);
+}
@@ -0,0 +1,145 @@
+// Vertex shader
+
+struct CameraUniform {
+    view_projection_matrix: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct LightUniform {
+    position: vec3<f32>,
+    color: vec3<f32>,
+    // From the scene frame to the frame of the environment maps
+    environment_rotation: mat4x4<f32>,
+    environment_intensity: f32,
+}
+
+@group(2) @binding(0)
+var<uniform> light: LightUniform;
+@group(2) @binding(1)
+var irradiance_map: texture_cube<f32>;
+@group(2) @binding(2)
+var prefiltered_map: texture_cube<f32>;
+@group(2) @binding(3)
+var environment_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) texture_index: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) local_position: vec3<f32>,
+    @location(3) body_index: u32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    var out: VertexOutput;
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.world_normal = normal_matrix * model.normal;
+    out.local_position = model.position;
+    out.body_index = instance.texture_index;
+    out.clip_position = camera.view_projection_matrix * world_position;
+    return out;
+}
+
+// Fragment shader
+
+struct BodyStyle {
+    base_color: vec3<f32>,
+    detail_color: vec3<f32>,
+}
+
+// Indexed by the texture index of the instance, see `MinorBodies::MAX_BODIES`
+@group(0) @binding(0)
+var<uniform> styles: array<BodyStyle, 8>;
+
+
+fn hash(p: vec3<f32>) -> f32 {
+    let q = fract(p * 0.3183099 + vec3<f32>(0.71, 0.113, 0.419));
+    let r = q * 17.0;
+    return fract(r.x * r.y * r.z * (r.x + r.y + r.z));
+}
+
+fn value_noise(p: vec3<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(
+            mix(hash(i + vec3<f32>(0.0, 0.0, 0.0)), hash(i + vec3<f32>(1.0, 0.0, 0.0)), u.x),
+            mix(hash(i + vec3<f32>(0.0, 1.0, 0.0)), hash(i + vec3<f32>(1.0, 1.0, 0.0)), u.x),
+            u.y,
+        ),
+        mix(
+            mix(hash(i + vec3<f32>(0.0, 0.0, 1.0)), hash(i + vec3<f32>(1.0, 0.0, 1.0)), u.x),
+            mix(hash(i + vec3<f32>(0.0, 1.0, 1.0)), hash(i + vec3<f32>(1.0, 1.0, 1.0)), u.x),
+            u.y,
+        ),
+        u.z,
+    );
+}
+
+// Large patches of the detail color over the base color, evaluated on the unit sphere so
+// that there are no seams or pinching at the poles. Every body gets its own pattern.
+fn surface_pattern(local_position: vec3<f32>, body_index: u32) -> f32 {
+    var p = normalize(local_position) * 3.0 + vec3<f32>(f32(body_index) * 7.31);
+    var value = 0.0;
+    var amplitude = 0.5;
+    for (var i = 0; i < 5; i++) {
+        value += amplitude * value_noise(p);
+        p = p * 2.1;
+        amplitude *= 0.5;
+    }
+    return smoothstep(0.35, 0.65, value);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let style = styles[in.body_index];
+    let object_color = mix(style.base_color, style.detail_color, surface_pattern(in.local_position, in.body_index));
+
+    let normal = normalize(in.world_normal);
+    let light_dir = normalize(light.position - in.world_position);
+
+    // Light of the surroundings, from the skybox
+    let environment_normal = (light.environment_rotation * vec4<f32>(normal, 0.0)).xyz;
+    let ambient_color = textureSampleLevel(irradiance_map, environment_sampler, environment_normal, 0.0).rgb * light.environment_intensity;
+    let diffuse_color = light.color * max(dot(normal, light_dir), 0.0);
+
+    return vec4<f32>((ambient_color + diffuse_color) * object_color, 1.0);
+}
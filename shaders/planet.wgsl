@@ -12,15 +12,38 @@ var<uniform> camera: CameraUniform;
 struct LightUniform {
     position: vec3<f32>,
     color: vec3<f32>,
+    // From the scene frame to the frame of the environment maps
+    environment_rotation: mat4x4<f32>,
+    environment_intensity: f32,
 }
 
 @group(2) @binding(0)
 var<uniform> light: LightUniform;
+@group(2) @binding(1)
+var irradiance_map: texture_cube<f32>;
+@group(2) @binding(2)
+var prefiltered_map: texture_cube<f32>;
+@group(2) @binding(3)
+var environment_sampler: sampler;
+
+struct CloudsUniform {
+    world_to_local: mat4x4<f32>,
+    center: vec3<f32>,
+    planet_radius: f32,
+    shell_radius: f32,
+    coverage: f32,
+    shadow_strength: f32,
+    night_brightness: f32,
+    planet_texture_index: u32,
+}
+
+@group(3) @binding(0)
+var<uniform> clouds: CloudsUniform;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
 }
 
 struct InstanceInput {
@@ -32,14 +55,22 @@ struct InstanceInput {
     @location(10) normal_matrix_1: vec3<f32>,
     @location(11) normal_matrix_2: vec3<f32>,
     @location(12) texture_index: u32,
+    @location(13) night_texture_index: u32,
+    @location(14) twilight_band: f32,
+    @location(15) surface_map_indices: vec2<u32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
+    // Direction from the center of the body in its own frame, textures are looked up with it
+    @location(0) local_direction: vec3<f32>,
     @location(1) texture_index: u32,
     @location(2) world_normal: vec3<f32>,
     @location(3) world_position: vec3<f32>,
+    @location(4) night_texture_index: u32,
+    @location(5) twilight_band: f32,
+    @location(6) world_tangent: vec3<f32>,
+    @location(7) surface_map_indices: vec2<u32>,
 };
 
 @vertex
@@ -59,9 +90,13 @@ fn vs_main(
         instance.normal_matrix_2,
     );
     var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
+    out.local_direction = model.position;
     out.texture_index = instance.texture_index;
+    out.night_texture_index = instance.night_texture_index;
+    out.twilight_band = instance.twilight_band;
     out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = normal_matrix * model.tangent;
+    out.surface_map_indices = instance.surface_map_indices;
     var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
     out.world_position = world_position.xyz;
     out.clip_position = camera.view_projection_matrix * world_position;
@@ -70,37 +105,161 @@ fn vs_main(
 
 // Fragment shader
 
-const EARTH_INDEX: u32 = 2;
-const EARTH_NIGHT_INDEX: u32 = 8;
+// Must match `InstanceRaw::NO_TEXTURE`
+const NO_TEXTURE: u32 = 0xffffffffu;
+
+const NIGHT_EMISSION_STRENGTH: f32 = 0.6;
 
-const AMBIENT_STRENGHT: f32 = 0.02;
-const AMBIENT_STRENGHT_NIGHT: f32 = 0.6;
+// Used for bodies without a specular map, which makes them purely diffuse
+const DEFAULT_SPECULAR: f32 = 0.0;
+const DEFAULT_ROUGHNESS: f32 = 1.0;
+
+const PI: f32 = 3.1415926535897932384626433832795;
+
+// Must match `HdrLoader::PREFILTERED_MIP_LEVELS`
+const PREFILTERED_MIP_LEVELS: f32 = 5.0;
+
+fn to_environment(direction: vec3<f32>) -> vec3<f32> {
+    return (light.environment_rotation * vec4<f32>(direction, 0.0)).xyz;
+}
 
 @group(0) @binding(0)
-var textures: texture_2d_array<f32>;
+var textures: texture_cube_array<f32>;
 @group(0) @binding(1)
 var textures_sampler: sampler;
+@group(0) @binding(2)
+var surface_maps: texture_cube_array<f32>;
+
+// Must be kept in sync with `cloud_density` in `clouds.wgsl`
+fn hash(p: vec3<f32>) -> f32 {
+    let q = fract(p * 0.3183099 + vec3<f32>(0.71, 0.113, 0.419));
+    let r = q * 17.0;
+    return fract(r.x * r.y * r.z * (r.x + r.y + r.z));
+}
+
+fn value_noise(p: vec3<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(
+            mix(hash(i + vec3<f32>(0.0, 0.0, 0.0)), hash(i + vec3<f32>(1.0, 0.0, 0.0)), u.x),
+            mix(hash(i + vec3<f32>(0.0, 1.0, 0.0)), hash(i + vec3<f32>(1.0, 1.0, 0.0)), u.x),
+            u.y,
+        ),
+        mix(
+            mix(hash(i + vec3<f32>(0.0, 0.0, 1.0)), hash(i + vec3<f32>(1.0, 0.0, 1.0)), u.x),
+            mix(hash(i + vec3<f32>(0.0, 1.0, 1.0)), hash(i + vec3<f32>(1.0, 1.0, 1.0)), u.x),
+            u.y,
+        ),
+        u.z,
+    );
+}
+
+fn cloud_density(local_direction: vec3<f32>) -> f32 {
+    var p = normalize(local_direction) * 4.5;
+    var value = 0.0;
+    var amplitude = 0.5;
+    for (var i = 0; i < 5; i++) {
+        value += amplitude * value_noise(p);
+        p = p * 2.03;
+        amplitude *= 0.5;
+    }
+    let threshold = 1.0 - clouds.coverage;
+    return smoothstep(threshold, threshold + 0.2, value);
+}
+
+// Cloud density directly above the given world position
+fn cloud_cover(world_position: vec3<f32>) -> f32 {
+    let direction = world_position - clouds.center;
+    return cloud_density((clouds.world_to_local * vec4<f32>(direction, 0.0)).xyz);
+}
+
+// Cloud density where the ray towards the sun crosses the cloud shell. The crossing point
+// is approximated by moving the surface point along the light direction by the shell height.
+fn cloud_shadow(world_position: vec3<f32>, light_dir: vec3<f32>) -> f32 {
+    let shell_height = clouds.shell_radius - clouds.planet_radius;
+    let shadow_position = world_position + light_dir * shell_height;
+    return 1.0 - cloud_cover(shadow_position) * clouds.shadow_strength;
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let light_dir = normalize(light.position - in.world_position);
 
-    let dot_product = dot(in.world_normal, light_dir);
-    
-    let faces_sun = dot_product >= 0.0;
-    let texture_index_earth = select(EARTH_NIGHT_INDEX, EARTH_INDEX, faces_sun);
-    let is_earth = in.texture_index == EARTH_INDEX;
-    let texture_index = select(in.texture_index, texture_index_earth, is_earth);
-    let object_color: vec4<f32> = textureSample(textures, textures_sampler, in.tex_coords, texture_index);
+    let geometric_normal = normalize(in.world_normal);
+
+    // The terminator follows the geometry, not the normal map
+    let geometric_dot_product = dot(geometric_normal, light_dir);
+
+    // 0 on the night side and 1 on the day side, blended across the twilight band
+    let half_band = max(in.twilight_band * 0.5, 0.0001);
+    let day_factor = smoothstep(-half_band, half_band, geometric_dot_product);
+
+    // Textures are cubemaps, so they don't pinch at the poles nor have a seam at the meridian
+    let object_color: vec4<f32> = textureSample(textures, textures_sampler, in.local_direction, in.texture_index);
+
+    // Like the night texture, surface maps are always sampled and dropped when missing
+    let has_normal_map = in.surface_map_indices.x != NO_TEXTURE;
+    let normal_map_index = select(0u, in.surface_map_indices.x, has_normal_map);
+    let normal_sample = textureSample(surface_maps, textures_sampler, in.local_direction, normal_map_index).xyz * 2.0 - 1.0;
+
+    let has_specular_map = in.surface_map_indices.y != NO_TEXTURE;
+    let specular_map_index = select(0u, in.surface_map_indices.y, has_specular_map);
+    let specular_sample = textureSample(surface_maps, textures_sampler, in.local_direction, specular_map_index);
 
-    let earth_at_night = is_earth && !faces_sun;
-    let ambient_strength = select(AMBIENT_STRENGHT, AMBIENT_STRENGHT_NIGHT, earth_at_night);
-    let ambient_color = light.color * ambient_strength;
+    // Tangent space -> world space, the tangent is re-orthogonalized after interpolation
+    let tangent = normalize(in.world_tangent - geometric_normal * dot(geometric_normal, in.world_tangent));
+    let bitangent = cross(geometric_normal, tangent);
+    let mapped_normal = normalize(tangent * normal_sample.x + bitangent * normal_sample.y + geometric_normal * normal_sample.z);
+    let normal = select(geometric_normal, mapped_normal, has_normal_map);
 
-    let diffuse_strength = max(dot_product, 0.0);
+    let dot_product = dot(normal, light_dir);
+
+    // Light of the surroundings, from the skybox
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, to_environment(normal), 0.0).rgb;
+    let ambient_color = irradiance * light.environment_intensity;
+
+    // Normal mapped surfaces must not be lit past the geometric terminator
+    let self_shadow = smoothstep(-0.05, 0.05, geometric_dot_product);
+    let diffuse_strength = max(dot_product, 0.0) * self_shadow;
     let diffuse_color = light.color * diffuse_strength;
 
-    let result = (ambient_color + diffuse_color) * object_color.xyz;
+    // Normalized Blinn-Phong, with the roughness remapped to the specular exponent
+    let specular_intensity = select(DEFAULT_SPECULAR, specular_sample.r, has_specular_map);
+    let roughness = select(DEFAULT_ROUGHNESS, specular_sample.g, has_specular_map);
+    let alpha = max(roughness * roughness, 0.01);
+    let shininess = 2.0 / (alpha * alpha) - 2.0;
+    let camera_position = camera.inv_view[3].xyz;
+    let view_dir = normalize(camera_position - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let normalization = (shininess + 8.0) / (8.0 * PI);
+    let specular_strength = specular_intensity * normalization * pow(max(dot(normal, half_dir), 0.0), shininess) * diffuse_strength;
+    let specular_color = light.color * specular_strength;
+
+    // Reflection of the surroundings, blurred according to the roughness
+    let reflected_dir = reflect(-view_dir, normal);
+    let mip_level = roughness * (PREFILTERED_MIP_LEVELS - 1.0);
+    let reflected = textureSampleLevel(prefiltered_map, environment_sampler, to_environment(reflected_dir), mip_level).rgb;
+    let environment_specular = reflected * specular_intensity * light.environment_intensity;
+
+    var lit_color = (ambient_color + diffuse_color) * object_color.xyz + specular_color + environment_specular;
+
+    // `textureSample` has to be called in uniform control flow, so the night texture is
+    // always sampled and its contribution dropped for bodies that don't declare one
+    let has_night_texture = in.night_texture_index != NO_TEXTURE;
+    let night_texture_index = select(in.texture_index, in.night_texture_index, has_night_texture);
+    let night_color: vec4<f32> = textureSample(textures, textures_sampler, in.local_direction, night_texture_index);
+    let night_strength = select(0.0, NIGHT_EMISSION_STRENGTH * (1.0 - day_factor), has_night_texture);
+    var emission_color = night_color.xyz * night_strength;
+
+    if in.texture_index == clouds.planet_texture_index {
+        // Clouds cast shadows on the lit surface and cover the emission on the night side
+        lit_color *= cloud_shadow(in.world_position, light_dir);
+        emission_color *= 1.0 - cloud_cover(in.world_position);
+    }
+
+    let result = lit_color + emission_color;
 
     return vec4<f32>(result, object_color.a);
 }
@@ -28,17 +28,20 @@ struct InstanceInput {
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
-    @location(9) normal_matrix_0: vec3<f32>,
-    @location(10) normal_matrix_1: vec3<f32>,
-    @location(11) normal_matrix_2: vec3<f32>,
-    @location(12) texture_index: u32,
+    // Inner and outer radius, forward scattering and particle density
+    @location(9) parameters: vec4<f32>,
+    @location(10) texture_index: u32,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_position: vec3<f32>,
-    @location(2) local_position: vec3<f32>, 
+    @location(2) center: vec3<f32>,
+    @location(3) world_normal: vec3<f32>,
+    @location(4) texture_index: u32,
+    @location(5) forward_scattering: f32,
+    @location(6) particle_density: f32,
 };
 
 @vertex
@@ -52,36 +55,68 @@ fn vs_main(
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
-    let normal_matrix = mat3x3<f32>(
-        instance.normal_matrix_0,
-        instance.normal_matrix_1,
-        instance.normal_matrix_2,
-    );
+    // The mesh lies on the unit circle, the radial texture coordinate picks the edge
+    let radius = mix(instance.parameters.x, instance.parameters.y, model.tex_coords.x);
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position * radius, 1.0);
     out.world_position = world_position.xyz;
     out.clip_position = camera.view_projection_matrix * world_position;
-    out.local_position = model.position;
+    out.center = model_matrix[3].xyz;
+    // The scale is uniform, so the model matrix can be used for the normal
+    out.world_normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.texture_index = instance.texture_index;
+    out.forward_scattering = instance.parameters.z;
+    out.particle_density = instance.parameters.w;
     return out;
 }
 
 // Fragment shader
 
 @group(0) @binding(0)
-var texture: texture_2d<f32>;
+var textures: texture_2d_array<f32>;
 @group(0) @binding(1)
 var texture_sampler: sampler;
 
+// Asymmetry of the Henyey-Greenstein lobes the scattering is made of
+const FORWARD_LOBE: f32 = 0.7;
+const BACKWARD_LOBE: f32 = -0.3;
+
+// Relative to isotropic scattering, `cos_angle` is 1 when the light keeps its direction
+fn henyey_greenstein(cos_angle: f32, g: f32) -> f32 {
+    let denominator = 1.0 + g * g - 2.0 * g * cos_angle;
+    return (1.0 - g * g) / (denominator * sqrt(denominator));
+}
+
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {    
-    let object_color: vec4<f32> = textureSample(texture, texture_sampler, in.tex_coords);
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texture_color: vec4<f32> = textureSample(textures, texture_sampler, in.tex_coords, in.texture_index);
+    // Denser rings block more light, the texture holds the opacity at the reference density
+    let alpha = 1.0 - pow(1.0 - min(texture_color.a, 0.999), in.particle_density);
+    let object_color = vec4<f32>(texture_color.rgb, alpha);
 
-    let sun_dir = normalize(light.position - in.world_position + in.local_position);
-    let ring_dir = normalize(in.local_position);
+    let sun_dir = normalize(light.position - in.center);
+    let ring_dir = normalize(in.world_position - in.center);
     let shadow = clamp(dot(sun_dir, ring_dir) + 0.75, 0.07, 1.0);
 
-    let result = shadow * object_color.xyz;
+    // Back-lit rings shine through forward scattering, front-lit ones by scattering back
+    let light_dir = normalize(light.position - in.world_position);
+    let camera_position = camera.inv_view[3].xyz;
+    let view_dir = normalize(camera_position - in.world_position);
+    let cos_angle = dot(-light_dir, view_dir);
+    let phase = mix(
+        henyey_greenstein(cos_angle, BACKWARD_LOBE),
+        henyey_greenstein(cos_angle, FORWARD_LOBE),
+        in.forward_scattering,
+    );
+
+    // Seen from the unlit side, only the light passing through the gaps between the
+    // particles reaches the camera, so dense parts of the rings stay dark
+    let normal = normalize(in.world_normal);
+    let lit_side = dot(normal, light_dir) * dot(normal, view_dir) > 0.0;
+    let transmission = select(1.0 - object_color.a, 1.0, lit_side);
+
+    let result = shadow * phase * transmission * light.color * object_color.xyz;
 
     return vec4<f32>(result, object_color.a);
 }
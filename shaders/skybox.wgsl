@@ -13,6 +13,15 @@ var skybox_map: texture_cube<f32>;
 @binding(1)
 var skybox_sampler: sampler;
 
+struct SkyboxUniform {
+    // From the scene frame to the frame of the image
+    rotation: mat4x4<f32>,
+    brightness: f32,
+}
+@group(1)
+@binding(2)
+var<uniform> skybox: SkyboxUniform;
+
 struct VertexOutput {
     @builtin(position) frag_position: vec4<f32>,
     @location(0) clip_position: vec4<f32>,
@@ -27,8 +36,10 @@ fn vs_main(
         (id >> 1u) & 1u,
     ));
     var out: VertexOutput;
+    // The projection is infinite, so the far plane can't be unprojected. The ray is computed
+    // from the near plane instead, while the skybox itself is drawn at the far plane (depth 0)
     out.clip_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
-    out.frag_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
+    out.frag_position = vec4(uv * 4.0 - 1.0, 0.0, 1.0);
     return out;
 }
 
@@ -38,6 +49,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let view_ray_direction = view_pos_homogeneous.xyz / view_pos_homogeneous.w;
     var ray_direction = normalize((camera.inv_view * vec4(view_ray_direction, 0.0)).xyz);
 
-    let sample = textureSample(skybox_map, skybox_sampler, ray_direction);
-    return sample;
+    let image_direction = (skybox.rotation * vec4(ray_direction, 0.0)).xyz;
+    let sample = textureSample(skybox_map, skybox_sampler, image_direction);
+    return vec4(sample.rgb * skybox.brightness, sample.a);
 }
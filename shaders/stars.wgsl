@@ -0,0 +1,65 @@
+struct CameraUniform {
+    view_projection_matrix: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct StarfieldUniform {
+    viewport_size: vec2<f32>,
+    glyph_scale: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> starfield: StarfieldUniform;
+
+struct InstanceInput {
+    @location(0) direction: vec3<f32>,
+    @location(1) size: f32,
+    @location(2) color: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Position on the sprite, from -1.0 to 1.0
+    @location(0) offset: vec2<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vi];
+
+    // A direction (w = 0.0) is projected onto the infinite far plane, at depth 0.0 like the
+    // skybox, so the stars stay behind everything else
+    var clip_position = camera.view_projection_matrix * vec4<f32>(instance.direction, 0.0);
+    // Constant size in pixels, whatever the field of view
+    let pixels = corner * instance.size;
+    clip_position.x += pixels.x * 2.0 / starfield.viewport_size.x * clip_position.w;
+    clip_position.y += pixels.y * 2.0 / starfield.viewport_size.y * clip_position.w;
+
+    var out: VertexOutput;
+    out.clip_position = clip_position;
+    out.offset = corner;
+    out.color = instance.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let falloff = exp(-dot(in.offset, in.offset) * 5.0);
+    return vec4<f32>(in.color * falloff, 0.0);
+}
@@ -16,9 +16,22 @@ struct LightUniform {
 @group(2) @binding(0)
 var<uniform> light: LightUniform;
 
+struct SunUniform {
+    center: vec3<f32>,
+    radius: f32,
+    time: f32,
+    intensity: f32,
+    corona_scale: f32,
+    corona_intensity: f32,
+}
+
+@group(3) @binding(0)
+var<uniform> sun: SunUniform;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
 }
 
 struct InstanceInput {
@@ -35,6 +48,9 @@ struct InstanceInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) local_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_position: vec3<f32>,
 };
 
 @vertex
@@ -44,6 +60,7 @@ fn vs_main(
 ) -> VertexOutput {
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
+    out.local_position = model.position;
 
     let model_matrix = mat4x4<f32>(
         instance.model_matrix_0,
@@ -51,19 +68,74 @@ fn vs_main(
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    out.world_normal = normal_matrix * model.normal;
 
-    out.clip_position = camera.view_projection_matrix * model_matrix * vec4<f32>(model.position, 1.0);
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_projection_matrix * world_position;
     return out;
 }
 
 // Fragment shader
 
+// Per channel limb darkening coefficients, blue darkens faster which reddens the limb
+const LIMB_DARKENING: vec3<f32> = vec3<f32>(0.5, 0.62, 0.75);
+
 @group(0) @binding(0)
 var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
 
+fn hash(p: vec3<f32>) -> f32 {
+    let q = fract(p * 0.3183099 + vec3<f32>(0.71, 0.113, 0.419));
+    let r = q * 17.0;
+    return fract(r.x * r.y * r.z * (r.x + r.y + r.z));
+}
+
+fn value_noise(p: vec3<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(
+            mix(hash(i + vec3<f32>(0.0, 0.0, 0.0)), hash(i + vec3<f32>(1.0, 0.0, 0.0)), u.x),
+            mix(hash(i + vec3<f32>(0.0, 1.0, 0.0)), hash(i + vec3<f32>(1.0, 1.0, 0.0)), u.x),
+            u.y,
+        ),
+        mix(
+            mix(hash(i + vec3<f32>(0.0, 0.0, 1.0)), hash(i + vec3<f32>(1.0, 0.0, 1.0)), u.x),
+            mix(hash(i + vec3<f32>(0.0, 1.0, 1.0)), hash(i + vec3<f32>(1.0, 1.0, 1.0)), u.x),
+            u.y,
+        ),
+        u.z,
+    );
+}
+
+// Convection cells boiling over time, the bright cells are separated by darker lanes
+fn granulation(local_direction: vec3<f32>, time: f32) -> f32 {
+    let p = normalize(local_direction);
+    let cells = value_noise(p * 90.0 + vec3<f32>(0.0, time * 0.35, 0.0));
+    let detail = value_noise(p * 210.0 - vec3<f32>(time * 0.6, 0.0, time * 0.4));
+    let large_scale = value_noise(p * 12.0 + vec3<f32>(time * 0.05));
+    return cells * 0.55 + detail * 0.25 + large_scale * 0.2;
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let texture_color = textureSample(t_diffuse, s_diffuse, in.tex_coords).xyz;
+
+    let normal = normalize(in.world_normal);
+    let camera_position = camera.inv_view[3].xyz;
+    let view_dir = normalize(camera_position - in.world_position);
+    let mu = max(dot(normal, view_dir), 0.0);
+    let limb = vec3<f32>(1.0) - LIMB_DARKENING * (1.0 - sqrt(mu));
+
+    let surface = texture_color * mix(0.7, 1.3, granulation(in.local_position, sun.time));
+
+    return vec4<f32>(surface * limb * sun.intensity, 1.0);
 }
@@ -0,0 +1,214 @@
+// Vertex shader
+
+struct CameraUniform {
+    view_projection_matrix: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct LightUniform {
+    position: vec3<f32>,
+    color: vec3<f32>,
+    // From the scene frame to the frame of the environment maps
+    environment_rotation: mat4x4<f32>,
+    environment_intensity: f32,
+}
+
+@group(2) @binding(0)
+var<uniform> light: LightUniform;
+@group(2) @binding(1)
+var irradiance_map: texture_cube<f32>;
+@group(2) @binding(2)
+var prefiltered_map: texture_cube<f32>;
+@group(2) @binding(3)
+var environment_sampler: sampler;
+
+// Must match `TerrainUniform`
+struct TerrainUniform {
+    // Rotation of the body and its position relative to the camera
+    model_matrix: mat4x4<f32>,
+    radius: f32,
+    // Elevation of the darkest texel of the height map, relative to the radius
+    lowest: f32,
+    // Difference between the brightest and the darkest texel of the height map
+    height_range: f32,
+    texture_index: u32,
+    normal_map_index: u32,
+    heightmap_index: u32,
+}
+
+@group(3) @binding(0)
+var<uniform> terrain: TerrainUniform;
+@group(3) @binding(1)
+var heightmaps: texture_2d_array<f32>;
+
+struct VertexInput {
+    // Position in the chunk, from 0.0 to 1.0
+    @location(0) grid_position: vec2<f32>,
+    // 1.0 for the skirt hanging below the edges of the chunk, which hides the cracks
+    // between chunks of different sizes
+    @location(1) skirt: f32,
+}
+
+struct ChunkInput {
+    // Offset on the cube face and size of the chunk, the face goes from 0.0 to 1.0
+    @location(5) offset_size: vec3<f32>,
+    @location(6) face: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    // Direction from the center of the body in its own frame, textures are looked up with it
+    @location(2) local_direction: vec3<f32>,
+}
+
+// Must match `InstanceRaw::NO_TEXTURE`
+const NO_TEXTURE: u32 = 0xffffffffu;
+
+const PI: f32 = 3.1415926535897932384626433832795;
+const TAU: f32 = 6.283185307179586;
+
+// Must match `Terrain::GRID_SIZE`
+const GRID_SIZE: f32 = 32.0;
+// Depth of the skirts, relative to the size of the chunk
+const SKIRT_DEPTH: f32 = 0.05;
+
+// Must match `face_point` in `terrain.rs`
+fn face_point(face: u32, st: vec2<f32>) -> vec3<f32> {
+    let a = st.x * 2.0 - 1.0;
+    let b = st.y * 2.0 - 1.0;
+    switch face {
+        case 0u: { return vec3<f32>(1.0, b, -a); }
+        case 1u: { return vec3<f32>(-1.0, b, a); }
+        case 2u: { return vec3<f32>(a, 1.0, -b); }
+        case 3u: { return vec3<f32>(a, -1.0, b); }
+        case 4u: { return vec3<f32>(a, b, 1.0); }
+        default: { return vec3<f32>(-a, b, -1.0); }
+    }
+}
+
+// Must match `cube_to_sphere` in `terrain.rs`
+fn cube_to_sphere(p: vec3<f32>) -> vec3<f32> {
+    let p2 = p * p;
+    return p * sqrt(1.0 - p2.yzx * 0.5 - p2.zxy * 0.5 + p2.yzx * p2.zxy / 3.0);
+}
+
+// Same mapping as the texture coordinates of `Sphere`
+fn equirectangular(direction: vec3<f32>) -> vec2<f32> {
+    let u = fract(atan2(direction.z, direction.x) / TAU);
+    let v = acos(clamp(direction.y, -1.0, 1.0)) / PI;
+    return vec2<f32>(u, v);
+}
+
+// Height maps are not filterable, so they are interpolated by hand
+fn height(direction: vec3<f32>) -> f32 {
+    if terrain.heightmap_index == NO_TEXTURE {
+        return 0.0;
+    }
+    let size = vec2<i32>(textureDimensions(heightmaps));
+    let texel = equirectangular(direction) * vec2<f32>(size) - 0.5;
+    let base = floor(texel);
+    let f = texel - base;
+    let x0 = (i32(base.x) + size.x) % size.x;
+    let x1 = (x0 + 1) % size.x;
+    let y0 = clamp(i32(base.y), 0, size.y - 1);
+    let y1 = clamp(i32(base.y) + 1, 0, size.y - 1);
+    let layer = terrain.heightmap_index;
+    let h00 = textureLoad(heightmaps, vec2<i32>(x0, y0), layer, 0).r;
+    let h10 = textureLoad(heightmaps, vec2<i32>(x1, y0), layer, 0).r;
+    let h01 = textureLoad(heightmaps, vec2<i32>(x0, y1), layer, 0).r;
+    let h11 = textureLoad(heightmaps, vec2<i32>(x1, y1), layer, 0).r;
+    let value = mix(mix(h00, h10, f.x), mix(h01, h11, f.x), f.y);
+    return terrain.lowest + value * terrain.height_range;
+}
+
+// Point of the surface in the frame of the body
+fn surface(face: u32, st: vec2<f32>) -> vec3<f32> {
+    let direction = cube_to_sphere(face_point(face, st));
+    return direction * (terrain.radius + height(direction));
+}
+
+@vertex
+fn vs_main(
+    vertex: VertexInput,
+    chunk: ChunkInput,
+) -> VertexOutput {
+    let offset = chunk.offset_size.xy;
+    let size = chunk.offset_size.z;
+    let st = offset + vertex.grid_position * size;
+    let direction = cube_to_sphere(face_point(chunk.face, st));
+
+    // Central differences of the neighbouring grid points
+    var normal = direction;
+    if terrain.heightmap_index != NO_TEXTURE {
+        let step = size / GRID_SIZE;
+        let du = surface(chunk.face, st + vec2<f32>(step, 0.0)) - surface(chunk.face, st - vec2<f32>(step, 0.0));
+        let dv = surface(chunk.face, st + vec2<f32>(0.0, step)) - surface(chunk.face, st - vec2<f32>(0.0, step));
+        normal = normalize(cross(du, dv));
+    }
+
+    let skirt = vertex.skirt * size * SKIRT_DEPTH * terrain.radius;
+    let local_position = direction * (terrain.radius + height(direction) - skirt);
+    let world_position = terrain.model_matrix * vec4<f32>(local_position, 1.0);
+
+    var out: VertexOutput;
+    out.world_position = world_position.xyz;
+    out.world_normal = (terrain.model_matrix * vec4<f32>(normal, 0.0)).xyz;
+    out.local_direction = direction;
+    out.clip_position = camera.view_projection_matrix * world_position;
+    return out;
+}
+
+// Fragment shader
+
+@group(0) @binding(0)
+var textures: texture_cube_array<f32>;
+@group(0) @binding(1)
+var textures_sampler: sampler;
+@group(0) @binding(2)
+var surface_maps: texture_cube_array<f32>;
+
+fn to_environment(direction: vec3<f32>) -> vec3<f32> {
+    return (light.environment_rotation * vec4<f32>(direction, 0.0)).xyz;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let local_direction = normalize(in.local_direction);
+
+    let object_color = textureSample(textures, textures_sampler, local_direction, terrain.texture_index);
+
+    let light_dir = normalize(light.position - in.world_position);
+    let sphere_normal = normalize((terrain.model_matrix * vec4<f32>(local_direction, 0.0)).xyz);
+    let terrain_normal = normalize(in.world_normal);
+
+    // Normal maps are in the tangent space of `Sphere`, the tangent follows the longitude
+    let has_normal_map = terrain.normal_map_index != NO_TEXTURE;
+    let normal_map_index = select(0u, terrain.normal_map_index, has_normal_map);
+    let normal_sample = textureSample(surface_maps, textures_sampler, local_direction, normal_map_index).xyz * 2.0 - 1.0;
+    // It degenerates at the poles, where any direction along the surface will do
+    let at_pole = abs(local_direction.y) > 0.9999;
+    let local_tangent = select(vec3<f32>(-local_direction.z, 0.0, local_direction.x), vec3<f32>(1.0, 0.0, 0.0), at_pole);
+    let world_tangent = (terrain.model_matrix * vec4<f32>(local_tangent, 0.0)).xyz;
+    let tangent = normalize(world_tangent - terrain_normal * dot(terrain_normal, world_tangent));
+    let bitangent = cross(terrain_normal, tangent);
+    let mapped_normal = normalize(tangent * normal_sample.x + bitangent * normal_sample.y + terrain_normal * normal_sample.z);
+    let normal = select(terrain_normal, mapped_normal, has_normal_map);
+
+    // Light of the surroundings, from the skybox
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, to_environment(normal), 0.0).rgb;
+    let ambient_color = irradiance * light.environment_intensity;
+
+    // Slopes must not be lit past the terminator of the body
+    let self_shadow = smoothstep(-0.05, 0.05, dot(sphere_normal, light_dir));
+    let diffuse_strength = max(dot(normal, light_dir), 0.0) * self_shadow;
+    let diffuse_color = light.color * diffuse_strength;
+
+    let result = (ambient_color + diffuse_color) * object_color.xyz;
+    return vec4<f32>(result, object_color.a);
+}
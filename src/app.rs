@@ -14,14 +14,25 @@ use winit::{
 };
 
 use crate::{
-    camera, hdr,
-    planets::{self, DrawPlanets},
-    skybox::{self, DrawSkybox},
-    sphere,
-    sun::{self, DrawSun},
-    texture,
+    belts, camera, clock, culling, hdr, lens_flare,
+    material::{self, Renderable},
+    minor_bodies, planets, render_graph, ring, scale, skybox, sphere, starfield, sun, texture,
 };
 
+const WINDOW_TITLE: &str = "Solar System";
+
+/// Passes of the render graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pass {
+    /// Compute shaders animating and culling the bodies
+    Simulation,
+    Scene,
+    /// Needs the depth buffer, so it runs in a separate pass before tonemapping
+    LensFlare,
+    /// HDR -> SDR
+    Tonemapping,
+}
+
 struct State {
     app_start_time: Instant,
     last_render_time: Instant,
@@ -31,14 +42,24 @@ struct State {
     config: wgpu::SurfaceConfiguration,
     is_surface_configured: bool,
     camera_container: camera::CameraContainer,
-    depth_texture: texture::Texture,
-    sphere: sphere::Sphere,
+    render_graph: render_graph::RenderGraph<Pass>,
     sun: sun::Sun,
     planets: planets::Planets,
+    belts: belts::Belts,
+    minor_bodies: minor_bodies::MinorBodies,
+    rings: ring::RingSystem,
+    system_scale: scale::SystemScale,
+    clock: clock::SimulationClock,
     hdr: hdr::HdrPipeline,
+    lens_flare: lens_flare::LensFlare,
+    /// Pipelines of the scene, shared by the bodies created with it
+    materials: material::PipelineCache,
     skybox: skybox::Skybox,
+    starfield: starfield::Starfield,
     max_size: PhysicalSize<u32>,
     window: Arc<Window>,
+    /// Textures still decoded in the background, shown in the title of the window
+    loading_textures: usize,
 }
 
 impl State {
@@ -94,19 +115,63 @@ impl State {
         };
 
         let hdr = hdr::HdrPipeline::new(&device, &config);
+        let lens_flare = lens_flare::LensFlare::new(&device, hdr.format());
+        let render_graph = Self::create_render_graph(&device, &config, &hdr, &lens_flare)?;
 
         let camera_container = camera::CameraContainer::new(config.width, config.height, &device);
 
-        let skybox = skybox::Skybox::new(&device, &queue, &hdr, &camera_container)?;
+        let mut materials =
+            material::PipelineCache::new(hdr::HdrPipeline::FORMAT, texture::Texture::DEPTH_FORMAT);
+        let sphere = sphere::Sphere::new(&device);
 
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+        let skybox = skybox::Skybox::new(&device, &queue, &mut materials, &camera_container)?;
+        let starfield = starfield::Starfield::new(
+            &device,
+            &mut materials,
+            &camera_container,
+            config.width,
+            config.height,
+        )?;
+
+        let sun = sun::Sun::new(
+            &device,
+            &queue,
+            &mut materials,
+            &camera_container,
+            skybox.environment(),
+            &sphere,
+        );
 
-        let sun = sun::Sun::new(&device, &queue, &hdr, &camera_container);
+        let planets = planets::Planets::new(
+            &device,
+            &queue,
+            &mut materials,
+            &camera_container,
+            &sun,
+            &sphere,
+        );
 
-        let planets = planets::Planets::new(&device, &queue, &hdr, &camera_container, &sun);
+        let belts = belts::Belts::new(&device, &mut materials, &camera_container, &sun);
 
-        let sphere = sphere::Sphere::new(&device);
+        let minor_bodies = minor_bodies::MinorBodies::new(
+            &device,
+            &mut materials,
+            &camera_container,
+            &sun,
+            &sphere,
+        );
+
+        let rings = ring::RingSystem::new(
+            &device,
+            &queue,
+            &mut materials,
+            &camera_container,
+            &sun,
+            planets
+                .rings()
+                .chain(minor_bodies.rings())
+                .map(|(_, ring)| ring),
+        )?;
 
         let state = State {
             app_start_time: Instant::now(),
@@ -117,29 +182,98 @@ impl State {
             config,
             is_surface_configured: false,
             camera_container,
-            depth_texture,
-            sphere,
+            render_graph,
             sun,
             planets,
+            belts,
+            minor_bodies,
+            rings,
+            system_scale: scale::SystemScale::new(scale::ScaleMode::Artistic),
+            clock: clock::SimulationClock::from_env(),
             hdr,
+            lens_flare,
+            materials,
             skybox,
+            starfield,
             max_size,
             window,
+            loading_textures: 0,
         };
+        log::info!(
+            "{} render pipelines in the scene",
+            state.materials.pipeline_count()
+        );
         state.update_window();
         Ok(state)
     }
 
+    fn create_render_graph(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        hdr: &hdr::HdrPipeline,
+        lens_flare: &lens_flare::LensFlare,
+    ) -> Result<render_graph::RenderGraph<Pass>> {
+        use render_graph::Resource;
+
+        let mut graph = render_graph::RenderGraph::new(config.width, config.height);
+        graph.add_texture(
+            device,
+            Resource::HdrColor,
+            render_graph::TextureDescriptor {
+                format: hdr::HdrPipeline::FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                downscale: 1,
+            },
+        );
+        graph.add_texture(
+            device,
+            Resource::Depth,
+            render_graph::TextureDescriptor {
+                format: texture::Texture::DEPTH_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                downscale: 1,
+            },
+        );
+
+        graph.add_pass(Pass::Simulation, &[], &[Resource::Bodies])?;
+        graph.add_pass(
+            Pass::Scene,
+            &[Resource::Bodies],
+            &[Resource::HdrColor, Resource::Depth],
+        )?;
+        graph.add_pass(
+            Pass::LensFlare,
+            &[Resource::HdrColor, Resource::Depth],
+            &[Resource::HdrColor],
+        )?;
+        graph.add_pass(
+            Pass::Tonemapping,
+            &[Resource::HdrColor],
+            &[Resource::Surface],
+        )?;
+
+        let create = lens_flare.bind_group_factory();
+        graph.set_bind_group(device, Pass::LensFlare, move |device, textures| {
+            create(device, textures.get(Resource::Depth))
+        });
+        let create = hdr.bind_group_factory();
+        graph.set_bind_group(device, Pass::Tonemapping, move |device, textures| {
+            create(device, textures.get(Resource::HdrColor))
+        });
+        Ok(graph)
+    }
+
     fn resize(&mut self, width: u32, height: u32) {
         if width > 0 && height > 0 {
             self.config.width = width;
             self.config.height = height;
             self.surface.configure(&self.device, &self.config);
             self.is_surface_configured = true;
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.render_graph.resize(&self.device, width, height);
             self.camera_container.projection.resize(width, height);
-            self.hdr.resize(&self.device, width, height);
+            self.starfield.resize(&self.queue, width, height);
         }
     }
 
@@ -156,6 +290,98 @@ impl State {
             self.camera_container.camera_controller.swap_cursor_locked();
             self.update_window();
         }
+        if code == KeyCode::KeyM && element_state.is_pressed() {
+            self.system_scale.switch_to_next_mode();
+            log::info!("Scale mode: {:?}", self.system_scale.mode());
+        }
+        if code == KeyCode::KeyE && element_state.is_pressed() {
+            self.planets.switch_ephemeris(self.clock.julian_date());
+            log::info!(
+                "Ephemeris: {}, date: {}",
+                self.planets.ephemeris_name(),
+                self.clock.date_string()
+            );
+        }
+        if code == KeyCode::KeyN && element_state.is_pressed() {
+            self.planets.next_nbody_scenario(self.clock.julian_date());
+            log::info!("Ephemeris: {}", self.planets.ephemeris_name());
+        }
+        if code == KeyCode::KeyI && element_state.is_pressed() {
+            self.planets.next_nbody_integrator(self.clock.julian_date());
+            log::info!("Ephemeris: {}", self.planets.ephemeris_name());
+        }
+        if code == KeyCode::BracketRight && element_state.is_pressed() {
+            self.clock.faster();
+            log::info!("Days per second: {}", self.clock.days_per_second());
+        }
+        if code == KeyCode::BracketLeft && element_state.is_pressed() {
+            self.clock.slower();
+            log::info!("Days per second: {}", self.clock.days_per_second());
+        }
+        if code == KeyCode::KeyP && element_state.is_pressed() {
+            self.clock.swap_paused();
+            log::info!(
+                "Clock paused: {}, date: {}",
+                self.clock.paused(),
+                self.clock.date_string()
+            );
+        }
+        if code == KeyCode::KeyF && element_state.is_pressed() {
+            let enabled = !self.render_graph.enabled(Pass::LensFlare);
+            self.render_graph.set_enabled(Pass::LensFlare, enabled);
+            log::info!("Lens flare enabled: {enabled}");
+        }
+        if code == KeyCode::KeyK && element_state.is_pressed() {
+            self.skybox.next_source();
+        }
+        if code == KeyCode::KeyJ && element_state.is_pressed() {
+            self.skybox.set_frame(self.skybox.frame().next());
+            log::info!("Skybox frame: {:?}", self.skybox.frame());
+        }
+        if code == KeyCode::Comma && element_state.is_pressed() {
+            self.skybox.set_rotation(self.skybox.rotation() - 15.0);
+            log::info!("Skybox rotation: {}°", self.skybox.rotation());
+        }
+        if code == KeyCode::Period && element_state.is_pressed() {
+            self.skybox.set_rotation(self.skybox.rotation() + 15.0);
+            log::info!("Skybox rotation: {}°", self.skybox.rotation());
+        }
+        if code == KeyCode::Minus && element_state.is_pressed() {
+            self.skybox.set_brightness(self.skybox.brightness() * 0.8);
+            log::info!("Skybox brightness: {:.2}", self.skybox.brightness());
+        }
+        if code == KeyCode::Equal && element_state.is_pressed() {
+            self.skybox.set_brightness(self.skybox.brightness() * 1.25);
+            log::info!("Skybox brightness: {:.2}", self.skybox.brightness());
+        }
+        if code == KeyCode::Semicolon && element_state.is_pressed() {
+            let light = self.sun.light_mut();
+            light.set_environment_intensity(light.environment_intensity() - 0.25);
+            log::info!("Environment light: {:.2}", light.environment_intensity());
+        }
+        if code == KeyCode::Quote && element_state.is_pressed() {
+            let light = self.sun.light_mut();
+            light.set_environment_intensity(light.environment_intensity() + 0.25);
+            log::info!("Environment light: {:.2}", light.environment_intensity());
+        }
+        if code == KeyCode::KeyV && element_state.is_pressed() {
+            self.starfield.swap_enabled();
+            log::info!("Starfield enabled: {}", self.starfield.enabled());
+        }
+        if code == KeyCode::KeyO && element_state.is_pressed() {
+            log::info!(
+                "Planets: {}, minor bodies: {}",
+                self.planets.culling().stats(),
+                self.minor_bodies.culling().stats()
+            );
+        }
+        if code == KeyCode::KeyC && element_state.is_pressed() {
+            self.starfield.swap_constellations_enabled();
+            log::info!(
+                "Constellations enabled: {}",
+                self.starfield.constellations_enabled()
+            );
+        }
         self.camera_container
             .camera_controller
             .process_keyboard(code, element_state);
@@ -178,35 +404,87 @@ impl State {
         }
     }
 
+    /// Swaps in the textures decoded in the background and shows how many are left
+    fn load_textures(&mut self) {
+        self.planets.load_textures(&self.device, &self.queue);
+        self.sun.load_texture(&self.device, &self.queue);
+        self.rings.load_textures(&self.queue);
+        if self.skybox.load_image(&self.device, &self.queue) {
+            self.sun
+                .light_mut()
+                .set_environment(&self.device, self.skybox.environment());
+        }
+
+        let loading_textures = self.planets.pending_textures()
+            + self.sun.pending_textures()
+            + self.rings.pending_textures()
+            + self.skybox.pending_images();
+        if loading_textures != self.loading_textures {
+            self.loading_textures = loading_textures;
+            match loading_textures {
+                0 => self.window.set_title(WINDOW_TITLE),
+                n => self
+                    .window
+                    .set_title(&format!("{WINDOW_TITLE} (loading {n} textures)")),
+            }
+        }
+    }
+
     fn update(&mut self, dt: Duration) {
+        self.load_textures();
         self.camera_container.update(dt);
         self.camera_container.sync_camera_buffer(&self.queue);
-        self.planets.update(self.app_start_time.elapsed());
-        self.planets.sync_instance_buffer(&self.queue);
-        self.sun.update(self.app_start_time.elapsed());
-        self.sun.sync_instance_buffer(&self.queue);
+        self.system_scale.update(dt);
+        self.clock.update(dt);
+        self.planets.update(&self.clock, &self.system_scale);
+        let origin = self.camera_container.camera.position();
+        let frustum = culling::Frustum::new(
+            &self.camera_container.camera,
+            &self.camera_container.projection,
+        );
+        self.planets
+            .sync_instance_buffer(&self.queue, origin, &frustum);
+        self.camera_container
+            .camera_controller
+            .set_speed_factor(self.planets.terrain().speed_factor());
+        self.sun.update(&self.clock, &self.system_scale);
+        self.sun
+            .light_mut()
+            .set_environment_transform(self.skybox.image_rotation(), self.skybox.brightness());
+        self.sun.sync_buffers(&self.queue, origin, &frustum);
+        self.belts.update(
+            self.app_start_time.elapsed(),
+            &self.clock,
+            &self.system_scale,
+        );
+        self.belts.sync_buffer(&self.queue, &self.sun, origin);
+        self.minor_bodies.update(
+            self.app_start_time.elapsed(),
+            &self.clock,
+            &self.system_scale,
+            &self.sun,
+        );
+        self.minor_bodies
+            .sync_buffers(&self.queue, origin, &frustum);
+        self.rings.sync_buffers(
+            &self.queue,
+            self.planets.rings().chain(self.minor_bodies.rings()),
+            origin,
+        );
+        self.skybox.sync_buffer(&self.queue);
+        self.lens_flare.update(&self.camera_container, &self.sun);
+        self.lens_flare.sync_buffer(&self.queue);
     }
 
-    fn render(&mut self, dt: Duration) -> Result<(), wgpu::SurfaceError> {
-        self.window.request_redraw();
-
-        // Cannot renderd to not configured surface
-        if !self.is_surface_configured {
-            return Ok(());
-        }
-
-        self.update(dt);
-
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-
+    fn draw_scene(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &render_graph::FrameResources,
+    ) {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: self.hdr.view(),
+                view: resources.view(render_graph::Resource::HdrColor),
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -219,9 +497,9 @@ impl State {
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
+                view: resources.view(render_graph::Resource::Depth),
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
+                    load: wgpu::LoadOp::Clear(texture::Texture::DEPTH_CLEAR_VALUE),
                     store: wgpu::StoreOp::Store,
                 }),
                 stencil_ops: None,
@@ -230,23 +508,37 @@ impl State {
             timestamp_writes: None,
         });
 
-        render_pass.draw_planets(
+        let scene = material::SceneBindGroups {
+            camera: &self.camera_container.camera_bind_group,
+            light: &self.sun.light().bind_group,
+        };
+        // Transparent objects have to be drawn last, as they don't write to the depth buffer
+        let renderables: [&dyn Renderable; 10] = [
             &self.planets,
-            &self.sphere,
-            &self.camera_container.camera_bind_group,
-            &self.sun.light().bind_group,
-        );
-
-        render_pass.draw_sun(
+            &self.minor_bodies,
+            &self.belts,
             &self.sun,
-            &self.sphere,
-            &self.camera_container.camera_bind_group,
-        );
+            &self.skybox,
+            &self.starfield,
+            self.sun.corona(),
+            &self.rings,
+            self.planets.clouds(),
+            self.minor_bodies.comet_tails(),
+        ];
+        for renderable in renderables {
+            renderable.draw(&mut render_pass, &scene);
+        }
+    }
 
-        render_pass.draw_skybox(&self.skybox, &self.camera_container.camera_bind_group);
+    fn render(&mut self, dt: Duration) -> Result<(), wgpu::SurfaceError> {
+        self.window.request_redraw();
+
+        // Cannot renderd to not configured surface
+        if !self.is_surface_configured {
+            return Ok(());
+        }
 
-        // `render_pass` mutably borrows encoder, so it must be dropped before using encoder again
-        drop(render_pass);
+        self.update(dt);
 
         let output = self.surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor {
@@ -254,11 +546,36 @@ impl State {
             ..Default::default()
         });
 
-        // Apply tonemapping (HDR -> SDR)
-        self.hdr.process(&mut encoder, &view);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        self.render_graph
+            .execute(&mut encoder, &view, |pass, encoder, resources| match pass {
+                Pass::Simulation => {
+                    self.belts.animate(encoder);
+                    self.planets.culling().cull(encoder);
+                    self.minor_bodies.culling().cull(encoder);
+                }
+                Pass::Scene => self.draw_scene(encoder, resources),
+                Pass::LensFlare => self.lens_flare.process(
+                    encoder,
+                    resources.bind_group(),
+                    resources.view(render_graph::Resource::HdrColor),
+                ),
+                Pass::Tonemapping => self.hdr.process(
+                    encoder,
+                    resources.bind_group(),
+                    resources.view(render_graph::Resource::Surface),
+                ),
+            });
 
         // submit will accept anything that implements IntoIter
         self.queue.submit(std::iter::once(encoder.finish()));
+        self.planets.culling().read_stats();
+        self.minor_bodies.culling().read_stats();
         output.present();
 
         Ok(())
@@ -285,7 +602,7 @@ impl Default for App {
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window_attributes = Window::default_attributes().with_title("Solar System");
+        let window_attributes = Window::default_attributes().with_title(WINDOW_TITLE);
         let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
         self.state = Some(pollster::block_on(State::new(window)).unwrap());
     }
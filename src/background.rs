@@ -0,0 +1,154 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{Mutex, OnceLock, mpsc},
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Threads shared by all the loaders, so that starting many jobs at once doesn't start as
+/// many threads
+struct WorkerPool {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+impl WorkerPool {
+    const MAX_WORKERS: usize = 4;
+
+    fn get() -> &'static Self {
+        static POOL: OnceLock<WorkerPool> = OnceLock::new();
+        POOL.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel::<Job>();
+            let receiver = std::sync::Arc::new(Mutex::new(receiver));
+            // One core is left to the render thread
+            let workers = std::thread::available_parallelism()
+                .map_or(1, |n| n.get().saturating_sub(1))
+                .clamp(1, Self::MAX_WORKERS);
+            for i in 0..workers {
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("background-{i}"))
+                    .spawn(move || {
+                        loop {
+                            // The lock is released before running the job
+                            let job = receiver.lock().unwrap().recv();
+                            match job {
+                                Ok(job) => job(),
+                                Err(_) => break,
+                            }
+                        }
+                    })
+                    .expect("Failed to start a background worker");
+            }
+            Self {
+                jobs: Mutex::new(sender),
+            }
+        })
+    }
+
+    fn run(&self, job: Job) {
+        // Workers only stop once the sender is dropped, which never happens
+        let _ = self.jobs.lock().unwrap().send(job);
+    }
+}
+
+/// Runs jobs (mostly image decoding) on a small pool of threads. Results are picked up by
+/// the render thread between frames, so the window shows up before the textures are ready.
+pub struct BackgroundLoader<T> {
+    sender: mpsc::Sender<Result<T, String>>,
+    receiver: mpsc::Receiver<Result<T, String>>,
+    pending: usize,
+}
+
+impl<T: Send + 'static> BackgroundLoader<T> {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            pending: 0,
+        }
+    }
+
+    pub fn spawn(&mut self, job: impl FnOnce() -> T + Send + 'static) {
+        let sender = self.sender.clone();
+        self.pending += 1;
+        WorkerPool::get().run(Box::new(move || {
+            // A panicking job still reports back, otherwise it would stay pending forever
+            let result = panic::catch_unwind(AssertUnwindSafe(job)).map_err(|payload| {
+                payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_owned())
+            });
+            // The receiver is gone if the app exits before the job is done
+            let _ = sender.send(result);
+        }));
+    }
+
+    /// Results of the jobs which finished since the last call, never blocks. Jobs which
+    /// panicked are logged and skipped.
+    pub fn poll(&mut self) -> Vec<T> {
+        let results: Vec<Result<T, String>> = self.receiver.try_iter().collect();
+        self.pending -= results.len();
+        results
+            .into_iter()
+            .filter_map(|result| {
+                result
+                    .inspect_err(|e| log::error!("Background job panicked: {e}"))
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Jobs which haven't finished yet
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+}
+
+impl<T: Send + 'static> Default for BackgroundLoader<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn wait_for<T: Send + 'static>(loader: &mut BackgroundLoader<T>) -> Vec<T> {
+        let start = Instant::now();
+        let mut results = Vec::new();
+        while loader.pending() > 0 {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "Jobs never finished"
+            );
+            results.extend(loader.poll());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        results
+    }
+
+    #[test]
+    fn runs_more_jobs_than_workers() {
+        let mut loader = BackgroundLoader::new();
+        for i in 0..32 {
+            loader.spawn(move || i);
+        }
+        let mut results = wait_for(&mut loader);
+        results.sort();
+        assert_eq!(results, (0..32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn panicking_jobs_are_not_pending() {
+        let mut loader = BackgroundLoader::new();
+        loader.spawn(|| panic!("bad asset"));
+        loader.spawn(|| 1);
+        assert_eq!(wait_for(&mut loader), vec![1]);
+    }
+}
@@ -0,0 +1,565 @@
+use std::{mem, time::Duration};
+
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera,
+    clock::SimulationClock,
+    material::{self, SetMaterial},
+    pipeline,
+    planets::Planets,
+    random::Random,
+    scale, sun,
+    vertex::Vertex,
+};
+
+/// Parameters of the procedurally generated population of a belt. Angles are in degrees,
+/// sizes are radii in the artistic layout.
+struct BeltDescription {
+    name: &'static str,
+    count: u32,
+    semi_major_axis_au: [f32; 2],
+    /// Ranges of semi-major axes left empty, e.g. by resonances with Jupiter
+    gaps_au: &'static [[f32; 2]],
+    max_eccentricity: f32,
+    max_inclination: f32,
+    size: [f32; 2],
+    color: [f32; 3],
+}
+
+/// Main asteroid belt and Kuiper belt, made of tens of thousands of small rocky bodies on
+/// their own Keplerian orbits. Orbits are generated once on the CPU from a seed, positions
+/// are computed every frame by a compute shader writing straight into the instance buffer,
+/// which is then drawn with a single instanced draw call.
+pub struct Belts {
+    count: u32,
+    vertex_buffer: wgpu::Buffer,
+    num_vertices: u32,
+    instance_buffer: wgpu::Buffer,
+    appearance_buffer: wgpu::Buffer,
+    uniform: BeltUniform,
+    uniform_buffer: wgpu::Buffer,
+    compute_bind_group: wgpu::BindGroup,
+    compute_pipeline: wgpu::ComputePipeline,
+    material: material::Material,
+}
+
+impl Belts {
+    const DESCRIPTIONS: [BeltDescription; 2] = [
+        BeltDescription {
+            name: "asteroid belt",
+            count: 20_000,
+            semi_major_axis_au: [2.1, 3.3],
+            // Kirkwood gaps at the 3:1, 5:2 and 7:3 resonances
+            gaps_au: &[[2.48, 2.52], [2.80, 2.84], [2.94, 2.97]],
+            max_eccentricity: 0.2,
+            max_inclination: 20.0,
+            size: [0.02, 0.08],
+            color: [0.45, 0.42, 0.4],
+        },
+        BeltDescription {
+            name: "Kuiper belt",
+            count: 30_000,
+            semi_major_axis_au: [39.0, 48.0],
+            gaps_au: &[],
+            max_eccentricity: 0.15,
+            max_inclination: 30.0,
+            size: [0.04, 0.12],
+            color: [0.55, 0.5, 0.55],
+        },
+    ];
+
+    /// Environment variable multiplying the number of bodies in every belt
+    const DENSITY_VARIABLE: &str = "SOLAR_SYSTEM_BELT_DENSITY";
+    /// Environment variable with the seed of the generator, as an integer
+    const SEED_VARIABLE: &str = "SOLAR_SYSTEM_BELT_SEED";
+    const DEFAULT_SEED: u64 = 2006;
+
+    /// Real radius corresponding to a radius of one unit in the artistic layout
+    const KM_PER_ARTISTIC_UNIT: f64 = 2_000.0;
+    /// Larger than for the planets, even the biggest asteroids are a few hundred km across
+    const SIZE_EXAGGERATION: f64 = 2_000.0;
+    /// Gaussian gravitational constant, the mean motion at 1 AU in radians per day
+    const GAUSSIAN_CONSTANT: f32 = 0.017_202_1;
+
+    /// Range covered by the lookup table of distances, in AU
+    const DISTANCE_TABLE_RANGE_AU: [f32; 2] = [1.0, 80.0];
+    const DISTANCE_TABLE_SIZE: usize = 64;
+
+    const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn new(
+        device: &wgpu::Device,
+        materials: &mut material::PipelineCache,
+        camera_container: &camera::CameraContainer,
+        sun: &sun::Sun,
+    ) -> Self {
+        let density = std::env::var(Self::DENSITY_VARIABLE)
+            .ok()
+            .and_then(|density| {
+                density
+                    .parse::<f32>()
+                    .inspect_err(|e| log::warn!("Invalid {}: {e}", Self::DENSITY_VARIABLE))
+                    .ok()
+            })
+            .unwrap_or(1.0)
+            .max(0.0);
+        let seed = std::env::var(Self::SEED_VARIABLE)
+            .ok()
+            .and_then(|seed| {
+                seed.parse::<u64>()
+                    .inspect_err(|e| log::warn!("Invalid {}: {e}", Self::SEED_VARIABLE))
+                    .ok()
+            })
+            .unwrap_or(Self::DEFAULT_SEED);
+
+        let mut random = Random::new(seed);
+        let mut orbits = Vec::new();
+        let mut appearances = Vec::new();
+        for description in &Self::DESCRIPTIONS {
+            let count = (description.count as f32 * density).round() as u32;
+            log::info!("Generating {count} bodies of the {}", description.name);
+            for _ in 0..count {
+                let (orbit, appearance) = Self::generate_body(description, &mut random);
+                orbits.push(orbit);
+                appearances.push(appearance);
+            }
+        }
+        let count = orbits.len() as u32;
+        // Buffers can't be empty, so there is always room for at least one body
+        if orbits.is_empty() {
+            orbits.push(RockOrbit::zeroed());
+            appearances.push(RockAppearance::zeroed());
+        }
+
+        let vertices = Self::generate_icosahedron();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("belt_vertex_buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let orbit_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("belt_orbit_buffer"),
+            contents: bytemuck::cast_slice(&orbits),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let appearance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("belt_appearance_buffer"),
+            contents: bytemuck::cast_slice(&appearances),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("belt_instance_buffer"),
+            size: (orbits.len() * mem::size_of::<RockInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+
+        let uniform = BeltUniform {
+            count,
+            distance_table_range_au: Self::DISTANCE_TABLE_RANGE_AU,
+            ..BeltUniform::zeroed()
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("belt_uniform_buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    storage_entry(1, true),
+                    storage_entry(2, false),
+                ],
+                label: Some("belt_compute_bind_group_layout"),
+            });
+        let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &compute_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: orbit_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("belt_compute_bind_group"),
+        });
+
+        let compute_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Belt Compute Pipeline Layout"),
+            bind_group_layouts: &[&compute_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let compute_shader = wgpu::include_wgsl!("../shaders/belt_compute.wgsl");
+        let compute_pipeline = pipeline::create_compute_pipeline(
+            device,
+            &compute_layout,
+            compute_shader,
+            Some("compute_pipeline_belts"),
+        );
+
+        let material = materials.material(
+            device,
+            material::MaterialDescriptor {
+                name: "belts",
+                shader: wgpu::include_wgsl!("../shaders/belt.wgsl"),
+                bind_groups: &[
+                    (
+                        material::BindGroupSlot::Camera,
+                        &camera_container.camera_bind_group_layout,
+                    ),
+                    (
+                        material::BindGroupSlot::Light,
+                        &sun.light().bind_group_layout,
+                    ),
+                ],
+                vertex_layouts: &[
+                    RockVertex::desc(),
+                    RockInstance::desc(),
+                    RockAppearance::desc(),
+                ],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                blending: material::Blending::Opaque,
+            },
+        );
+
+        Self {
+            count,
+            vertex_buffer,
+            num_vertices: vertices.len() as _,
+            instance_buffer,
+            appearance_buffer,
+            uniform,
+            uniform_buffer,
+            compute_bind_group,
+            compute_pipeline,
+            material,
+        }
+    }
+
+    fn generate_body(
+        description: &BeltDescription,
+        random: &mut Random,
+    ) -> (RockOrbit, RockAppearance) {
+        let [min_axis, max_axis] = description.semi_major_axis_au;
+        let semi_major_axis = loop {
+            let axis = random.range(min_axis, max_axis);
+            if !description
+                .gaps_au
+                .iter()
+                .any(|[from, to]| (*from..*to).contains(&axis))
+            {
+                break axis;
+            }
+        };
+        // Squaring favours nearly circular orbits close to the ecliptic
+        let eccentricity = description.max_eccentricity * random.next_f32().powi(2);
+        let inclination = (description.max_inclination * random.next_f32().powi(2)).to_radians();
+
+        let orbit = RockOrbit {
+            semi_major_axis,
+            eccentricity,
+            inclination,
+            ascending_node: random.range(0.0, std::f32::consts::TAU),
+            argument_of_perihelion: random.range(0.0, std::f32::consts::TAU),
+            mean_anomaly: random.range(0.0, std::f32::consts::TAU),
+            // Kepler's third law, the mass of the body is negligible
+            mean_motion: Self::GAUSSIAN_CONSTANT / semi_major_axis.powf(1.5),
+            // Small bodies are much more common than large ones
+            size: description.size[0]
+                + (description.size[1] - description.size[0]) * random.next_f32().powi(3),
+            spin_axis: random.unit_vector().into(),
+            spin_rate: random.range(0.2, 1.5),
+        };
+
+        let shade = random.range(0.7, 1.2);
+        let appearance = RockAppearance {
+            shape: [
+                random.range(0.6, 1.0),
+                random.range(0.6, 1.0),
+                random.range(0.6, 1.0),
+            ],
+            color: description.color.map(|channel| channel * shade),
+        };
+
+        (orbit, appearance)
+    }
+
+    /// Flat shaded, so every face has its own vertices
+    fn generate_icosahedron() -> Vec<RockVertex> {
+        let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+        let corners = [
+            [-1.0, t, 0.0],
+            [1.0, t, 0.0],
+            [-1.0, -t, 0.0],
+            [1.0, -t, 0.0],
+            [0.0, -1.0, t],
+            [0.0, 1.0, t],
+            [0.0, -1.0, -t],
+            [0.0, 1.0, -t],
+            [t, 0.0, -1.0],
+            [t, 0.0, 1.0],
+            [-t, 0.0, -1.0],
+            [-t, 0.0, 1.0],
+        ]
+        .map(|corner| glam::Vec3::from(corner).normalize());
+        let faces: [[usize; 3]; 20] = [
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        faces
+            .iter()
+            .flat_map(|face| {
+                let [a, b, c] = face.map(|i| corners[i]);
+                let normal = (b - a).cross(c - a).normalize();
+                [a, b, c].map(|position| RockVertex {
+                    position: position.into(),
+                    normal: normal.into(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn update(
+        &mut self,
+        total_time: Duration,
+        clock: &SimulationClock,
+        system_scale: &scale::SystemScale,
+    ) {
+        self.uniform.days = (clock.julian_date() - SimulationClock::J2000) as f32;
+        self.uniform.time = total_time.as_secs_f32();
+        self.uniform.size_factor = system_scale.radius(&scale::BodyDimensions {
+            artistic_distance: 0.0,
+            artistic_radius: 1.0,
+            distance_au: 0.0,
+            radius_km: Self::KM_PER_ARTISTIC_UNIT,
+            exaggeration: Self::SIZE_EXAGGERATION,
+        });
+
+        // Orbits are elliptical, so the distance is mapped per body on the GPU, which
+        // interpolates this table of scene units per AU, sampled logarithmically
+        let [min_au, max_au] = Self::DISTANCE_TABLE_RANGE_AU.map(|au| au as f64);
+        for i in 0..Self::DISTANCE_TABLE_SIZE {
+            let t = i as f64 / (Self::DISTANCE_TABLE_SIZE - 1) as f64;
+            let distance_au = min_au * (max_au / min_au).powf(t);
+            let dimensions = scale::BodyDimensions {
+                artistic_distance: Planets::artistic_distance(distance_au),
+                artistic_radius: 0.0,
+                distance_au,
+                radius_km: 0.0,
+                exaggeration: 0.0,
+            };
+            self.uniform.units_per_au[i / 4][i % 4] =
+                (system_scale.distance(&dimensions, distance_au) / distance_au) as f32;
+        }
+    }
+
+    /// `origin` is the camera position, all positions are sent relative to it
+    pub fn sync_buffer(&mut self, queue: &wgpu::Queue, sun: &sun::Sun, origin: glam::DVec3) {
+        self.uniform.sun_position = (sun.position() - origin).as_vec3().into();
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    /// Moves every body along its orbit, it has to run before the render pass
+    pub fn animate(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Belt Compute Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.count.div_ceil(Self::WORKGROUP_SIZE), 1, 1);
+    }
+}
+
+/// Orbital elements in the ecliptic frame of J2000, lengths in AU, angles in radians
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct RockOrbit {
+    semi_major_axis: f32,
+    eccentricity: f32,
+    inclination: f32,
+    ascending_node: f32,
+    argument_of_perihelion: f32,
+    /// At J2000
+    mean_anomaly: f32,
+    /// In radians per day
+    mean_motion: f32,
+    /// Radius in the artistic layout
+    size: f32,
+    spin_axis: [f32; 3],
+    /// In radians per second of real time, like the rotation of the planets
+    spin_rate: f32,
+}
+
+/// Written by the compute shader every frame
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct RockInstance {
+    /// Relative to the camera
+    position: [f32; 3],
+    /// Radius in scene units
+    size: f32,
+    rotation: [f32; 4],
+}
+
+impl Vertex for RockInstance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<RockInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Per-body data which doesn't change over time
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct RockAppearance {
+    /// Stretch along each axis of the mesh, so that no two bodies look the same
+    shape: [f32; 3],
+    color: [f32; 3],
+}
+
+impl Vertex for RockAppearance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<RockAppearance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct RockVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+}
+
+impl Vertex for RockVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<RockVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BeltUniform {
+    /// Relative to the camera
+    sun_position: [f32; 3],
+    /// Days since J2000
+    days: f32,
+    /// Seconds since the start
+    time: f32,
+    /// Radius in scene units of a body with a radius of one unit in the artistic layout
+    size_factor: f32,
+    count: u32,
+    _padding: f32,
+    distance_table_range_au: [f32; 2],
+    _padding2: [f32; 2],
+    /// Scene units per AU, at distances spaced logarithmically over
+    /// `distance_table_range_au`
+    units_per_au: [[f32; 4]; Belts::DISTANCE_TABLE_SIZE / 4],
+}
+
+impl material::Renderable for Belts {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &material::SceneBindGroups) {
+        pass.set_material(&self.material, scene, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        pass.set_vertex_buffer(2, self.appearance_buffer.slice(..));
+        pass.draw(0..self.num_vertices, 0..self.count);
+    }
+}
@@ -3,17 +3,19 @@ use std::{f32::consts::FRAC_PI_2, time::Duration};
 use wgpu::util::DeviceExt;
 use winit::{event::ElementState, keyboard::KeyCode};
 
+use crate::material;
+
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
 #[derive(Debug)]
 pub struct Camera {
-    position: glam::Vec3,
+    position: glam::DVec3,
     yaw: f32,
     pitch: f32,
 }
 
 impl Camera {
-    pub fn new<P: Into<glam::Vec3>>(position: P, yaw: f32, pitch: f32) -> Self {
+    pub fn new<P: Into<glam::DVec3>>(position: P, yaw: f32, pitch: f32) -> Self {
         Self {
             position: position.into(),
             yaw,
@@ -21,12 +23,18 @@ impl Camera {
         }
     }
 
+    pub fn position(&self) -> glam::DVec3 {
+        self.position
+    }
+
+    /// Everything is rendered relative to the camera (see [`crate::instance::InstanceRaw::new`]),
+    /// so the view matrix only rotates the scene and the camera sits at the origin.
     pub fn view_matrix(&self) -> glam::Mat4 {
         let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
 
         glam::Mat4::look_to_rh(
-            self.position,
+            glam::Vec3::ZERO,
             glam::Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw),
             glam::Vec3::Y,
         )
@@ -64,25 +72,33 @@ pub struct Projection {
     aspect: f32,
     fovy: f32,
     znear: f32,
-    zfar: f32,
+    /// Height of the viewport in pixels
+    height: u32,
 }
 
 impl Projection {
-    pub fn new(width: u32, height: u32, fovy: f32, znear: f32, zfar: f32) -> Self {
+    pub fn new(width: u32, height: u32, fovy: f32, znear: f32) -> Self {
         Self {
             aspect: width as f32 / height as f32,
             fovy,
             znear,
-            zfar,
+            height,
         }
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
+        self.height = height;
+    }
+
+    /// Distance in pixels from the eye to the image plane
+    pub fn focal_length(&self) -> f32 {
+        self.height as f32 * 0.5 / (self.fovy * 0.5).tan()
     }
 
+    /// Infinite projection with reversed depth, see `Texture::DEPTH_COMPARE`
     pub fn projection_matrix(&self) -> glam::Mat4 {
-        glam::Mat4::perspective_rh(self.fovy, self.aspect, self.znear, self.zfar)
+        glam::Mat4::perspective_infinite_reverse_rh(self.fovy, self.aspect, self.znear)
     }
 }
 
@@ -123,6 +139,8 @@ pub struct CameraController {
     rotate_horizontal: f32,
     rotate_vertical: f32,
     speed: f32,
+    /// Scales the speed, the camera slows down near the ground
+    speed_factor: f32,
     sensitivity: f32,
     next_camera_position: Option<CameraPositionType>,
     cursor_locked: bool,
@@ -140,6 +158,7 @@ impl CameraController {
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
             speed,
+            speed_factor: 1.0,
             sensitivity,
             next_camera_position: None,
             cursor_locked,
@@ -222,9 +241,10 @@ impl CameraController {
         let up = glam::Vec3::Y;
 
         // Move in the direction the camera is facing
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
-        camera.position += up * (self.amount_up - self.amount_down) * self.speed * dt;
+        let movement = forward * (self.amount_forward - self.amount_backward)
+            + right * (self.amount_right - self.amount_left)
+            + up * (self.amount_up - self.amount_down);
+        camera.position += (movement * self.speed * self.speed_factor * dt).as_dvec3();
 
         // Rotate
         if self.cursor_locked {
@@ -239,6 +259,10 @@ impl CameraController {
         camera.pitch = camera.pitch.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
     }
 
+    pub fn set_speed_factor(&mut self, speed_factor: f32) {
+        self.speed_factor = speed_factor;
+    }
+
     pub fn cursor_locked(&self) -> bool {
         self.cursor_locked
     }
@@ -261,7 +285,7 @@ pub struct CameraContainer {
 impl CameraContainer {
     pub fn new(width: u32, height: u32, device: &wgpu::Device) -> Self {
         let camera = Camera::from_top();
-        let projection = Projection::new(width, height, (45.0_f32).to_radians(), 0.1, 500.0);
+        let projection = Projection::new(width, height, (45.0_f32).to_radians(), 0.00001);
         let camera_controller = CameraController::new(16.0, 12.0, true);
 
         let mut camera_uniform = CameraUniform::new();
@@ -273,28 +297,19 @@ impl CameraContainer {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
+        let camera_bind_group_layout = material::create_bind_group_layout(
+            device,
+            "camera_bind_group_layout",
+            &[material::Binding::Uniform(
+                wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            )],
+        );
+        let camera_bind_group = material::create_bind_group(
+            device,
+            "camera_bind_group",
+            &camera_bind_group_layout,
+            &[camera_buffer.as_entire_binding()],
+        );
 
         Self {
             camera,
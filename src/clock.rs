@@ -0,0 +1,225 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+
+/// Simulated date, expressed as a Julian date, advancing at an adjustable rate
+pub struct SimulationClock {
+    julian_date: f64,
+    start_julian_date: f64,
+    days_per_second: f64,
+    paused: bool,
+}
+
+impl SimulationClock {
+    pub const J2000: f64 = 2_451_545.0;
+    const JULIAN_DATE_AT_UNIX_EPOCH: f64 = 2_440_587.5;
+    pub const DEFAULT_DAYS_PER_SECOND: f64 = 10.0;
+    /// Environment variable holding the start date, in the `YYYY-MM-DD` format
+    const START_DATE_VARIABLE: &str = "SOLAR_SYSTEM_DATE";
+
+    pub fn new(julian_date: f64) -> Self {
+        Self {
+            julian_date,
+            start_julian_date: julian_date,
+            days_per_second: Self::DEFAULT_DAYS_PER_SECOND,
+            paused: false,
+        }
+    }
+
+    /// Starts at the date from `SOLAR_SYSTEM_DATE` if it is set, otherwise at the current date
+    pub fn from_env() -> Self {
+        let julian_date = match std::env::var(Self::START_DATE_VARIABLE) {
+            Ok(date) => Self::parse_date(&date).unwrap_or_else(|e| {
+                log::warn!("Invalid {}: {e:#}", Self::START_DATE_VARIABLE);
+                Self::now()
+            }),
+            Err(_) => Self::now(),
+        };
+        Self::new(julian_date)
+    }
+
+    pub fn now() -> f64 {
+        let unix_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self::JULIAN_DATE_AT_UNIX_EPOCH + unix_time.as_secs_f64() / 86_400.0
+    }
+
+    fn parse_date(date: &str) -> Result<f64> {
+        let parts = date
+            .trim()
+            .split('-')
+            .map(|part| part.parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()
+            .context("date must be in the YYYY-MM-DD format")?;
+        let [year, month, day] = parts[..] else {
+            bail!("date must be in the YYYY-MM-DD format");
+        };
+        if !(1..=12).contains(&month) {
+            bail!("month out of range");
+        }
+        if !(1..=Self::days_in_month(year, month)).contains(&day) {
+            bail!("day out of range");
+        }
+        Ok(Self::julian_date_from_calendar(year, month, day as f64))
+    }
+
+    /// In the Gregorian calendar
+    fn days_in_month(year: i32, month: i32) -> i32 {
+        match month {
+            2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    /// Converts a date of the Gregorian calendar (day may have a fractional part) to a
+    /// Julian date, see Meeus, "Astronomical Algorithms", chapter 7
+    pub fn julian_date_from_calendar(year: i32, month: i32, day: f64) -> f64 {
+        let (year, month) = if month <= 2 {
+            (year - 1, month + 12)
+        } else {
+            (year, month)
+        };
+        let a = (year as f64 / 100.0).floor();
+        let b = 2.0 - a + (a / 4.0).floor();
+        (365.25 * (year as f64 + 4716.0)).floor()
+            + (30.6001 * (month as f64 + 1.0)).floor()
+            + day
+            + b
+            - 1524.5
+    }
+
+    /// Inverse of [`Self::julian_date_from_calendar`], returns year, month and day
+    pub fn calendar_from_julian_date(julian_date: f64) -> (i32, i32, f64) {
+        let jd = julian_date + 0.5;
+        let z = jd.floor();
+        let f = jd - z;
+        let alpha = ((z - 1_867_216.25) / 36_524.25).floor();
+        let a = z + 1.0 + alpha - (alpha / 4.0).floor();
+        let b = a + 1524.0;
+        let c = ((b - 122.1) / 365.25).floor();
+        let d = (365.25 * c).floor();
+        let e = ((b - d) / 30.6001).floor();
+
+        let day = b - d - (30.6001 * e).floor() + f;
+        let month = if e < 14.0 { e - 1.0 } else { e - 13.0 } as i32;
+        let year = if month > 2 { c - 4716.0 } else { c - 4715.0 } as i32;
+        (year, month, day)
+    }
+
+    pub fn julian_date(&self) -> f64 {
+        self.julian_date
+    }
+
+    /// Simulated days since the clock started, converted to seconds at the default rate.
+    /// Drives the animations (rotation, surface motion), so that they follow the pause and
+    /// the rate of the clock like the orbits do.
+    pub fn animation_time(&self) -> f32 {
+        ((self.julian_date - self.start_julian_date) / Self::DEFAULT_DAYS_PER_SECOND) as f32
+    }
+
+    pub fn days_per_second(&self) -> f64 {
+        self.days_per_second
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        if !self.paused {
+            self.julian_date += dt.as_secs_f64() * self.days_per_second;
+        }
+    }
+
+    pub fn faster(&mut self) {
+        self.days_per_second *= 2.0;
+    }
+
+    pub fn slower(&mut self) {
+        self.days_per_second /= 2.0;
+    }
+
+    pub fn swap_paused(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Date in the `YYYY-MM-DD` format, for logging
+    pub fn date_string(&self) -> String {
+        let (year, month, day) = Self::calendar_from_julian_date(self.julian_date);
+        format!("{year:04}-{month:02}-{:02}", day.floor() as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn j2000_is_noon_on_january_first_2000() {
+        let julian_date = SimulationClock::julian_date_from_calendar(2000, 1, 1.5);
+        assert_eq!(julian_date, SimulationClock::J2000);
+        assert_eq!(
+            SimulationClock::calendar_from_julian_date(SimulationClock::J2000),
+            (2000, 1, 1.5)
+        );
+    }
+
+    #[test]
+    fn julian_dates_match_meeus() {
+        // Examples 7.a and 7.b, and the table of chapter 7 of Meeus
+        let dates = [
+            (1957, 10, 4.81, 2_436_116.31),
+            (1987, 1, 27.0, 2_446_822.5),
+            (1988, 6, 19.5, 2_447_332.0),
+            (1600, 12, 31.0, 2_305_812.5),
+            (1900, 1, 1.0, 2_415_020.5),
+        ];
+        for (year, month, day, expected) in dates {
+            let julian_date = SimulationClock::julian_date_from_calendar(year, month, day);
+            assert!(
+                (julian_date - expected).abs() < 1e-9,
+                "{year}-{month}-{day}"
+            );
+        }
+    }
+
+    #[test]
+    fn calendar_dates_round_trip() {
+        for julian_date in (2_300_000..2_500_000).step_by(997) {
+            let julian_date = julian_date as f64 + 0.25;
+            let (year, month, day) = SimulationClock::calendar_from_julian_date(julian_date);
+            let round_trip = SimulationClock::julian_date_from_calendar(year, month, day);
+            assert!((round_trip - julian_date).abs() < 1e-6, "JD {julian_date}");
+        }
+    }
+
+    #[test]
+    fn parses_start_dates() {
+        let julian_date = SimulationClock::parse_date("2000-01-01").unwrap();
+        assert_eq!(julian_date, SimulationClock::J2000 - 0.5);
+        assert!(SimulationClock::parse_date("2000-13-01").is_err());
+        assert!(SimulationClock::parse_date("2000-02-29").is_ok());
+        assert!(SimulationClock::parse_date("1900-02-29").is_err());
+        assert!(SimulationClock::parse_date("2000-02-31").is_err());
+        assert!(SimulationClock::parse_date("2001-04-31").is_err());
+        assert!(SimulationClock::parse_date("2001-12-32").is_err());
+        assert!(SimulationClock::parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn animation_time_follows_the_clock() {
+        let mut clock = SimulationClock::new(SimulationClock::J2000);
+        clock.update(Duration::from_secs(2));
+        assert_eq!(clock.animation_time(), 2.0);
+        clock.swap_paused();
+        clock.update(Duration::from_secs(2));
+        assert_eq!(clock.animation_time(), 2.0);
+        clock.swap_paused();
+        clock.faster();
+        clock.update(Duration::from_secs(1));
+        assert_eq!(clock.animation_time(), 4.0);
+    }
+}
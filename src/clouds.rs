@@ -0,0 +1,205 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera, instance,
+    material::{self, SetMaterial},
+    sphere::{self, Sphere},
+    sun,
+    vertex::Vertex,
+};
+
+/// Transparent cloud shell drawn around a planet. Cloud density is procedural, so the
+/// same function is evaluated by the planet shader to cast cloud shadows on the surface.
+pub struct Clouds {
+    instance: instance::Instance,
+    instance_buffer: wgpu::Buffer,
+    buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    material: material::Material,
+    sphere: Sphere,
+    /// Same as the planet's, so that the surface never pokes through the shell
+    lod: usize,
+}
+
+impl Clouds {
+    /// Radius of the cloud shell relative to the planet radius
+    const SHELL_SCALE: f32 = 1.015;
+    const ROTATION_SPEED: f32 = 0.43;
+    const COVERAGE: f32 = 0.48;
+    const SHADOW_STRENGTH: f32 = 0.45;
+    /// How much the clouds on the night side are lit (e.g. by moonlight)
+    const NIGHT_BRIGHTNESS: f32 = 0.04;
+
+    pub fn new(
+        device: &wgpu::Device,
+        materials: &mut material::PipelineCache,
+        camera_container: &camera::CameraContainer,
+        sun: &sun::Sun,
+        sphere: &Sphere,
+        planet: &instance::Instance,
+    ) -> Self {
+        let origin = camera_container.camera.position();
+        let instance = instance::Instance::new(
+            planet.position,
+            glam::Quat::from_rotation_y(0.0),
+            0,
+            planet.scale() * Self::SHELL_SCALE,
+        );
+
+        let instance_data = vec![instance::InstanceRaw::new(&instance, origin)];
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Clouds Instance Buffer"),
+            contents: bytemuck::cast_slice(&instance_data),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform = CloudsUniform::new(
+            &instance,
+            planet,
+            origin,
+            Self::COVERAGE,
+            Self::SHADOW_STRENGTH,
+            Self::NIGHT_BRIGHTNESS,
+        );
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Clouds Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = materials.bind_group_layout(
+            device,
+            "clouds_bind_group_layout",
+            &[material::Binding::Uniform(
+                wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            )],
+        );
+        let bind_group = material::create_bind_group(
+            device,
+            "clouds_bind_group",
+            &bind_group_layout,
+            &[buffer.as_entire_binding()],
+        );
+
+        let material = materials.material(
+            device,
+            material::MaterialDescriptor {
+                name: "clouds",
+                shader: wgpu::include_wgsl!("../shaders/clouds.wgsl"),
+                bind_groups: &[
+                    (material::BindGroupSlot::Object, &bind_group_layout),
+                    (
+                        material::BindGroupSlot::Camera,
+                        &camera_container.camera_bind_group_layout,
+                    ),
+                    (
+                        material::BindGroupSlot::Light,
+                        &sun.light().bind_group_layout,
+                    ),
+                ],
+                vertex_layouts: &[sphere::SphereVertex::desc(), instance::InstanceRaw::desc()],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                blending: material::Blending::Transparent {
+                    blend: wgpu::BlendState::ALPHA_BLENDING,
+                    cull_mode: Some(wgpu::Face::Back),
+                },
+            },
+        );
+
+        Self {
+            instance,
+            instance_buffer,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            material,
+            sphere: sphere.clone(),
+            lod: 0,
+        }
+    }
+
+    /// Follows the planet, while rotating at its own rate. `time` is the
+    /// [`crate::clock::SimulationClock::animation_time`].
+    pub fn update(&mut self, time: f32, planet: &instance::Instance) {
+        let rotation_angle = time * Self::ROTATION_SPEED;
+        self.instance = instance::Instance::new(
+            planet.position,
+            glam::Quat::from_rotation_y(rotation_angle),
+            0,
+            planet.scale() * Self::SHELL_SCALE,
+        );
+    }
+
+    /// `origin` is the camera position, all positions are sent relative to it. `lod` is the
+    /// level of detail of the planet.
+    pub fn sync_buffers(
+        &mut self,
+        queue: &wgpu::Queue,
+        planet: &instance::Instance,
+        origin: glam::DVec3,
+        lod: usize,
+    ) {
+        self.lod = lod;
+        let instance_data = vec![instance::InstanceRaw::new(&self.instance, origin)];
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&instance_data),
+        );
+        let uniform = CloudsUniform::new(
+            &self.instance,
+            planet,
+            origin,
+            Self::COVERAGE,
+            Self::SHADOW_STRENGTH,
+            Self::NIGHT_BRIGHTNESS,
+        );
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CloudsUniform {
+    world_to_local: [[f32; 4]; 4],
+    center: [f32; 3],
+    planet_radius: f32,
+    shell_radius: f32,
+    coverage: f32,
+    shadow_strength: f32,
+    night_brightness: f32,
+    planet_texture_index: u32,
+    _padding: [u32; 3],
+}
+
+impl CloudsUniform {
+    pub fn new(
+        shell: &instance::Instance,
+        planet: &instance::Instance,
+        origin: glam::DVec3,
+        coverage: f32,
+        shadow_strength: f32,
+        night_brightness: f32,
+    ) -> Self {
+        Self {
+            world_to_local: glam::Mat4::from_quat(shell.rotation.inverse()).to_cols_array_2d(),
+            center: (shell.position - origin).as_vec3().into(),
+            planet_radius: planet.scale(),
+            shell_radius: shell.scale(),
+            coverage,
+            shadow_strength,
+            night_brightness,
+            planet_texture_index: planet.texture_index(),
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Clouds are transparent, they have to be drawn after all opaque objects
+impl material::Renderable for Clouds {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &material::SceneBindGroups) {
+        pass.set_material(&self.material, scene, &[&self.bind_group]);
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        self.sphere.draw_instanced(pass, self.lod, 0..1);
+    }
+}
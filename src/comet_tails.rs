@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera,
+    material::{self, SetMaterial},
+    pipeline,
+};
+
+/// State of a comet needed to shape its tails, positions are in scene units
+pub struct TailSource {
+    pub nucleus: glam::DVec3,
+    pub direction_of_motion: glam::Vec3,
+    pub distance_au: f32,
+    /// Scale of the scene around the comet, used to convert tail lengths
+    pub units_per_au: f32,
+    /// Length of the ion tail at 1 AU from the sun
+    pub tail_length_au: f32,
+}
+
+/// Ion and dust tails of the comets, drawn as particle systems. Particles don't have any
+/// state, their positions are derived in the vertex shader from their index and the time,
+/// so that they stream away from the nucleus.
+pub struct CometTails {
+    comets_count: usize,
+    /// Nucleus of every comet, in double precision until it is made relative to the camera
+    nuclei: Vec<glam::DVec3>,
+    uniform: CometTailsUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    material: material::Material,
+}
+
+impl CometTails {
+    /// Must match `MAX_TAILS` in `comet_tail.wgsl`, every comet has two tails
+    const MAX_COMETS: usize = 4;
+    /// Must match `PARTICLES_PER_TAIL` in `comet_tail.wgsl`
+    const PARTICLES_PER_TAIL: u32 = 4096;
+    /// Comets are inactive further from the sun than this
+    const ACTIVITY_DISTANCE_AU: f32 = 4.0;
+
+    const ION_COLOR: [f32; 3] = [0.35, 0.6, 1.0];
+    const ION_WIDTH: f32 = 0.03;
+    const ION_BRIGHTNESS: f32 = 1.0;
+    const DUST_COLOR: [f32; 3] = [1.0, 0.85, 0.6];
+    /// Dust tails are shorter and wider than ion tails
+    const DUST_LENGTH: f32 = 0.6;
+    const DUST_WIDTH: f32 = 0.12;
+    const DUST_BRIGHTNESS: f32 = 0.7;
+    /// How much the dust tail bends towards the path already travelled
+    const DUST_CURVATURE: f32 = 0.5;
+
+    pub fn new(
+        device: &wgpu::Device,
+        materials: &mut material::PipelineCache,
+        camera_container: &camera::CameraContainer,
+        comets_count: usize,
+    ) -> Self {
+        assert!(comets_count <= Self::MAX_COMETS, "Too many comets");
+
+        let uniform = CometTailsUniform {
+            tails: [TailUniform::default(); Self::MAX_COMETS * 2],
+            time: 0.0,
+            _padding: [0.0; 3],
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Comet Tails Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = materials.bind_group_layout(
+            device,
+            "comet_tails_bind_group_layout",
+            &[material::Binding::Uniform(wgpu::ShaderStages::VERTEX)],
+        );
+        let bind_group = material::create_bind_group(
+            device,
+            "comet_tails_bind_group",
+            &bind_group_layout,
+            &[buffer.as_entire_binding()],
+        );
+
+        let material = materials.material(
+            device,
+            material::MaterialDescriptor {
+                name: "comet_tails",
+                shader: wgpu::include_wgsl!("../shaders/comet_tail.wgsl"),
+                bind_groups: &[
+                    (material::BindGroupSlot::Object, &bind_group_layout),
+                    (
+                        material::BindGroupSlot::Camera,
+                        &camera_container.camera_bind_group_layout,
+                    ),
+                ],
+                // Particle billboards are generated in the shader
+                vertex_layouts: &[],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                blending: material::Blending::Transparent {
+                    blend: pipeline::ADDITIVE_BLENDING,
+                    cull_mode: Some(wgpu::Face::Back),
+                },
+            },
+        );
+
+        Self {
+            comets_count,
+            nuclei: vec![glam::DVec3::ZERO; comets_count],
+            uniform,
+            buffer,
+            bind_group,
+            material,
+        }
+    }
+
+    /// Tails point away from the light of the sun and grow as the comet gets closer to it
+    pub fn update(
+        &mut self,
+        total_time: Duration,
+        light_position: glam::DVec3,
+        sources: &[TailSource],
+    ) {
+        self.uniform.time = total_time.as_secs_f32();
+        for (i, source) in sources.iter().enumerate() {
+            let activity = Self::activity(source.distance_au);
+            let length = source.tail_length_au * activity.sqrt() * source.units_per_au;
+            let direction = (source.nucleus - light_position)
+                .normalize_or_zero()
+                .as_vec3();
+            let trailing = -source.direction_of_motion;
+
+            self.nuclei[i] = source.nucleus;
+            self.uniform.tails[2 * i] = TailUniform {
+                length,
+                direction: direction.into(),
+                width: Self::ION_WIDTH,
+                trailing: trailing.into(),
+                curvature: 0.0,
+                color: Self::ION_COLOR,
+                brightness: Self::ION_BRIGHTNESS * activity,
+                ..Default::default()
+            };
+            self.uniform.tails[2 * i + 1] = TailUniform {
+                length: length * Self::DUST_LENGTH,
+                direction: direction.into(),
+                width: Self::DUST_WIDTH,
+                trailing: trailing.into(),
+                curvature: Self::DUST_CURVATURE,
+                color: Self::DUST_COLOR,
+                brightness: Self::DUST_BRIGHTNESS * activity,
+                ..Default::default()
+            };
+        }
+    }
+
+    /// 0.0 beyond [`Self::ACTIVITY_DISTANCE_AU`], 1.0 at 1 AU, growing with the sunlight
+    /// received, which falls with the square of the distance
+    fn activity(distance_au: f32) -> f32 {
+        let limit = 1.0 / (Self::ACTIVITY_DISTANCE_AU * Self::ACTIVITY_DISTANCE_AU);
+        ((1.0 / (distance_au * distance_au) - limit) / (1.0 - limit)).max(0.0)
+    }
+
+    /// `origin` is the camera position, all positions are sent relative to it
+    pub fn sync_buffer(&self, queue: &wgpu::Queue, origin: glam::DVec3) {
+        let mut uniform = self.uniform;
+        for (i, nucleus) in self.nuclei.iter().enumerate() {
+            let position = (*nucleus - origin).as_vec3().into();
+            uniform.tails[2 * i].origin = position;
+            uniform.tails[2 * i + 1].origin = position;
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TailUniform {
+    /// Nucleus, relative to the camera
+    origin: [f32; 3],
+    /// In scene units, 0.0 hides the tail
+    length: f32,
+    /// Away from the sun
+    direction: [f32; 3],
+    /// Relative to the length
+    width: f32,
+    /// Opposite to the direction of motion
+    trailing: [f32; 3],
+    curvature: f32,
+    color: [f32; 3],
+    brightness: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CometTailsUniform {
+    tails: [TailUniform; CometTails::MAX_COMETS * 2],
+    /// Seconds since the start
+    time: f32,
+    _padding: [f32; 3],
+}
+
+/// Tails are transparent, they have to be drawn after all opaque objects
+impl material::Renderable for CometTails {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &material::SceneBindGroups) {
+        pass.set_material(&self.material, scene, &[&self.bind_group]);
+        let particles = self.comets_count as u32 * 2 * Self::PARTICLES_PER_TAIL;
+        pass.draw(0..6, 0..particles);
+    }
+}
@@ -0,0 +1,401 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow, bail};
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::{
+    ktx2::{CompressedFormat, Ktx2Cubemap},
+    texture::{CubeTextureSource, TextureContainer},
+};
+
+/// Offline conversion of images to compressed KTX2 cubemaps with mipmaps, which the planets
+/// load instead of the images when the device supports the format:
+///
+/// `solar-system convert-textures [--format bc7|etc2] [--face-size N] [--thumbnails] [IMAGE...]`
+///
+/// Without images, the planet textures are converted to every format which can be encoded.
+/// With `--thumbnails`, the low resolution images shown while the planet textures are
+/// loading are written instead, see [`TextureContainer::thumbnail_path`].
+pub fn convert_textures(args: &[String]) -> Result<()> {
+    let mut formats = Vec::new();
+    let mut face_size = TextureContainer::PLANETS_FACE_SIZE;
+    let mut thumbnails = false;
+    let mut images = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--thumbnails" => thumbnails = true,
+            "--format" => {
+                let name = args.next().ok_or_else(|| anyhow!("Missing format"))?;
+                let format = CompressedFormat::parse(name)
+                    .ok_or_else(|| anyhow!("Unknown format {name}"))?;
+                formats.push(format);
+            }
+            "--face-size" => {
+                let size = args.next().ok_or_else(|| anyhow!("Missing face size"))?;
+                face_size = size.parse().context("Invalid face size")?;
+            }
+            image => images.push(image),
+        }
+    }
+    if formats.is_empty() {
+        formats = vec![CompressedFormat::Bc7, CompressedFormat::Etc2];
+    }
+    if images.is_empty() {
+        images = TextureContainer::PLANET_TEXTURES.to_vec();
+    }
+    if thumbnails {
+        return write_thumbnails(&images);
+    }
+    // Mipmaps are halved down to a single block
+    if !face_size.is_power_of_two() || face_size < 4 {
+        bail!("The face size must be a power of two of at least 4");
+    }
+
+    for image in images {
+        let faces = match CubeTextureSource::load(image)? {
+            CubeTextureSource::Equirectangular(image) => equirect_to_faces(&image, face_size),
+            CubeTextureSource::CubeFaces(faces) => faces.map(|face| {
+                image::imageops::resize(
+                    &face,
+                    face_size,
+                    face_size,
+                    image::imageops::FilterType::Triangle,
+                )
+            }),
+        };
+        let mip_levels = mip_levels(faces);
+        for &format in &formats {
+            let cubemap = Ktx2Cubemap {
+                format,
+                size: face_size,
+                levels: mip_levels
+                    .iter()
+                    .map(|faces| encode_faces(faces, format))
+                    .collect::<Result<_>>()?,
+            };
+            let path = format.path_for(Path::new(image));
+            std::fs::write(&path, cubemap.to_bytes())
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("Wrote {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn write_thumbnails(images: &[&str]) -> Result<()> {
+    let (width, height) = TextureContainer::THUMBNAIL_SIZE;
+    for image in images {
+        let thumbnail = image::open(image)
+            .with_context(|| format!("Failed to load {image}"))?
+            .resize_exact(width, height, image::imageops::FilterType::Triangle)
+            .to_rgb8();
+        let path = TextureContainer::thumbnail_path(image);
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)
+                .with_context(|| format!("Failed to create {}", directory.display()))?;
+        }
+        thumbnail
+            .save(&path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// Same mapping as `cube_faces.wgsl`, on the CPU
+fn equirect_to_faces(image: &image::RgbaImage, face_size: u32) -> [image::RgbaImage; 6] {
+    let (width, height) = image.dimensions();
+    let texel = |x: i64, y: i64| {
+        // Equirectangular maps wrap around the meridian, but not around the poles
+        let x = x.rem_euclid(width as i64) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        Vec4::from_array(image.get_pixel(x, y).0.map(f32::from))
+    };
+
+    std::array::from_fn(|face| {
+        image::RgbaImage::from_fn(face_size, face_size, |x, y| {
+            let uv = (glam::Vec2::new(x as f32, y as f32) + 0.5) / face_size as f32 * 2.0 - 1.0;
+            let direction = face_direction(face, uv).normalize();
+            let u = (direction.z.atan2(direction.x) / std::f32::consts::TAU).rem_euclid(1.0);
+            let v = direction.y.clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+
+            // Bilinear filtering
+            let (tx, ty) = (u * width as f32 - 0.5, v * height as f32 - 0.5);
+            let (x0, y0) = (tx.floor(), ty.floor());
+            let (fx, fy) = (tx - x0, ty - y0);
+            let (x0, y0) = (x0 as i64, y0 as i64);
+            let top = texel(x0, y0).lerp(texel(x0 + 1, y0), fx);
+            let bottom = texel(x0, y0 + 1).lerp(texel(x0 + 1, y0 + 1), fx);
+            let color = top.lerp(bottom, fy).round();
+            image::Rgba(color.to_array().map(|c| c as u8))
+        })
+    })
+}
+
+/// Must match `face_direction` in `cube_faces.wgsl`
+fn face_direction(face: usize, uv: glam::Vec2) -> Vec3 {
+    match face {
+        0 => Vec3::new(1.0, -uv.y, -uv.x),
+        1 => Vec3::new(-1.0, -uv.y, uv.x),
+        2 => Vec3::new(uv.x, 1.0, uv.y),
+        3 => Vec3::new(uv.x, -1.0, -uv.y),
+        4 => Vec3::new(uv.x, -uv.y, 1.0),
+        _ => Vec3::new(-uv.x, -uv.y, -1.0),
+    }
+}
+
+/// Every level halves the previous one, down to a single block
+fn mip_levels(faces: [image::RgbaImage; 6]) -> Vec<[image::RgbaImage; 6]> {
+    let mut levels = vec![faces];
+    loop {
+        let size = levels.last().unwrap()[0].width() / 2;
+        if size < 4 {
+            return levels;
+        }
+        let level = levels.last().unwrap().each_ref().map(|face| {
+            image::imageops::resize(face, size, size, image::imageops::FilterType::Triangle)
+        });
+        levels.push(level);
+    }
+}
+
+/// Blocks of the six faces one after another, every face row by row. Faces are encoded on
+/// their own threads.
+fn encode_faces(faces: &[image::RgbaImage; 6], format: CompressedFormat) -> Result<Vec<u8>> {
+    let encode_block: fn(&[[u8; 4]; 16]) -> Vec<u8> = match format {
+        CompressedFormat::Bc7 => |block| encode_bc7(block).to_vec(),
+        CompressedFormat::Etc2 => |block| encode_etc2(block).to_vec(),
+        CompressedFormat::Astc4x4 => {
+            bail!("ASTC is not encoded here, use an external encoder such as astcenc")
+        }
+    };
+    let faces = std::thread::scope(|scope| {
+        let threads = faces
+            .iter()
+            .map(|face| scope.spawn(move || encode_face(face, encode_block)))
+            .collect::<Vec<_>>();
+        threads
+            .into_iter()
+            .map(|thread| thread.join().expect("Encoding thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    Ok(faces.concat())
+}
+
+fn encode_face(face: &image::RgbaImage, encode_block: fn(&[[u8; 4]; 16]) -> Vec<u8>) -> Vec<u8> {
+    let mut data = Vec::new();
+    for block_y in 0..face.height() / 4 {
+        for block_x in 0..face.width() / 4 {
+            // Row by row, as the blocks index their texels
+            let block = std::array::from_fn(|i| {
+                let (x, y) = (i as u32 % 4, i as u32 / 4);
+                face.get_pixel(block_x * 4 + x, block_y * 4 + y).0
+            });
+            data.extend(encode_block(&block));
+        }
+    }
+    data
+}
+
+/// Mode 6 of BC7: a single RGBA line with 7 bit endpoints, a shared bit per endpoint and 4 bit
+/// indices. The endpoints are fitted along the principal axis of the texels.
+fn encode_bc7(texels: &[[u8; 4]; 16]) -> [u8; 16] {
+    const WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+    let colors = texels.map(|texel| Vec4::from_array(texel.map(f32::from)));
+    let mean = colors.iter().sum::<Vec4>() / 16.0;
+    let covariance = colors
+        .iter()
+        .map(|&color| {
+            let d = color - mean;
+            Mat4::from_cols(d * d.x, d * d.y, d * d.z, d * d.w)
+        })
+        .fold(Mat4::ZERO, |sum, m| sum + m);
+    // Power iteration
+    let mut axis = Vec4::ONE;
+    for _ in 0..8 {
+        axis = (covariance * axis).normalize_or_zero();
+    }
+    let (low, high) = colors
+        .iter()
+        .map(|&color| (color - mean).dot(axis))
+        .fold((0.0f32, 0.0f32), |(low, high), t| (low.min(t), high.max(t)));
+
+    // Every endpoint picks the shared bit which gets it closer
+    let quantize = |endpoint: Vec4| {
+        let endpoint = endpoint.clamp(Vec4::ZERO, Vec4::splat(255.0));
+        [0u32, 1]
+            .map(|p| {
+                let q = ((endpoint - p as f32) / 2.0)
+                    .round()
+                    .clamp(Vec4::ZERO, Vec4::splat(127.0));
+                let value = q * 2.0 + p as f32;
+                ((value - endpoint).length_squared(), q, p)
+            })
+            .into_iter()
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, q, p)| (q.to_array().map(|c| c as u32), p))
+            .unwrap()
+    };
+    let mut endpoints = [quantize(mean + axis * low), quantize(mean + axis * high)];
+
+    let unquantize = |(q, p): ([u32; 4], u32)| q.map(|c| (c << 1) | p);
+    let palette = |endpoints: &[([u32; 4], u32); 2]| {
+        let [e0, e1] = endpoints.map(unquantize);
+        WEIGHTS
+            .map(|w| std::array::from_fn::<u32, 4, _>(|c| ((64 - w) * e0[c] + w * e1[c] + 32) >> 6))
+    };
+    let palette_colors = palette(&endpoints);
+    let mut indices = texels.map(|texel| {
+        (0..16)
+            .min_by_key(|&i| {
+                (0..4)
+                    .map(|c| (palette_colors[i][c] as i32 - texel[c] as i32).pow(2))
+                    .sum::<i32>()
+            })
+            .unwrap() as u32
+    });
+    // The most significant bit of the first index is implicitly zero
+    if indices[0] >= 8 {
+        endpoints.swap(0, 1);
+        indices = indices.map(|i| 15 - i);
+    }
+
+    let mut bits = 0u128;
+    let mut position = 0;
+    let mut write = |value: u32, count: u32| {
+        bits |= (value as u128) << position;
+        position += count;
+    };
+    write(1 << 6, 7);
+    for channel in 0..4 {
+        write(endpoints[0].0[channel], 7);
+        write(endpoints[1].0[channel], 7);
+    }
+    write(endpoints[0].1, 1);
+    write(endpoints[1].1, 1);
+    write(indices[0], 3);
+    for &index in &indices[1..] {
+        write(index, 4);
+    }
+    bits.to_le_bytes()
+}
+
+/// ETC2 RGB blocks in the individual or differential modes of ETC1, which is a subset of
+/// ETC2. Both orientations of the two sub-blocks and both modes are tried.
+fn encode_etc2(texels: &[[u8; 4]; 16]) -> [u8; 8] {
+    const MODIFIERS: [[i32; 4]; 8] = [
+        [2, 8, -2, -8],
+        [5, 17, -5, -17],
+        [9, 29, -9, -29],
+        [13, 42, -13, -42],
+        [18, 60, -18, -60],
+        [24, 80, -24, -80],
+        [33, 106, -33, -106],
+        [47, 183, -47, -183],
+    ];
+
+    // Best table of a sub-block with the given base color, with its error and the index of
+    // every texel
+    let fit = |base: [i32; 3], members: &[usize]| {
+        (0..8)
+            .map(|table| {
+                let mut error = 0;
+                let mut indices = Vec::new();
+                for &i in members {
+                    let (index_error, index) = (0..4)
+                        .map(|index| {
+                            let error = (0..3)
+                                .map(|c| {
+                                    let value = (base[c] + MODIFIERS[table][index]).clamp(0, 255);
+                                    (value - texels[i][c] as i32).pow(2)
+                                })
+                                .sum::<i32>();
+                            (error, index)
+                        })
+                        .min()
+                        .unwrap();
+                    error += index_error;
+                    indices.push((i, index as u64));
+                }
+                (error, table as u64, indices)
+            })
+            .min_by_key(|(error, _, _)| *error)
+            .unwrap()
+    };
+
+    let mut best: Option<(i32, u64)> = None;
+    for flip in [false, true] {
+        let halves: [Vec<usize>; 2] = [0, 1].map(|half| {
+            (0..16)
+                .filter(|&i| {
+                    let (x, y) = (i % 4, i / 4);
+                    let coordinate = if flip { y } else { x };
+                    (coordinate >= 2) == (half == 1)
+                })
+                .collect()
+        });
+        let averages = halves.each_ref().map(|members| {
+            let sum = members.iter().fold(Vec3::ZERO, |sum, &i| {
+                sum + Vec3::new(
+                    texels[i][0] as f32,
+                    texels[i][1] as f32,
+                    texels[i][2] as f32,
+                )
+            });
+            sum / members.len() as f32
+        });
+
+        let individual = averages.map(|average| {
+            (average / 17.0)
+                .round()
+                .clamp(Vec3::ZERO, Vec3::splat(15.0))
+                .to_array()
+                .map(|c| c as i32)
+        });
+        let differential = averages.map(|average| {
+            (average * 31.0 / 255.0)
+                .round()
+                .clamp(Vec3::ZERO, Vec3::splat(31.0))
+                .to_array()
+                .map(|c| c as i32)
+        });
+        let deltas: [i32; 3] = std::array::from_fn(|c| differential[1][c] - differential[0][c]);
+
+        let mut candidates = vec![(false, individual, individual.map(|q| q.map(|c| c * 17)))];
+        // Larger deltas would select the other modes of ETC2
+        if deltas.iter().all(|delta| (-4..=3).contains(delta)) {
+            let expanded = differential.map(|q| q.map(|c| (c << 3) | (c >> 2)));
+            candidates.push((true, differential, expanded));
+        }
+        for (is_differential, quantized, bases) in candidates {
+            let fits = [0, 1].map(|half| fit(bases[half], &halves[half]));
+            let error = fits[0].0 + fits[1].0;
+            if best.is_some_and(|(best_error, _)| best_error <= error) {
+                continue;
+            }
+
+            let mut block = 0u64;
+            for c in 0..3 {
+                let shift = 59 - 8 * c as u64;
+                if is_differential {
+                    block |= (quantized[0][c] as u64) << shift;
+                    block |= ((deltas[c] & 0b111) as u64) << (shift - 3);
+                } else {
+                    block |= (quantized[0][c] as u64) << (shift + 1);
+                    block |= (quantized[1][c] as u64) << (shift - 3);
+                }
+            }
+            block |= fits[0].1 << 37 | fits[1].1 << 34;
+            block |= (is_differential as u64) << 33 | (flip as u64) << 32;
+            // Texels are numbered column by column, the high bits of the indices come first
+            for (i, index) in fits.iter().flat_map(|(_, _, indices)| indices) {
+                let texel = (i % 4) * 4 + i / 4;
+                block |= (index >> 1) << (16 + texel) | (index & 1) << texel;
+            }
+            best = Some((error, block));
+        }
+    }
+    best.unwrap().1.to_be_bytes()
+}
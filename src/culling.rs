@@ -0,0 +1,491 @@
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    camera, instance, pipeline,
+    sphere::{self, Sphere},
+};
+
+/// View frustum of the camera, relative to the camera like everything sent to the GPU
+pub struct Frustum {
+    /// Inward facing planes as `(normal, distance)`, the far plane is at infinity
+    planes: [glam::Vec4; 6],
+    focal_length: f32,
+}
+
+impl Frustum {
+    pub fn new(camera: &camera::Camera, projection: &camera::Projection) -> Self {
+        let view_projection = projection.projection_matrix() * camera.view_matrix();
+        let (x, y, z, w) = (
+            view_projection.row(0),
+            view_projection.row(1),
+            view_projection.row(2),
+            view_projection.row(3),
+        );
+        // Gribb-Hartmann, with the near and far planes swapped by the reversed depth
+        let planes = [w + x, w - x, w + y, w - y, w - z, z].map(|plane| {
+            let length = plane.truncate().length();
+            // The far plane of the infinite projection has no normal and is always passed
+            if length > f32::EPSILON {
+                plane / length
+            } else {
+                glam::Vec4::W
+            }
+        });
+        Self {
+            planes,
+            focal_length: projection.focal_length(),
+        }
+    }
+
+    /// Whether any part of a sphere is inside of the frustum
+    pub fn contains_sphere(&self, center: glam::Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
+
+    /// Radius in pixels of a sphere `distance` away from the camera, it is infinite when the
+    /// camera is inside of the sphere
+    pub fn projected_radius(&self, distance: f32, radius: f32) -> f32 {
+        if distance <= radius {
+            return f32::INFINITY;
+        }
+        radius / (distance * distance - radius * radius).sqrt() * self.focal_length
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CullingStats {
+    pub visible: u32,
+    pub culled: u32,
+}
+
+impl std::fmt::Display for CullingStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} visible, {} culled", self.visible, self.culled)
+    }
+}
+
+/// Culls instanced spheres against the view frustum and, optionally, by their size on the
+/// screen, and groups the visible ones by level of detail. When the adapter supports
+/// indirect draws with a first instance this is done by a compute shader, which also
+/// writes the arguments of the draws, otherwise on the CPU.
+pub struct InstanceCulling {
+    capacity: u32,
+    /// Visible instances, `capacity` of them for every level of detail on the GPU
+    instance_buffer: wgpu::Buffer,
+    min_projected_radius: f32,
+    method: CullingMethod,
+    stats: CullingStats,
+}
+
+enum CullingMethod {
+    Cpu {
+        batches: sphere::LodBatches,
+    },
+    Gpu {
+        count: u32,
+        input_buffer: wgpu::Buffer,
+        uniform_buffer: wgpu::Buffer,
+        /// One `wgpu::util::DrawIndexedIndirectArgs` for every level of detail
+        indirect_buffer: wgpu::Buffer,
+        bind_group: wgpu::BindGroup,
+        pipeline: wgpu::ComputePipeline,
+        stats_readback: StatsReadback,
+    },
+}
+
+impl InstanceCulling {
+    /// Environment variable which forces culling on the CPU when set to `0`
+    const GPU_CULLING_VARIABLE: &str = "SOLAR_SYSTEM_GPU_CULLING";
+    /// Environment variable with the radius in pixels below which bodies are not drawn
+    const MIN_RADIUS_VARIABLE: &str = "SOLAR_SYSTEM_CULL_RADIUS";
+    const WORKGROUP_SIZE: u32 = 64;
+    const INDIRECT_ARGS_SIZE: wgpu::BufferAddress =
+        mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as _;
+
+    pub fn new(device: &wgpu::Device, capacity: usize, label: &str) -> Self {
+        let capacity = capacity as u32;
+        let min_projected_radius = std::env::var(Self::MIN_RADIUS_VARIABLE)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0.0);
+        // Without a first instance, the indirect draws could only read the first region
+        let gpu_culling = device
+            .features()
+            .contains(wgpu::Features::INDIRECT_FIRST_INSTANCE)
+            && std::env::var(Self::GPU_CULLING_VARIABLE).as_deref() != Ok("0");
+        log::info!(
+            "Culling of {label} on the {}",
+            if gpu_culling { "GPU" } else { "CPU" }
+        );
+
+        let instance_size = mem::size_of::<instance::InstanceRaw>() as wgpu::BufferAddress;
+        let levels = if gpu_culling { Sphere::LOD_COUNT } else { 1 };
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{label} Visible Instance Buffer")),
+            size: instance_size * capacity as wgpu::BufferAddress * levels as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let method = if gpu_culling {
+            Self::create_gpu_method(device, capacity, &instance_buffer, label)
+        } else {
+            CullingMethod::Cpu {
+                batches: sphere::LodBatches::default(),
+            }
+        };
+
+        Self {
+            capacity,
+            instance_buffer,
+            min_projected_radius,
+            method,
+            stats: CullingStats::default(),
+        }
+    }
+
+    fn create_gpu_method(
+        device: &wgpu::Device,
+        capacity: u32,
+        instance_buffer: &wgpu::Buffer,
+        label: &str,
+    ) -> CullingMethod {
+        let input_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{label} Culling Input Buffer")),
+            size: mem::size_of::<instance::InstanceRaw>() as wgpu::BufferAddress
+                * capacity as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{label} Culling Uniform Buffer")),
+            size: mem::size_of::<CullingUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let indirect_size = Self::INDIRECT_ARGS_SIZE * Sphere::LOD_COUNT as wgpu::BufferAddress;
+        let indirect_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{label} Indirect Buffer")),
+            size: indirect_size,
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+                storage_entry(3, false),
+            ],
+            label: Some("culling_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: input_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: indirect_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("culling_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Culling Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = wgpu::include_wgsl!("../shaders/cull_instances.wgsl");
+        let pipeline = pipeline::create_compute_pipeline(
+            device,
+            &layout,
+            shader,
+            Some("compute_pipeline_culling"),
+        );
+
+        CullingMethod::Gpu {
+            count: 0,
+            input_buffer,
+            uniform_buffer,
+            indirect_buffer,
+            bind_group,
+            pipeline,
+            stats_readback: StatsReadback::new(device, indirect_size, label),
+        }
+    }
+
+    /// Statistics of the last culled frame, on the GPU they arrive a few frames late
+    pub fn stats(&self) -> CullingStats {
+        self.stats
+    }
+
+    /// `origin` is the camera position, all positions are sent relative to it
+    pub fn sync_buffers<'i>(
+        &mut self,
+        queue: &wgpu::Queue,
+        instances: impl IntoIterator<Item = &'i instance::Instance>,
+        origin: glam::DVec3,
+        frustum: &Frustum,
+    ) {
+        let instances = instances
+            .into_iter()
+            .take(self.capacity as usize)
+            .collect::<Vec<_>>();
+        match &mut self.method {
+            CullingMethod::Cpu { batches } => {
+                let visible = instances
+                    .iter()
+                    .filter_map(|instance| {
+                        let center = (instance.position - origin).as_vec3();
+                        let radius = instance.scale();
+                        let size = frustum.projected_radius(center.length(), radius);
+                        (frustum.contains_sphere(center, radius)
+                            && size >= self.min_projected_radius)
+                            .then(|| {
+                                (
+                                    Sphere::lod(size),
+                                    instance::InstanceRaw::new(instance, origin),
+                                )
+                            })
+                    })
+                    .collect::<Vec<_>>();
+                let (lod_batches, instance_data) = sphere::LodBatches::sort(visible);
+                *batches = lod_batches;
+                queue.write_buffer(
+                    &self.instance_buffer,
+                    0,
+                    bytemuck::cast_slice(&instance_data),
+                );
+                self.stats = CullingStats {
+                    visible: instance_data.len() as u32,
+                    culled: (instances.len() - instance_data.len()) as u32,
+                };
+            }
+            CullingMethod::Gpu {
+                count,
+                input_buffer,
+                uniform_buffer,
+                indirect_buffer,
+                stats_readback,
+                ..
+            } => {
+                if let Some(stats) = stats_readback.take() {
+                    self.stats = stats;
+                }
+                *count = instances.len() as u32;
+                let instance_data = instances
+                    .iter()
+                    .map(|instance| instance::InstanceRaw::new(instance, origin))
+                    .collect::<Vec<_>>();
+                queue.write_buffer(input_buffer, 0, bytemuck::cast_slice(&instance_data));
+                let uniform = CullingUniform {
+                    planes: frustum.planes.map(|plane| plane.to_array()),
+                    focal_length: frustum.focal_length,
+                    min_projected_radius: self.min_projected_radius,
+                    count: *count,
+                    capacity: self.capacity,
+                };
+                queue.write_buffer(uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+                // Instance counts are reset, the compute shader adds the visible instances
+                let draws = (0..Sphere::LOD_COUNT)
+                    .flat_map(|lod| {
+                        let indices = Sphere::lod_indices(lod);
+                        wgpu::util::DrawIndexedIndirectArgs {
+                            index_count: indices.len() as u32,
+                            instance_count: 0,
+                            first_index: indices.start,
+                            base_vertex: 0,
+                            first_instance: lod as u32 * self.capacity,
+                        }
+                        .as_bytes()
+                        .to_vec()
+                    })
+                    .collect::<Vec<_>>();
+                queue.write_buffer(indirect_buffer, 0, &draws);
+            }
+        }
+    }
+
+    /// Runs the culling compute shader, it has to be recorded before the render pass
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder) {
+        let CullingMethod::Gpu {
+            count,
+            indirect_buffer,
+            bind_group,
+            pipeline,
+            stats_readback,
+            ..
+        } = &self.method
+        else {
+            return;
+        };
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Culling Compute Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(count.div_ceil(Self::WORKGROUP_SIZE), 1, 1);
+        }
+        stats_readback.copy(encoder, indirect_buffer, *count);
+    }
+
+    /// Starts reading the statistics back from the GPU, it has to be called after the
+    /// commands recorded by [`Self::cull`] are submitted
+    pub fn read_stats(&self) {
+        if let CullingMethod::Gpu { stats_readback, .. } = &self.method {
+            stats_readback.map();
+        }
+    }
+}
+
+/// Copies of the indirect draw arguments, mapped asynchronously so that reading the
+/// statistics never stalls the frame. A new copy is only made once the last one is read.
+struct StatsReadback {
+    buffer: wgpu::Buffer,
+    state: Arc<Mutex<ReadbackState>>,
+}
+
+#[derive(Clone, Copy)]
+enum ReadbackState {
+    Idle,
+    /// Holds the number of instances the copied frame started with
+    Copied(u32),
+    Mapping,
+    Mapped(u32),
+}
+
+impl StatsReadback {
+    fn new(device: &wgpu::Device, size: wgpu::BufferAddress, label: &str) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{label} Culling Stats Buffer")),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            state: Arc::new(Mutex::new(ReadbackState::Idle)),
+        }
+    }
+
+    fn copy(&self, encoder: &mut wgpu::CommandEncoder, indirect_buffer: &wgpu::Buffer, count: u32) {
+        let mut state = self.state.lock().unwrap();
+        if let ReadbackState::Idle = *state {
+            encoder.copy_buffer_to_buffer(indirect_buffer, 0, &self.buffer, 0, self.buffer.size());
+            *state = ReadbackState::Copied(count);
+        }
+    }
+
+    fn map(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let ReadbackState::Copied(count) = *state {
+            *state = ReadbackState::Mapping;
+            let callback_state = self.state.clone();
+            self.buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    let mut state = callback_state.lock().unwrap();
+                    *state = match result {
+                        Ok(()) => ReadbackState::Mapped(count),
+                        Err(_) => ReadbackState::Idle,
+                    };
+                });
+        }
+    }
+
+    /// Statistics of the copied frame, once the buffer is mapped
+    fn take(&self) -> Option<CullingStats> {
+        let mut state = self.state.lock().unwrap();
+        let ReadbackState::Mapped(count) = *state else {
+            return None;
+        };
+        let visible = {
+            let data = self.buffer.slice(..).get_mapped_range();
+            data.chunks_exact(InstanceCulling::INDIRECT_ARGS_SIZE as usize)
+                .map(|args| u32::from_ne_bytes(args[4..8].try_into().unwrap()))
+                .sum::<u32>()
+        };
+        self.buffer.unmap();
+        *state = ReadbackState::Idle;
+        Some(CullingStats {
+            visible,
+            culled: count - visible,
+        })
+    }
+}
+
+/// Must match `CullingUniform` in `cull_instances.wgsl`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullingUniform {
+    planes: [[f32; 4]; 6],
+    focal_length: f32,
+    min_projected_radius: f32,
+    count: u32,
+    capacity: u32,
+}
+
+impl InstanceCulling {
+    /// Draws the visible instances, the material has to be set
+    pub fn draw(&self, pass: &mut wgpu::RenderPass, sphere: &Sphere) {
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        match &self.method {
+            CullingMethod::Cpu { batches } => sphere.draw_batches(pass, batches),
+            CullingMethod::Gpu {
+                indirect_buffer, ..
+            } => {
+                for lod in 0..Sphere::LOD_COUNT {
+                    sphere.draw_indirect(
+                        pass,
+                        indirect_buffer,
+                        lod as wgpu::BufferAddress * Self::INDIRECT_ARGS_SIZE,
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::f64::consts::{PI, TAU};
+
+use crate::clock::SimulationClock;
+
+/// Source of planet positions. Planets are identified by their index in the order used by
+/// [`crate::planets::Planets`] (Mercury to Neptune).
+pub trait Ephemeris {
+    fn name(&self) -> &'static str;
+
+    /// Heliocentric position in the ecliptic frame of J2000 (`z` points to the north
+    /// ecliptic pole), in astronomical units
+    fn position(&self, planet: usize, julian_date: f64) -> glam::DVec3;
+}
+
+/// Converts from the ecliptic frame, where `z` is up, to the scene frame, where `y` is up
+pub fn ecliptic_to_scene(position: glam::DVec3) -> glam::DVec3 {
+    glam::DVec3::new(position.x, position.z, -position.y)
+}
+
+/// Obliquity of the ecliptic at J2000, in degrees
+const OBLIQUITY: f64 = 23.439_281;
+
+/// Rotates from the equatorial frame of J2000 (`z` points to the north celestial pole) to
+/// the ecliptic frame, around the vernal equinox shared by both
+pub fn equatorial_to_ecliptic(position: glam::DVec3) -> glam::DVec3 {
+    let (sin_e, cos_e) = OBLIQUITY.to_radians().sin_cos();
+    glam::DVec3::new(
+        position.x,
+        position.y * cos_e + position.z * sin_e,
+        -position.y * sin_e + position.z * cos_e,
+    )
+}
+
+/// Inverse of [`equatorial_to_ecliptic`]
+pub fn ecliptic_to_equatorial(position: glam::DVec3) -> glam::DVec3 {
+    let (sin_e, cos_e) = OBLIQUITY.to_radians().sin_cos();
+    glam::DVec3::new(
+        position.x,
+        position.y * cos_e - position.z * sin_e,
+        position.y * sin_e + position.z * cos_e,
+    )
+}
+
+/// Rotates from the equatorial frame of J2000 to the galactic frame (`x` points to the
+/// galactic center, `z` to the north galactic pole)
+pub fn equatorial_to_galactic(position: glam::DVec3) -> glam::DVec3 {
+    const ROTATION: [[f64; 3]; 3] = [
+        [-0.054_875_560_4, -0.873_437_090_2, -0.483_835_015_5],
+        [0.494_109_427_9, -0.444_829_630_0, 0.746_982_244_5],
+        [-0.867_666_149_0, -0.198_076_373_4, 0.455_983_776_2],
+    ];
+    let [x, y, z] = ROTATION.map(|row| glam::DVec3::from(row).dot(position));
+    glam::DVec3::new(x, y, z)
+}
+
+pub struct CircularOrbit {
+    pub radius_au: f64,
+    /// Angle at J2000, in radians
+    pub initial_angle: f64,
+    /// In radians per day
+    pub angular_speed: f64,
+}
+
+/// Idealised, coplanar circular orbits. Used by the artistic layout, where the planets
+/// move at speeds chosen to look good rather than at their real speeds.
+pub struct CircularOrbits {
+    orbits: Vec<CircularOrbit>,
+}
+
+impl CircularOrbits {
+    pub fn new(orbits: Vec<CircularOrbit>) -> Self {
+        Self { orbits }
+    }
+}
+
+impl Ephemeris for CircularOrbits {
+    fn name(&self) -> &'static str {
+        "circular orbits"
+    }
+
+    fn position(&self, planet: usize, julian_date: f64) -> glam::DVec3 {
+        let orbit = &self.orbits[planet];
+        let days = julian_date - SimulationClock::J2000;
+        let angle = orbit.initial_angle + orbit.angular_speed * days;
+        glam::DVec3::new(angle.cos(), angle.sin(), 0.0) * orbit.radius_au
+    }
+}
+
+/// Keplerian elements with their rates of change, in AU, degrees and their rates per
+/// Julian century
+struct KeplerElements {
+    semi_major_axis: [f64; 2],
+    eccentricity: [f64; 2],
+    inclination: [f64; 2],
+    mean_longitude: [f64; 2],
+    longitude_of_perihelion: [f64; 2],
+    longitude_of_ascending_node: [f64; 2],
+}
+
+/// Elliptical orbits with slowly changing elements, accurate to a few arcminutes between
+/// 1800 and 2050. Elements come from E. M. Standish, "Keplerian Elements for Approximate
+/// Positions of the Major Planets" (JPL), table 1.
+pub struct KeplerOrbits;
+
+impl KeplerOrbits {
+    const ELEMENTS: [KeplerElements; 8] = [
+        // Mercury
+        KeplerElements {
+            semi_major_axis: [0.38709927, 0.00000037],
+            eccentricity: [0.20563593, 0.00001906],
+            inclination: [7.00497902, -0.00594749],
+            mean_longitude: [252.25032350, 149472.67411175],
+            longitude_of_perihelion: [77.45779628, 0.16047689],
+            longitude_of_ascending_node: [48.33076593, -0.12534081],
+        },
+        // Venus
+        KeplerElements {
+            semi_major_axis: [0.72333566, 0.00000390],
+            eccentricity: [0.00677672, -0.00004107],
+            inclination: [3.39467605, -0.00078890],
+            mean_longitude: [181.97909950, 58517.81538729],
+            longitude_of_perihelion: [131.60246718, 0.00268329],
+            longitude_of_ascending_node: [76.67984255, -0.27769418],
+        },
+        // Earth-Moon barycenter
+        KeplerElements {
+            semi_major_axis: [1.00000261, 0.00000562],
+            eccentricity: [0.01671123, -0.00004392],
+            inclination: [-0.00001531, -0.01294668],
+            mean_longitude: [100.46457166, 35999.37244981],
+            longitude_of_perihelion: [102.93768193, 0.32327364],
+            longitude_of_ascending_node: [0.0, 0.0],
+        },
+        // Mars
+        KeplerElements {
+            semi_major_axis: [1.52371034, 0.00001847],
+            eccentricity: [0.09339410, 0.00007882],
+            inclination: [1.84969142, -0.00813131],
+            mean_longitude: [-4.55343205, 19140.30268499],
+            longitude_of_perihelion: [-23.94362959, 0.44441088],
+            longitude_of_ascending_node: [49.55953891, -0.29257343],
+        },
+        // Jupiter
+        KeplerElements {
+            semi_major_axis: [5.20288700, -0.00011607],
+            eccentricity: [0.04838624, -0.00013253],
+            inclination: [1.30439695, -0.00183714],
+            mean_longitude: [34.39644051, 3034.74612775],
+            longitude_of_perihelion: [14.72847983, 0.21252668],
+            longitude_of_ascending_node: [100.47390909, 0.20469106],
+        },
+        // Saturn
+        KeplerElements {
+            semi_major_axis: [9.53667594, -0.00125060],
+            eccentricity: [0.05386179, -0.00050991],
+            inclination: [2.48599187, 0.00193609],
+            mean_longitude: [49.95424423, 1222.49362201],
+            longitude_of_perihelion: [92.59887831, -0.41897216],
+            longitude_of_ascending_node: [113.66242448, -0.28867794],
+        },
+        // Uranus
+        KeplerElements {
+            semi_major_axis: [19.18916464, -0.00196176],
+            eccentricity: [0.04725744, -0.00004397],
+            inclination: [0.77263783, -0.00242939],
+            mean_longitude: [313.23810451, 428.48202785],
+            longitude_of_perihelion: [170.95427630, 0.40805281],
+            longitude_of_ascending_node: [74.01692503, 0.04240589],
+        },
+        // Neptune
+        KeplerElements {
+            semi_major_axis: [30.06992276, 0.00026291],
+            eccentricity: [0.00859048, 0.00005105],
+            inclination: [1.77004347, 0.00035372],
+            mean_longitude: [-55.12002969, 218.45945325],
+            longitude_of_perihelion: [44.96476227, -0.32241464],
+            longitude_of_ascending_node: [131.78422574, -0.00508664],
+        },
+    ];
+}
+
+impl Ephemeris for KeplerOrbits {
+    fn name(&self) -> &'static str {
+        "Kepler orbits"
+    }
+
+    fn position(&self, planet: usize, julian_date: f64) -> glam::DVec3 {
+        let elements = &Self::ELEMENTS[planet];
+        let centuries = (julian_date - SimulationClock::J2000) / 36_525.0;
+        let at = |[value, rate]: [f64; 2]| value + rate * centuries;
+
+        let a = at(elements.semi_major_axis);
+        let e = at(elements.eccentricity);
+        let inclination = at(elements.inclination).to_radians();
+        let mean_longitude = at(elements.mean_longitude);
+        let perihelion = at(elements.longitude_of_perihelion);
+        let node = at(elements.longitude_of_ascending_node);
+
+        let argument_of_perihelion = (perihelion - node).to_radians();
+        let mean_anomaly = (mean_longitude - perihelion).to_radians();
+        position_on_orbit(
+            a,
+            e,
+            inclination,
+            node.to_radians(),
+            argument_of_perihelion,
+            mean_anomaly,
+        )
+    }
+}
+
+/// Solves Kepler's equation `M = E - e sin E` for the eccentric anomaly with Newton's
+/// method. Starting from `E = π` for very eccentric orbits keeps it converging for comets.
+fn eccentric_anomaly(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let mut anomaly = if eccentricity > 0.8 {
+        PI
+    } else {
+        mean_anomaly + eccentricity * mean_anomaly.sin()
+    };
+    for _ in 0..50 {
+        let delta = (anomaly - eccentricity * anomaly.sin() - mean_anomaly)
+            / (1.0 - eccentricity * anomaly.cos());
+        anomaly -= delta;
+        if delta.abs() < 1e-12 {
+            break;
+        }
+    }
+    anomaly
+}
+
+/// Heliocentric position in the ecliptic frame of J2000 of a body on an elliptical orbit,
+/// angles are in radians
+fn position_on_orbit(
+    semi_major_axis: f64,
+    eccentricity: f64,
+    inclination: f64,
+    ascending_node: f64,
+    argument_of_perihelion: f64,
+    mean_anomaly: f64,
+) -> glam::DVec3 {
+    let (a, e) = (semi_major_axis, eccentricity);
+    let eccentric_anomaly = eccentric_anomaly(mean_anomaly.rem_euclid(TAU), e);
+
+    // Position in the orbital plane, with `x` pointing to the perihelion
+    let x = a * (eccentric_anomaly.cos() - e);
+    let y = a * (1.0 - e * e).sqrt() * eccentric_anomaly.sin();
+
+    let rotation = glam::DQuat::from_rotation_z(ascending_node)
+        * glam::DQuat::from_rotation_x(inclination)
+        * glam::DQuat::from_rotation_z(argument_of_perihelion);
+    rotation * glam::DVec3::new(x, y, 0.0)
+}
+
+/// Fixed osculating elements of a body which is not covered by the ephemerides (dwarf
+/// planets, comets), in AU and degrees. Perturbations are ignored, so positions slowly
+/// drift away from the real ones far from the perihelion passage.
+pub struct OrbitalElements {
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    pub inclination: f64,
+    pub longitude_of_ascending_node: f64,
+    pub argument_of_perihelion: f64,
+    /// Julian date of a passage through the perihelion
+    pub perihelion_time: f64,
+}
+
+impl OrbitalElements {
+    /// Gaussian gravitational constant, the mean motion at 1 AU in radians per day
+    const GAUSSIAN_CONSTANT: f64 = 0.01720209895;
+
+    /// Heliocentric position in the ecliptic frame of J2000, in AU
+    pub fn position(&self, julian_date: f64) -> glam::DVec3 {
+        // Kepler's third law, the mass of the body is negligible
+        let mean_motion = Self::GAUSSIAN_CONSTANT / self.semi_major_axis.powf(1.5);
+        position_on_orbit(
+            self.semi_major_axis,
+            self.eccentricity,
+            self.inclination.to_radians(),
+            self.longitude_of_ascending_node.to_radians(),
+            self.argument_of_perihelion.to_radians(),
+            mean_motion * (julian_date - self.perihelion_time),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Angle between two heliocentric positions, in arcseconds
+    fn separation_arcsec(a: glam::DVec3, b: glam::DVec3) -> f64 {
+        a.angle_between(b).to_degrees() * 3600.0
+    }
+
+    #[test]
+    fn kepler_orbits_match_jpl_at_j2000() {
+        // Heliocentric ecliptic positions at JD 2451545.0 from the JPL DE ephemeris (AU),
+        // with the maximum errors of table 1 of Standish (1800-2050, arcseconds) plus a
+        // margin. The table gives the Earth-Moon barycenter, a few arcseconds from Earth.
+        let references = [
+            (0, [-0.130_093_6, -0.447_287_6, -0.024_596_7], 20.0),
+            (2, [-0.177_135_5, 0.967_241_6, -0.000_003_9], 30.0),
+            (3, [1.390_715_9, -0.013_416_0, -0.034_467_1], 60.0),
+            (4, [4.001_174_0, 2.938_581_0, -0.101_783_8], 600.0),
+            (5, [6.406_460_6, 6.570_000_0, -0.369_040_0], 900.0),
+        ];
+        for (planet, reference, tolerance) in references {
+            let reference = glam::DVec3::from(reference);
+            let position = KeplerOrbits.position(planet, SimulationClock::J2000);
+            let separation = separation_arcsec(position, reference);
+            assert!(
+                separation < tolerance,
+                "Planet {planet} is {separation:.1}\" away from the reference"
+            );
+            // Perturbations by Jupiter move Saturn by about 0.1% of its distance
+            let distance_error = (position.length() - reference.length()) / reference.length();
+            assert!(
+                distance_error.abs() < 2e-3,
+                "Planet {planet} distance is off by {distance_error:e}"
+            );
+        }
+    }
+
+    #[test]
+    fn kepler_orbits_return_after_a_sidereal_year() {
+        let start = KeplerOrbits.position(2, SimulationClock::J2000);
+        let end = KeplerOrbits.position(2, SimulationClock::J2000 + 365.256_363);
+        assert!(separation_arcsec(start, end) < 5.0);
+    }
+
+    #[test]
+    fn equatorial_to_ecliptic_round_trips() {
+        let position = glam::DVec3::new(0.3, -1.2, 0.7);
+        let round_trip = ecliptic_to_equatorial(equatorial_to_ecliptic(position));
+        assert!(round_trip.distance(position) < 1e-12);
+    }
+}
@@ -1,40 +1,22 @@
 use anyhow::Result;
-use image::{GenericImageView, ImageReader};
+use image::{ImageReader, imageops::FilterType};
 use std::io::Cursor;
-use wgpu::Operations;
+use wgpu::{Operations, util::DeviceExt};
 
 use crate::{pipeline, texture};
 
-/// Owns the render texture and controls tonemapping
+/// Tonemaps the HDR color texture of the render graph
 pub struct HdrPipeline {
     pipeline: wgpu::RenderPipeline,
-    bind_group: wgpu::BindGroup,
-    texture: texture::Texture,
-    width: u32,
-    height: u32,
-    format: wgpu::TextureFormat,
     layout: wgpu::BindGroupLayout,
 }
 
 impl HdrPipeline {
-    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
-        let width = config.width;
-        let height = config.height;
-
-        // We could use `Rgba32Float`, but that requires some extra
-        // features to be enabled for rendering.
-        let format = wgpu::TextureFormat::Rgba16Float;
-
-        let texture = texture::Texture::create_2d_texture(
-            device,
-            width,
-            height,
-            format,
-            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
-            wgpu::FilterMode::Nearest,
-            Some("Hdr::texture"),
-        );
+    /// We could use `Rgba32Float`, but that requires some extra features to be enabled for
+    /// rendering.
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
         let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Hdr::layout"),
             entries: &[
@@ -57,21 +39,6 @@ impl HdrPipeline {
                 },
             ],
         });
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Hdr::bind_group"),
-            layout: &layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-            ],
-        });
-
         let shader = wgpu::include_wgsl!("../shaders/hdr.wgsl");
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
@@ -91,59 +58,52 @@ impl HdrPipeline {
             Some("render_pipeline_hdr"),
         );
 
-        Self {
-            pipeline,
-            bind_group,
-            layout,
-            texture,
-            width,
-            height,
-            format,
-        }
+        Self { pipeline, layout }
     }
 
-    /// Resize the HDR texture
-    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
-        self.texture = texture::Texture::create_2d_texture(
-            device,
-            width,
-            height,
-            wgpu::TextureFormat::Rgba16Float,
-            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
-            wgpu::FilterMode::Nearest,
-            Some("Hdr::texture"),
-        );
-        self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Hdr::bind_group"),
-            layout: &self.layout,
+            layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&self.texture.view),
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
                 },
             ],
-        });
-        self.width = width;
-        self.height = height;
+        })
     }
 
-    /// Exposes the HDR texture
-    pub fn view(&self) -> &wgpu::TextureView {
-        &self.texture.view
+    /// Creates the bind group of [`Self::process`] for an HDR texture, the render graph
+    /// calls it again whenever it reallocates the texture
+    pub fn bind_group_factory(
+        &self,
+    ) -> impl Fn(&wgpu::Device, &texture::Texture) -> wgpu::BindGroup + 'static {
+        let layout = self.layout.clone();
+        move |device, texture| Self::create_bind_group(device, &layout, texture)
     }
 
     /// The format of the HDR texture
     pub fn format(&self) -> wgpu::TextureFormat {
-        self.format
+        Self::FORMAT
     }
 
-    /// This renders the internal HDR texture to the [`wgpu::TextureView`]
-    /// supplied as parameter.
-    pub fn process(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+    /// This renders the HDR texture of `bind_group` (see [`Self::bind_group_factory`]) to
+    /// the [`wgpu::TextureView`] supplied as parameter.
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+    ) {
         let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Hdr::process"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
@@ -159,19 +119,53 @@ impl HdrPipeline {
             timestamp_writes: None,
         });
         pass.set_pipeline(&self.pipeline);
-        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_bind_group(0, bind_group, &[]);
         pass.draw(0..3, 0..1);
     }
 }
 
+/// Maps for the lighting of the bodies by their surroundings, derived from the skybox
+pub struct EnvironmentMaps {
+    irradiance: texture::CubeTexture,
+    prefiltered: texture::CubeTexture,
+}
+
+impl EnvironmentMaps {
+    /// Diffuse lighting: the radiance reflected by a white surface facing each direction
+    pub fn irradiance(&self) -> &texture::CubeTexture {
+        &self.irradiance
+    }
+
+    /// Specular lighting: the environment blurred more and more for rougher surfaces, the
+    /// roughness goes from 0.0 at the first mip level to 1.0 at the last one
+    pub fn prefiltered(&self) -> &texture::CubeTexture {
+        &self.prefiltered
+    }
+}
+
 pub struct HdrLoader {
     texture_format: wgpu::TextureFormat,
     equirect_layout: wgpu::BindGroupLayout,
     equirect_to_cubemap: wgpu::ComputePipeline,
+    downsample_layout: wgpu::BindGroupLayout,
+    downsample: wgpu::ComputePipeline,
+    environment_layout: wgpu::BindGroupLayout,
+    irradiance: wgpu::ComputePipeline,
+    prefiltered: wgpu::ComputePipeline,
+    environment_sampler: wgpu::Sampler,
 }
 
 impl HdrLoader {
     const CUBEMAP_LAYERS: u32 = 6;
+    /// Environment maps are filtered, they have to be in a filterable format
+    const ENVIRONMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    /// Low resolution copy of the skybox the environment maps are computed from
+    const RADIANCE_SIZE: u32 = 64;
+    const IRRADIANCE_SIZE: u32 = 32;
+    const PREFILTERED_SIZE: u32 = 64;
+    /// From 64x64 for smooth surfaces down to 4x4 for the roughest ones, must match
+    /// `PREFILTERED_MIP_LEVELS` in `planet.wgsl`
+    const PREFILTERED_MIP_LEVELS: u32 = 5;
 
     pub fn new(device: &wgpu::Device) -> Self {
         let module =
@@ -219,31 +213,165 @@ impl HdrLoader {
                 cache: None,
             });
 
+        let downsample_module =
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/downsample_cubemap.wgsl"));
+        let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("HdrLoader::downsample_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: Self::ENVIRONMENT_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let downsample_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&downsample_layout],
+                push_constant_ranges: &[],
+            });
+        let downsample = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("downsample_cubemap"),
+            layout: Some(&downsample_pipeline_layout),
+            module: &downsample_module,
+            entry_point: Some("compute_downsample"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let environment_module =
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/environment.wgsl"));
+        let environment_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("HdrLoader::environment_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: Self::ENVIRONMENT_FORMAT,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let environment_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&environment_layout],
+                push_constant_ranges: &[],
+            });
+        let create_environment_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&environment_pipeline_layout),
+                module: &environment_module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let irradiance = create_environment_pipeline("compute_irradiance");
+        let prefiltered = create_environment_pipeline("compute_prefiltered");
+
+        let environment_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("HdrLoader::environment_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
         Self {
             equirect_to_cubemap,
             texture_format,
             equirect_layout,
+            downsample_layout,
+            downsample,
+            environment_layout,
+            irradiance,
+            prefiltered,
+            environment_sampler,
         }
     }
 
-    pub fn equirectangular_bytes(
+    /// Decodes a panorama, on any thread, for [`Self::equirectangular`]
+    pub fn decode_equirectangular(data: &[u8]) -> Result<image::Rgba32FImage> {
+        let img = ImageReader::new(Cursor::new(data))
+            .with_guessed_format()?
+            .decode()?;
+        Ok(Self::opaque(img))
+    }
+
+    /// The alpha channel of the images is ignored
+    fn opaque(img: image::DynamicImage) -> image::Rgba32FImage {
+        let mut rgba32f = img.into_rgba32f();
+        rgba32f.pixels_mut().for_each(|pixel| pixel.0[3] = 1.0);
+        rgba32f
+    }
+
+    pub fn equirectangular(
         &self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        data: &[u8],
+        image: &image::Rgba32FImage,
         dst_size: u32,
         label: Option<&str>,
-    ) -> Result<texture::CubeTexture> {
-        let img = ImageReader::new(Cursor::new(data))
-            .with_guessed_format()?
-            .decode()?;
-        let (width, height) = img.dimensions();
-        let rgb32f = img.into_rgb32f();
-        let pixels: Vec<[f32; 4]> = rgb32f
-            .pixels()
-            .map(|pixel| [pixel.0[0], pixel.0[1], pixel.0[2], 1.0])
-            .collect();
-
+    ) -> texture::CubeTexture {
+        let (width, height) = image.dimensions();
         let src = texture::Texture::create_2d_texture(
             device,
             width,
@@ -261,7 +389,7 @@ impl HdrLoader {
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            bytemuck::cast_slice(&pixels),
+            bytemuck::cast_slice(image.as_raw()),
             wgpu::TexelCopyBufferLayout {
                 offset: 0,
                 bytes_per_row: Some(src.size.width * std::mem::size_of::<[f32; 4]>() as u32),
@@ -324,6 +452,227 @@ impl HdrLoader {
 
         queue.submit([encoder.finish()]);
 
-        Ok(dst)
+        dst
+    }
+
+    /// Computes the environment lighting of `skybox`: it is first reduced to a small cubemap,
+    /// which is then integrated over the hemisphere of every direction
+    pub fn environment_maps(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        skybox: &texture::CubeTexture,
+    ) -> EnvironmentMaps {
+        let usage = wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING;
+        let radiance = texture::CubeTexture::create_2d(
+            device,
+            Self::RADIANCE_SIZE,
+            Self::RADIANCE_SIZE,
+            Self::ENVIRONMENT_FORMAT,
+            1,
+            usage,
+            wgpu::FilterMode::Linear,
+            Some("Environment Radiance"),
+        );
+        let irradiance = texture::CubeTexture::create_2d(
+            device,
+            Self::IRRADIANCE_SIZE,
+            Self::IRRADIANCE_SIZE,
+            Self::ENVIRONMENT_FORMAT,
+            1,
+            usage,
+            wgpu::FilterMode::Linear,
+            Some("Environment Irradiance"),
+        );
+        let prefiltered = texture::CubeTexture::create_2d(
+            device,
+            Self::PREFILTERED_SIZE,
+            Self::PREFILTERED_SIZE,
+            Self::ENVIRONMENT_FORMAT,
+            Self::PREFILTERED_MIP_LEVELS,
+            usage,
+            wgpu::FilterMode::Linear,
+            Some("Environment Prefiltered"),
+        );
+
+        // Storage textures can only be bound one mip level at a time, as arrays of layers
+        let layers_view = |texture: &texture::CubeTexture, mip_level| {
+            texture.texture().create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                base_mip_level: mip_level,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        };
+        let radiance_layers = layers_view(&radiance, 0);
+
+        let downsample_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("downsample_bind_group"),
+            layout: &self.downsample_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(skybox.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(skybox.sampler()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&radiance_layers),
+                },
+            ],
+        });
+
+        // Every pass writes one map (or mip level) from the radiance, with its own roughness
+        let mut passes = vec![(
+            &self.irradiance,
+            layers_view(&irradiance, 0),
+            0.0,
+            Self::IRRADIANCE_SIZE,
+        )];
+        for mip_level in 0..Self::PREFILTERED_MIP_LEVELS {
+            let roughness = mip_level as f32 / (Self::PREFILTERED_MIP_LEVELS - 1) as f32;
+            passes.push((
+                &self.prefiltered,
+                layers_view(&prefiltered, mip_level),
+                roughness,
+                Self::PREFILTERED_SIZE >> mip_level,
+            ));
+        }
+        let bind_groups: Vec<(&wgpu::ComputePipeline, wgpu::BindGroup, u32)> = passes
+            .iter()
+            .map(|(pipeline, view, roughness, size)| {
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Prefilter Uniform Buffer"),
+                    contents: bytemuck::cast_slice(&[*roughness, 0.0, 0.0, 0.0]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("environment_bind_group"),
+                    layout: &self.environment_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(radiance.view()),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.environment_sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: buffer.as_entire_binding(),
+                        },
+                    ],
+                });
+                (*pipeline, bind_group, *size)
+            })
+            .collect();
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Environment Maps"),
+            timestamp_writes: None,
+        });
+        let num_workgroups = Self::RADIANCE_SIZE.div_ceil(8);
+        pass.set_pipeline(&self.downsample);
+        pass.set_bind_group(0, &downsample_bind_group, &[]);
+        pass.dispatch_workgroups(num_workgroups, num_workgroups, Self::CUBEMAP_LAYERS);
+        drop(pass);
+
+        // The radiance has to be complete before it is sampled
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Environment Maps"),
+            timestamp_writes: None,
+        });
+        for (pipeline, bind_group, size) in &bind_groups {
+            let num_workgroups = size.div_ceil(8);
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(num_workgroups, num_workgroups, Self::CUBEMAP_LAYERS);
+        }
+        drop(pass);
+
+        queue.submit([encoder.finish()]);
+
+        EnvironmentMaps {
+            irradiance,
+            prefiltered,
+        }
+    }
+
+    /// Decodes six images in the order of the cubemap layers (+X, -X, +Y, -Y, +Z, -Z), on any
+    /// thread, for [`Self::cube_faces`]. They are resized to `dst_size` if needed.
+    pub fn decode_cube_faces(faces: [&[u8]; 6], dst_size: u32) -> Result<[image::Rgba32FImage; 6]> {
+        let faces = faces
+            .into_iter()
+            .map(|data| {
+                let img = Self::opaque(
+                    ImageReader::new(Cursor::new(data))
+                        .with_guessed_format()?
+                        .decode()?,
+                );
+                Ok(if img.dimensions() == (dst_size, dst_size) {
+                    img
+                } else {
+                    image::imageops::resize(&img, dst_size, dst_size, FilterType::Triangle)
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(faces.try_into().unwrap())
+    }
+
+    pub fn cube_faces(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::Rgba32FImage; 6],
+        label: Option<&str>,
+    ) -> texture::CubeTexture {
+        let dst_size = faces[0].width();
+        let dst = texture::CubeTexture::create_2d(
+            device,
+            dst_size,
+            dst_size,
+            self.texture_format,
+            1,
+            wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::FilterMode::Nearest,
+            label,
+        );
+
+        for (layer, img) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: dst.texture(),
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(img.as_raw()),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(dst_size * std::mem::size_of::<[f32; 4]>() as u32),
+                    rows_per_image: Some(dst_size),
+                },
+                wgpu::Extent3d {
+                    width: dst_size,
+                    height: dst_size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        dst
     }
 }
@@ -2,21 +2,73 @@ use std::mem;
 
 #[derive(Default)]
 pub struct Instance {
-    pub position: glam::Vec3,
+    /// Stored in double precision, so that bodies far away from the origin don't jitter.
+    /// Rendering is done relative to the camera, see [`InstanceRaw::new`].
+    pub position: glam::DVec3,
     pub rotation: glam::Quat,
     texture_index: u32,
     scale: f32,
+    night_texture_index: Option<u32>,
+    twilight_band: f32,
+    normal_map_index: Option<u32>,
+    specular_map_index: Option<u32>,
 }
 
 impl Instance {
-    pub fn new(position: glam::Vec3, rotation: glam::Quat, texture_index: u32, scale: f32) -> Self {
+    pub fn new(
+        position: glam::DVec3,
+        rotation: glam::Quat,
+        texture_index: u32,
+        scale: f32,
+    ) -> Self {
         Self {
             position,
             rotation,
             texture_index,
             scale,
+            night_texture_index: None,
+            twilight_band: 0.0,
+            normal_map_index: None,
+            specular_map_index: None,
         }
     }
+
+    /// Declares an emissive texture shown on the side facing away from the sun. Day and
+    /// night textures are blended over `twilight_band`, measured as the cosine of the
+    /// angle between the surface normal and the direction to the sun.
+    pub fn with_night_side(mut self, night_texture_index: Option<u32>, twilight_band: f32) -> Self {
+        self.night_texture_index = night_texture_index;
+        self.twilight_band = twilight_band;
+        self
+    }
+
+    /// Declares layers of the surface maps array used for lighting. The specular map holds
+    /// the specular intensity in the red channel and the roughness in the green channel.
+    pub fn with_surface_maps(
+        mut self,
+        normal_map_index: Option<u32>,
+        specular_map_index: Option<u32>,
+    ) -> Self {
+        self.normal_map_index = normal_map_index;
+        self.specular_map_index = specular_map_index;
+        self
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    pub fn texture_index(&self) -> u32 {
+        self.texture_index
+    }
+
+    pub fn normal_map_index(&self) -> Option<u32> {
+        self.normal_map_index
+    }
 }
 
 #[repr(C)]
@@ -25,11 +77,17 @@ pub struct InstanceRaw {
     model_matrix: [[f32; 4]; 4],
     normal_matrix: [[f32; 3]; 3],
     texture_index: u32,
+    night_texture_index: u32,
+    twilight_band: f32,
+    surface_map_indices: [u32; 2],
     // for alignment
-    _padding: [f32; 6],
+    _padding: [f32; 2],
 }
 
 impl InstanceRaw {
+    /// Marks the lack of a texture in the texture index fields
+    pub const NO_TEXTURE: u32 = u32::MAX;
+
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
@@ -81,22 +139,45 @@ impl InstanceRaw {
                     shader_location: 12,
                     format: wgpu::VertexFormat::Uint32,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 26]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 27]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                // normal and specular map indices
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 28]>() as wgpu::BufferAddress,
+                    shader_location: 15,
+                    format: wgpu::VertexFormat::Uint32x2,
+                },
             ],
         }
     }
-}
 
-impl From<&Instance> for InstanceRaw {
-    fn from(value: &Instance) -> Self {
+    /// The model matrix places the instance relative to `origin` (the camera position). The
+    /// subtraction is done in double precision, so only the small, camera-relative offset is
+    /// converted to `f32`.
+    pub fn new(value: &Instance, origin: glam::DVec3) -> Self {
         let scale_matrix = glam::Mat4::from_scale(glam::Vec3::splat(value.scale));
-        let model_matrix = glam::Mat4::from_translation(value.position)
+        let model_matrix = glam::Mat4::from_translation((value.position - origin).as_vec3())
             * glam::Mat4::from_quat(value.rotation)
             * scale_matrix;
         InstanceRaw {
             model_matrix: model_matrix.to_cols_array_2d(),
             normal_matrix: (glam::Mat3::from_quat(value.rotation)).to_cols_array_2d(),
             texture_index: value.texture_index,
-            _padding: [0.0; 6],
+            night_texture_index: value.night_texture_index.unwrap_or(InstanceRaw::NO_TEXTURE),
+            twilight_band: value.twilight_band,
+            surface_map_indices: [
+                value.normal_map_index.unwrap_or(InstanceRaw::NO_TEXTURE),
+                value.specular_map_index.unwrap_or(InstanceRaw::NO_TEXTURE),
+            ],
+            _padding: [0.0; 2],
         }
     }
 }
@@ -0,0 +1,322 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow, bail};
+
+/// Block compressed formats the planet textures can be stored in, in order of preference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressedFormat {
+    Bc7,
+    Astc4x4,
+    Etc2,
+}
+
+impl CompressedFormat {
+    pub const ALL: [Self; 3] = [Self::Bc7, Self::Astc4x4, Self::Etc2];
+
+    /// Formats the device can sample from, in order of preference
+    pub fn supported(device: &wgpu::Device) -> Vec<Self> {
+        Self::ALL
+            .into_iter()
+            .filter(|format| device.features().contains(format.feature()))
+            .collect()
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.name() == name.to_ascii_lowercase())
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bc7 => "bc7",
+            Self::Astc4x4 => "astc",
+            Self::Etc2 => "etc2",
+        }
+    }
+
+    /// Path of the compressed version of an image, e.g. `mars.bc7.ktx2` for `mars.jpg`
+    pub fn path_for(self, image: &Path) -> std::path::PathBuf {
+        image.with_extension(format!("{}.ktx2", self.name()))
+    }
+
+    fn feature(self) -> wgpu::Features {
+        match self {
+            Self::Bc7 => wgpu::Features::TEXTURE_COMPRESSION_BC,
+            Self::Astc4x4 => wgpu::Features::TEXTURE_COMPRESSION_ASTC,
+            Self::Etc2 => wgpu::Features::TEXTURE_COMPRESSION_ETC2,
+        }
+    }
+
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            Self::Bc7 => wgpu::TextureFormat::Bc7RgbaUnormSrgb,
+            Self::Astc4x4 => wgpu::TextureFormat::Astc {
+                block: wgpu::AstcBlock::B4x4,
+                channel: wgpu::AstcChannel::UnormSrgb,
+            },
+            Self::Etc2 => wgpu::TextureFormat::Etc2Rgb8UnormSrgb,
+        }
+    }
+
+    /// `VkFormat` of the sRGB variant, which is what the files store
+    fn vk_format(self) -> u32 {
+        match self {
+            Self::Bc7 => 146,
+            Self::Astc4x4 => 158,
+            Self::Etc2 => 148,
+        }
+    }
+
+    /// Color model of the data format descriptor
+    fn color_model(self) -> u8 {
+        match self {
+            Self::Bc7 => 134,
+            Self::Etc2 => 161,
+            Self::Astc4x4 => 162,
+        }
+    }
+
+    /// Channel of the single sample of the data format descriptor
+    fn sample_channel(self) -> u8 {
+        match self {
+            Self::Bc7 | Self::Astc4x4 => 0,
+            Self::Etc2 => 2,
+        }
+    }
+
+    pub fn block_size(self) -> usize {
+        match self {
+            Self::Bc7 | Self::Astc4x4 => 16,
+            Self::Etc2 => 8,
+        }
+    }
+}
+
+/// Cubemap with mipmaps in a KTX2 container, without supercompression. Only the subset of the
+/// format used for the planet textures is supported.
+pub struct Ktx2Cubemap {
+    pub format: CompressedFormat,
+    pub size: u32,
+    /// From the largest level, each with the six faces one after another
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl Ktx2Cubemap {
+    const IDENTIFIER: [u8; 12] = [
+        0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+    ];
+    const HEADER_SIZE: usize = 12 + 9 * 4 + 4 * 4 + 2 * 8;
+    const LEVEL_INDEX_ENTRY_SIZE: usize = 3 * 8;
+    const FACES: u32 = 6;
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::from_bytes(&bytes).with_context(|| format!("Invalid KTX2 file {}", path.display()))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < Self::HEADER_SIZE || bytes[..12] != Self::IDENTIFIER {
+            bail!("Not a KTX2 file");
+        }
+        let field = |offset: usize, size: usize| {
+            bytes
+                .get(offset..offset + size)
+                .with_context(|| format!("File is truncated at byte {offset}"))
+        };
+        let u32_at = |offset: usize| -> Result<u32> {
+            Ok(u32::from_le_bytes(field(offset, 4)?.try_into().unwrap()))
+        };
+        let u64_at = |offset: usize| -> Result<u64> {
+            Ok(u64::from_le_bytes(field(offset, 8)?.try_into().unwrap()))
+        };
+
+        let vk_format = u32_at(12)?;
+        let format = CompressedFormat::ALL
+            .into_iter()
+            .find(|format| format.vk_format() == vk_format)
+            .ok_or_else(|| anyhow!("Unsupported VkFormat {vk_format}"))?;
+        let mut header = [0; 7];
+        for (i, value) in header.iter_mut().enumerate() {
+            *value = u32_at(20 + i * 4)?;
+        }
+        let [
+            width,
+            height,
+            depth,
+            layers,
+            faces,
+            level_count,
+            supercompression,
+        ] = header;
+        if width != height || depth != 0 || layers != 0 || faces != Self::FACES {
+            bail!("Not a cubemap");
+        }
+        if supercompression != 0 {
+            bail!("Supercompression scheme {supercompression} is not supported");
+        }
+        // Sizes are 32-bit, so there can't be more levels than bits
+        if level_count > u32::BITS {
+            bail!("Too many levels ({level_count})");
+        }
+
+        let levels = (0..level_count.max(1))
+            .map(|level| {
+                let entry = Self::HEADER_SIZE + level as usize * Self::LEVEL_INDEX_ENTRY_SIZE;
+                let offset = usize::try_from(u64_at(entry)?)?;
+                let length = usize::try_from(u64_at(entry + 8)?)?;
+                let size = width.checked_shr(level).unwrap_or(0).max(1);
+                if length != Self::level_size(format, size) {
+                    bail!("Unexpected size of level {level}");
+                }
+                offset
+                    .checked_add(length)
+                    .and_then(|end| bytes.get(offset..end))
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| anyhow!("Level {level} is out of the file"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            format,
+            size: width,
+            levels,
+        })
+    }
+
+    /// Bytes of the six faces of a level whose faces have the given size
+    pub fn level_size(format: CompressedFormat, size: u32) -> usize {
+        let blocks = size.div_ceil(4) as usize;
+        blocks * blocks * format.block_size() * Self::FACES as usize
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let dfd = self.data_format_descriptor();
+        let level_index_size = self.levels.len() * Self::LEVEL_INDEX_ENTRY_SIZE;
+        let dfd_offset = Self::HEADER_SIZE + level_index_size;
+
+        let mut bytes = Vec::new();
+        bytes.extend(Self::IDENTIFIER);
+        for value in [
+            self.format.vk_format(),
+            // Type size, 1 for block compressed formats
+            1,
+            self.size,
+            self.size,
+            0,
+            0,
+            Self::FACES,
+            self.levels.len() as u32,
+            0,
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+        // The key/value data and the supercompression global data are empty
+        for value in [dfd_offset as u32, dfd.len() as u32, 0, 0] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend([0; 16]);
+
+        // Levels are stored from the smallest one, so a file can be streamed
+        let mut offsets = vec![0; self.levels.len()];
+        let mut data = Vec::new();
+        let mut end = dfd_offset + dfd.len();
+        for (level, level_data) in self.levels.iter().enumerate().rev() {
+            let padding = end.next_multiple_of(self.format.block_size()) - end;
+            data.extend(std::iter::repeat_n(0, padding));
+            offsets[level] = end + padding;
+            data.extend(level_data);
+            end = offsets[level] + level_data.len();
+        }
+        for (offset, level_data) in offsets.iter().zip(&self.levels) {
+            let length = level_data.len() as u64;
+            for value in [*offset as u64, length, length] {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+        bytes.extend(dfd);
+        bytes.extend(data);
+        bytes
+    }
+
+    /// Basic data format descriptor with a single sample covering the whole block
+    fn data_format_descriptor(&self) -> Vec<u8> {
+        const BLOCK_SIZE: u16 = 24 + 16;
+        const BT709_PRIMARIES: u8 = 1;
+        const SRGB_TRANSFER: u8 = 2;
+        let block_size = self.format.block_size();
+
+        let mut dfd = Vec::new();
+        dfd.extend((4 + BLOCK_SIZE as u32).to_le_bytes());
+        // Khronos vendor, basic descriptor type
+        dfd.extend(0u32.to_le_bytes());
+        // Version 1.3 of the specification
+        dfd.extend(2u16.to_le_bytes());
+        dfd.extend(BLOCK_SIZE.to_le_bytes());
+        dfd.extend([self.format.color_model(), BT709_PRIMARIES, SRGB_TRANSFER, 0]);
+        // 4x4 texel blocks, stored minus one
+        dfd.extend([3, 3, 0, 0]);
+        dfd.extend([block_size as u8, 0, 0, 0, 0, 0, 0, 0]);
+        // The only sample: bit offset, bit length minus one, channel, position, bounds
+        dfd.extend(0u16.to_le_bytes());
+        dfd.extend([(block_size * 8 - 1) as u8, self.format.sample_channel()]);
+        dfd.extend([0; 4]);
+        dfd.extend(0u32.to_le_bytes());
+        dfd.extend(u32::MAX.to_le_bytes());
+        dfd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cubemap(format: CompressedFormat) -> Ktx2Cubemap {
+        let size = 8;
+        let levels = (0..4)
+            .map(|level| {
+                let level_size = Ktx2Cubemap::level_size(format, size >> level);
+                (0..level_size).map(|i| (i + level) as u8).collect()
+            })
+            .collect();
+        Ktx2Cubemap {
+            format,
+            size,
+            levels,
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for format in CompressedFormat::ALL {
+            let cubemap = cubemap(format);
+            let parsed = Ktx2Cubemap::from_bytes(&cubemap.to_bytes()).unwrap();
+            assert_eq!(parsed.format, format);
+            assert_eq!(parsed.size, cubemap.size);
+            assert_eq!(parsed.levels, cubemap.levels);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = cubemap(CompressedFormat::Bc7).to_bytes();
+        for length in 0..bytes.len() {
+            assert!(Ktx2Cubemap::from_bytes(&bytes[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_level_index() {
+        let bytes = cubemap(CompressedFormat::Etc2).to_bytes();
+        // Level count
+        let mut corrupt = bytes.clone();
+        corrupt[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Ktx2Cubemap::from_bytes(&corrupt).is_err());
+        // Offset of the first level
+        let mut corrupt = bytes.clone();
+        let entry = Ktx2Cubemap::HEADER_SIZE;
+        corrupt[entry..entry + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Ktx2Cubemap::from_bytes(&corrupt).is_err());
+    }
+}
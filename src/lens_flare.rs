@@ -0,0 +1,189 @@
+use wgpu::util::DeviceExt;
+
+use crate::{camera, material, pipeline, sun, texture};
+
+/// Screen space glare, starburst and ghosts drawn into the HDR texture when the sun is
+/// on screen. Occlusion is checked against the depth buffer, so planets passing in front
+/// of the sun hide the flare.
+pub struct LensFlare {
+    uniform: LensFlareUniform,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl LensFlare {
+    const COLOR: [f32; 3] = [1.0, 0.85, 0.65];
+    const INTENSITY: f32 = 1.0;
+
+    pub fn new(device: &wgpu::Device, hdr_format: wgpu::TextureFormat) -> Self {
+        let uniform = LensFlareUniform::hidden();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("LensFlare::buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let vertex_fragment = wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT;
+        let bind_group_layout = material::create_bind_group_layout(
+            device,
+            "LensFlare::layout",
+            &[
+                material::Binding::Uniform(vertex_fragment),
+                // The depth buffer is only read with `textureLoad`, so no sampler is needed.
+                // Binding it as an unfilterable float texture keeps it readable on every backend.
+                material::Binding::Texture {
+                    visibility: vertex_fragment,
+                    dimension: wgpu::TextureViewDimension::D2,
+                    filterable: false,
+                },
+            ],
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("LensFlare::pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = wgpu::include_wgsl!("../shaders/lens_flare.wgsl");
+        let pipeline = pipeline::create_transparent_render_pipeline(
+            device,
+            &pipeline_layout,
+            hdr_format,
+            None,
+            // Vertices are generated in the shader
+            &[],
+            wgpu::PrimitiveTopology::TriangleList,
+            pipeline::ADDITIVE_BLENDING,
+            shader,
+            Some("render_pipeline_lens_flare"),
+        );
+
+        Self {
+            uniform,
+            buffer,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        depth_texture: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        material::create_bind_group(
+            device,
+            "LensFlare::bind_group",
+            layout,
+            &[
+                buffer.as_entire_binding(),
+                wgpu::BindingResource::TextureView(&depth_texture.view),
+            ],
+        )
+    }
+
+    /// Creates the bind group of [`Self::process`] for a depth texture, the render graph
+    /// calls it again whenever it reallocates the texture
+    pub fn bind_group_factory(
+        &self,
+    ) -> impl Fn(&wgpu::Device, &texture::Texture) -> wgpu::BindGroup + 'static {
+        let layout = self.bind_group_layout.clone();
+        let buffer = self.buffer.clone();
+        move |device, depth_texture| {
+            Self::create_bind_group(device, &layout, &buffer, depth_texture)
+        }
+    }
+
+    /// Projects the sun to the screen
+    pub fn update(&mut self, camera_container: &camera::CameraContainer, sun: &sun::Sun) {
+        let view_matrix = camera_container.camera.view_matrix();
+        let view_projection_matrix = camera_container.projection.projection_matrix() * view_matrix;
+
+        // The view matrix is camera-relative, see `Camera::view_matrix`
+        let sun_position = (sun.position() - camera_container.camera.position()).as_vec3();
+        let center = view_projection_matrix * sun_position.extend(1.0);
+        // The sun is behind the camera
+        if center.w <= 0.0 {
+            self.uniform = LensFlareUniform::hidden();
+            return;
+        }
+
+        // The point of the sun closest to the camera is the first one written to the depth
+        // buffer, anything in front of it occludes the sun
+        let to_camera = (-sun_position).normalize_or_zero();
+        let closest =
+            view_projection_matrix * (sun_position + to_camera * sun.radius()).extend(1.0);
+
+        let camera_up = view_matrix.inverse().col(1).truncate();
+        let top = view_projection_matrix * (sun_position + camera_up * sun.radius()).extend(1.0);
+
+        let center_ndc = center.truncate().truncate() / center.w;
+        let top_ndc = top.truncate().truncate() / top.w;
+
+        self.uniform = LensFlareUniform {
+            sun_position: center_ndc.into(),
+            sun_radius: (top_ndc - center_ndc).length(),
+            sun_depth: closest.z / closest.w,
+            color: Self::COLOR,
+            intensity: Self::INTENSITY,
+        };
+    }
+
+    pub fn sync_buffer(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    /// Adds the flare on top of the HDR texture supplied as parameter, the depth texture
+    /// cannot be used as an attachment at this point and is read through `bind_group` (see
+    /// [`Self::bind_group_factory`]).
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("LensFlare::process"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LensFlareUniform {
+    /// In normalized device coordinates
+    sun_position: [f32; 2],
+    /// In normalized device coordinates, along the vertical axis
+    sun_radius: f32,
+    sun_depth: f32,
+    color: [f32; 3],
+    intensity: f32,
+}
+
+impl LensFlareUniform {
+    fn hidden() -> Self {
+        Self {
+            sun_position: [0.0, 0.0],
+            sun_radius: 0.0,
+            sun_depth: 0.0,
+            color: [0.0, 0.0, 0.0],
+            intensity: 0.0,
+        }
+    }
+}
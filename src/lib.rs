@@ -1,13 +1,33 @@
 pub mod app;
+mod background;
+mod belts;
 mod camera;
+mod clock;
+mod clouds;
+mod comet_tails;
+pub mod compress;
+mod culling;
+mod ephemeris;
 mod hdr;
 mod instance;
+mod ktx2;
+mod lens_flare;
 mod light;
+mod material;
+mod minor_bodies;
+mod nbody;
 mod pipeline;
 mod planets;
+mod random;
+mod render_graph;
 mod ring;
+mod scale;
 mod skybox;
 mod sphere;
+mod starfield;
 mod sun;
+mod surface_maps;
+mod terrain;
 mod texture;
 mod vertex;
+mod vsop87;
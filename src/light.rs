@@ -1,47 +1,141 @@
 use wgpu::util::DeviceExt;
 
+use crate::{hdr, material};
+
+/// Light of the sun, along with the light of the surroundings (image-based lighting from
+/// the skybox)
 pub struct Light {
+    color: [f32; 3],
+    /// From the scene frame to the frame of the environment maps
+    environment_rotation: glam::Mat4,
+    /// Brightness of the skybox the environment maps come from
+    environment_brightness: f32,
+    /// Scales the environment lighting on top of the brightness, 0.0 turns it off
+    environment_intensity: f32,
+    buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
 }
 
 impl Light {
-    pub fn new(device: &wgpu::Device, position: [f32; 3], color: [f32; 3]) -> Self {
-        let uniform = LightUniform::new(position, color);
+    /// Environment variable with the initial intensity of the environment lighting
+    const ENVIRONMENT_INTENSITY_VARIABLE: &str = "SOLAR_SYSTEM_ENVIRONMENT_LIGHT";
+    const DEFAULT_ENVIRONMENT_INTENSITY: f32 = 1.0;
+
+    pub fn new(
+        device: &wgpu::Device,
+        position: [f32; 3],
+        color: [f32; 3],
+        environment: &hdr::EnvironmentMaps,
+    ) -> Self {
+        let environment_intensity = std::env::var(Self::ENVIRONMENT_INTENSITY_VARIABLE)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(Self::DEFAULT_ENVIRONMENT_INTENSITY);
+        let uniform = LightUniform::new(position, color, glam::Mat4::IDENTITY, 0.0);
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Light VB"),
             contents: bytemuck::cast_slice(&[uniform]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-            label: None,
-        });
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
-            label: None,
+        let cube = material::Binding::fragment_texture(wgpu::TextureViewDimension::Cube);
+        let bind_group_layout = material::create_bind_group_layout(
+            device,
+            "light_bind_group_layout",
+            &[
+                material::Binding::Uniform(
+                    wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ),
+                // Irradiance and prefiltered environment maps
+                cube,
+                cube,
+                material::Binding::FRAGMENT_SAMPLER,
+            ],
+        );
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
         });
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &buffer, &sampler, environment);
 
         Self {
+            color,
+            environment_rotation: glam::Mat4::IDENTITY,
+            environment_brightness: 1.0,
+            environment_intensity,
+            buffer,
+            sampler,
             bind_group_layout,
             bind_group,
         }
     }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        sampler: &wgpu::Sampler,
+        environment: &hdr::EnvironmentMaps,
+    ) -> wgpu::BindGroup {
+        material::create_bind_group(
+            device,
+            "light_bind_group",
+            layout,
+            &[
+                buffer.as_entire_binding(),
+                wgpu::BindingResource::TextureView(environment.irradiance().view()),
+                wgpu::BindingResource::TextureView(environment.prefiltered().view()),
+                wgpu::BindingResource::Sampler(sampler),
+            ],
+        )
+    }
+
+    /// Has to be called when the skybox changes
+    pub fn set_environment(&mut self, device: &wgpu::Device, environment: &hdr::EnvironmentMaps) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.buffer,
+            &self.sampler,
+            environment,
+        );
+    }
+
+    /// Orientation and brightness of the skybox the environment maps come from
+    pub fn set_environment_transform(&mut self, rotation: glam::Mat4, brightness: f32) {
+        self.environment_rotation = rotation;
+        self.environment_brightness = brightness;
+    }
+
+    pub fn environment_intensity(&self) -> f32 {
+        self.environment_intensity
+    }
+
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.environment_intensity = intensity.max(0.0);
+    }
+
+    /// Position is relative to the camera, like everything else sent to the GPU
+    pub fn sync_buffer(&self, queue: &wgpu::Queue, position: [f32; 3]) {
+        let uniform = LightUniform::new(
+            position,
+            self.color,
+            self.environment_rotation,
+            self.environment_brightness * self.environment_intensity,
+        );
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
 }
 
+/// Shaders that only need the sun can declare just `position` and `color`
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
@@ -49,15 +143,26 @@ pub struct LightUniform {
     _padding: f32,
     color: [f32; 3],
     _padding2: f32,
+    environment_rotation: [[f32; 4]; 4],
+    environment_intensity: f32,
+    _padding3: [f32; 3],
 }
 
 impl LightUniform {
-    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+    pub fn new(
+        position: [f32; 3],
+        color: [f32; 3],
+        environment_rotation: glam::Mat4,
+        environment_intensity: f32,
+    ) -> Self {
         Self {
             position,
             _padding: 0.0,
             color,
             _padding2: 0.0,
+            environment_rotation: environment_rotation.to_cols_array_2d(),
+            environment_intensity,
+            _padding3: [0.0; 3],
         }
     }
 }
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use solar_system::app::App;
+use solar_system::{app::App, compress};
 use winit::event_loop::EventLoop;
 
 fn run() -> Result<()> {
@@ -11,5 +11,9 @@ fn run() -> Result<()> {
 
 fn main() -> Result<()> {
     env_logger::init();
-    run()
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.first().map(String::as_str) {
+        Some("convert-textures") => compress::convert_textures(&args[1..]),
+        _ => run(),
+    }
 }
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+
+use crate::pipeline;
+
+/// Binding of a bind group layout, they are numbered in the order they are declared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Uniform(wgpu::ShaderStages),
+    /// Float texture
+    Texture {
+        visibility: wgpu::ShaderStages,
+        dimension: wgpu::TextureViewDimension,
+        filterable: bool,
+    },
+    /// Has to be `filtering` to sample a filterable texture
+    Sampler {
+        visibility: wgpu::ShaderStages,
+        filtering: bool,
+    },
+}
+
+impl Binding {
+    /// Texture sampled with [`Self::FRAGMENT_SAMPLER`], the usual binding of a color map
+    pub const fn fragment_texture(dimension: wgpu::TextureViewDimension) -> Self {
+        Self::Texture {
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            dimension,
+            filterable: true,
+        }
+    }
+
+    pub const FRAGMENT_SAMPLER: Self = Self::Sampler {
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        filtering: true,
+    };
+
+    fn entry(self, binding: u32) -> wgpu::BindGroupLayoutEntry {
+        let (visibility, ty) = match self {
+            Self::Uniform(visibility) => (
+                visibility,
+                wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            ),
+            Self::Texture {
+                visibility,
+                dimension,
+                filterable,
+            } => (
+                visibility,
+                wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable },
+                    view_dimension: dimension,
+                    multisampled: false,
+                },
+            ),
+            Self::Sampler {
+                visibility,
+                filtering,
+            } => (
+                visibility,
+                wgpu::BindingType::Sampler(match filtering {
+                    true => wgpu::SamplerBindingType::Filtering,
+                    false => wgpu::SamplerBindingType::NonFiltering,
+                }),
+            ),
+        };
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty,
+            count: None,
+        }
+    }
+}
+
+pub fn create_bind_group_layout(
+    device: &wgpu::Device,
+    label: &str,
+    bindings: &[Binding],
+) -> wgpu::BindGroupLayout {
+    let entries: Vec<_> = bindings
+        .iter()
+        .zip(0..)
+        .map(|(binding, index)| binding.entry(index))
+        .collect();
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(label),
+        entries: &entries,
+    })
+}
+
+/// Binds `resources` in order, like the bindings of [`create_bind_group_layout`]
+pub fn create_bind_group(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::BindGroupLayout,
+    resources: &[wgpu::BindingResource],
+) -> wgpu::BindGroup {
+    let entries: Vec<_> = resources
+        .iter()
+        .zip(0..)
+        .map(|(resource, binding)| wgpu::BindGroupEntry {
+            binding,
+            resource: resource.clone(),
+        })
+        .collect();
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &entries,
+    })
+}
+
+/// Where a bind group of a material comes from when it is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindGroupSlot {
+    /// See [`SceneBindGroups`]
+    Camera,
+    Light,
+    /// Passed to [`SetMaterial::set_material`] by the object, in the order of the slots
+    Object,
+}
+
+/// How the fragments are written to the HDR texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Blending {
+    /// Replaces the color and writes to the depth buffer
+    Opaque,
+    /// Blends using `blend` and tests against the depth buffer without writing to it, so it
+    /// has to be drawn after all opaque materials. Flat geometry seen from both sides has no
+    /// `cull_mode`.
+    Transparent {
+        blend: wgpu::BlendState,
+        cull_mode: Option<wgpu::Face>,
+    },
+}
+
+pub struct MaterialDescriptor<'a> {
+    /// Identifies the shader in the [`PipelineCache`], materials with the same name must
+    /// use the same shader
+    pub name: &'static str,
+    pub shader: wgpu::ShaderModuleDescriptor<'a>,
+    /// The index of a group is its position, so it has to match the shader
+    pub bind_groups: &'a [(BindGroupSlot, &'a wgpu::BindGroupLayout)],
+    pub vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+    pub topology: wgpu::PrimitiveTopology,
+    pub blending: Blending,
+}
+
+/// Render pipeline along with where its bind groups come from, see [`SetMaterial`]
+pub struct Material {
+    pipeline: wgpu::RenderPipeline,
+    slots: Vec<BindGroupSlot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct VertexLayoutKey {
+    array_stride: wgpu::BufferAddress,
+    step_mode: wgpu::VertexStepMode,
+    attributes: Vec<wgpu::VertexAttribute>,
+}
+
+/// Everything a pipeline is created from, but the shader which is fixed by the name
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    material: &'static str,
+    /// Compared by identity, layouts made with [`PipelineCache::bind_group_layout`] are the
+    /// same for equal bindings
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    vertex_layouts: Vec<VertexLayoutKey>,
+    topology: wgpu::PrimitiveTopology,
+    blending: Blending,
+}
+
+/// Render pipelines of the scene, which all draw to the HDR texture with the depth buffer.
+/// Materials with the same name, bind group layouts, vertex layouts, topology and blending
+/// share their pipeline.
+pub struct PipelineCache {
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+    layouts: HashMap<Vec<Binding>, wgpu::BindGroupLayout>,
+}
+
+impl PipelineCache {
+    pub fn new(color_format: wgpu::TextureFormat, depth_format: wgpu::TextureFormat) -> Self {
+        Self {
+            color_format,
+            depth_format,
+            pipelines: HashMap::new(),
+            layouts: HashMap::new(),
+        }
+    }
+
+    /// Like [`create_bind_group_layout`], but equal bindings get the same layout, so that
+    /// objects with the same material share its pipeline. `label` is the one of the first
+    /// layout with these bindings.
+    pub fn bind_group_layout(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        bindings: &[Binding],
+    ) -> wgpu::BindGroupLayout {
+        self.layouts
+            .entry(bindings.to_vec())
+            .or_insert_with(|| create_bind_group_layout(device, label, bindings))
+            .clone()
+    }
+
+    /// Distinct pipelines created so far
+    pub fn pipeline_count(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn material(&mut self, device: &wgpu::Device, descriptor: MaterialDescriptor) -> Material {
+        let key = PipelineKey {
+            material: descriptor.name,
+            bind_group_layouts: descriptor
+                .bind_groups
+                .iter()
+                .map(|(_, layout)| (*layout).clone())
+                .collect(),
+            vertex_layouts: descriptor
+                .vertex_layouts
+                .iter()
+                .map(|layout| VertexLayoutKey {
+                    array_stride: layout.array_stride,
+                    step_mode: layout.step_mode,
+                    attributes: layout.attributes.to_vec(),
+                })
+                .collect(),
+            topology: descriptor.topology,
+            blending: descriptor.blending,
+        };
+        let slots = descriptor
+            .bind_groups
+            .iter()
+            .map(|(slot, _)| *slot)
+            .collect();
+        let pipeline = self
+            .pipelines
+            .entry(key)
+            .or_insert_with(|| {
+                Self::create_pipeline(device, self.color_format, self.depth_format, descriptor)
+            })
+            .clone();
+        Material { pipeline, slots }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        descriptor: MaterialDescriptor,
+    ) -> wgpu::RenderPipeline {
+        let bind_group_layouts: Vec<_> = descriptor
+            .bind_groups
+            .iter()
+            .map(|(_, layout)| *layout)
+            .collect();
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(descriptor.name),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let label = format!("render_pipeline_{}", descriptor.name);
+        match descriptor.blending {
+            Blending::Opaque => pipeline::create_render_pipeline(
+                device,
+                &layout,
+                color_format,
+                Some(depth_format),
+                descriptor.vertex_layouts,
+                descriptor.topology,
+                descriptor.shader,
+                Some(&label),
+            ),
+            Blending::Transparent {
+                blend,
+                cull_mode: Some(wgpu::Face::Back),
+            } => pipeline::create_transparent_render_pipeline(
+                device,
+                &layout,
+                color_format,
+                Some(depth_format),
+                descriptor.vertex_layouts,
+                descriptor.topology,
+                blend,
+                descriptor.shader,
+                Some(&label),
+            ),
+            Blending::Transparent { blend, .. } => {
+                pipeline::create_transparent_render_pipeline_without_culling(
+                    device,
+                    &layout,
+                    color_format,
+                    Some(depth_format),
+                    descriptor.vertex_layouts,
+                    descriptor.topology,
+                    blend,
+                    descriptor.shader,
+                    Some(&label),
+                )
+            }
+        }
+    }
+}
+
+/// Bind groups shared by the materials, set up once per frame
+pub struct SceneBindGroups<'a> {
+    pub camera: &'a wgpu::BindGroup,
+    pub light: &'a wgpu::BindGroup,
+}
+
+pub trait SetMaterial {
+    /// Sets the pipeline of `material` and all its bind groups, `object_bind_groups` are
+    /// the ones of its [`BindGroupSlot::Object`] slots
+    fn set_material(
+        &mut self,
+        material: &Material,
+        scene: &SceneBindGroups,
+        object_bind_groups: &[&wgpu::BindGroup],
+    );
+}
+
+impl SetMaterial for wgpu::RenderPass<'_> {
+    fn set_material(
+        &mut self,
+        material: &Material,
+        scene: &SceneBindGroups,
+        object_bind_groups: &[&wgpu::BindGroup],
+    ) {
+        self.set_pipeline(&material.pipeline);
+        let mut object_bind_groups = object_bind_groups.iter();
+        for (slot, index) in material.slots.iter().zip(0..) {
+            let bind_group = match slot {
+                BindGroupSlot::Camera => scene.camera,
+                BindGroupSlot::Light => scene.light,
+                BindGroupSlot::Object => object_bind_groups
+                    .next()
+                    .expect("Missing bind group of the object"),
+            };
+            self.set_bind_group(index, bind_group, &[]);
+        }
+        debug_assert!(
+            object_bind_groups.next().is_none(),
+            "Too many bind groups for the material"
+        );
+    }
+}
+
+/// Anything drawn in the scene pass
+pub trait Renderable {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &SceneBindGroups);
+}
@@ -0,0 +1,382 @@
+use std::time::Duration;
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera,
+    clock::SimulationClock,
+    comet_tails::{self, CometTails},
+    culling,
+    ephemeris::{self, OrbitalElements},
+    instance::{self, Instance},
+    material::{self, SetMaterial},
+    planets::Planets,
+    ring, scale,
+    sphere::{self, Sphere},
+    sun,
+    vertex::Vertex,
+};
+
+/// Entry of the catalog of bodies which are not covered by the planet ephemerides
+struct MinorBody {
+    elements: OrbitalElements,
+    radius_km: f64,
+    /// Radius in the artistic layout, in scene units
+    artistic_radius: f32,
+    /// The surface is a procedural mix of these two colors
+    base_color: [f32; 3],
+    detail_color: [f32; 3],
+    /// Length of the ion tail at 1 AU from the sun, only comets have one
+    tail_length_au: Option<f32>,
+    ring: Option<ring::RingParameters>,
+}
+
+/// Dwarf planets and comets, on fixed Keplerian orbits. Surfaces are procedural, as there
+/// are no good global maps of most of these bodies.
+pub struct MinorBodies {
+    instances: Vec<Instance>,
+    culling: culling::InstanceCulling,
+    bind_group: wgpu::BindGroup,
+    material: material::Material,
+    sphere: Sphere,
+    comet_tails: CometTails,
+}
+
+impl MinorBodies {
+    const CATALOG: [MinorBody; 7] = [
+        // Ceres
+        MinorBody {
+            elements: OrbitalElements {
+                semi_major_axis: 2.7675,
+                eccentricity: 0.0785,
+                inclination: 10.59,
+                longitude_of_ascending_node: 80.27,
+                argument_of_perihelion: 73.42,
+                perihelion_time: 2_459_920.5,
+            },
+            radius_km: 469.7,
+            artistic_radius: 0.25,
+            base_color: [0.35, 0.34, 0.33],
+            detail_color: [0.5, 0.49, 0.47],
+            tail_length_au: None,
+            ring: None,
+        },
+        // Pluto
+        MinorBody {
+            elements: OrbitalElements {
+                semi_major_axis: 39.482,
+                eccentricity: 0.2488,
+                inclination: 17.16,
+                longitude_of_ascending_node: 110.30,
+                argument_of_perihelion: 113.83,
+                perihelion_time: 2_447_774.5,
+            },
+            radius_km: 1188.3,
+            artistic_radius: 0.4,
+            base_color: [0.62, 0.5, 0.38],
+            detail_color: [0.9, 0.85, 0.78],
+            tail_length_au: None,
+            ring: None,
+        },
+        // Eris
+        MinorBody {
+            elements: OrbitalElements {
+                semi_major_axis: 67.86,
+                eccentricity: 0.4361,
+                inclination: 44.04,
+                longitude_of_ascending_node: 35.95,
+                argument_of_perihelion: 151.64,
+                perihelion_time: 2_341_727.5,
+            },
+            radius_km: 1163.0,
+            artistic_radius: 0.4,
+            base_color: [0.85, 0.85, 0.86],
+            detail_color: [0.95, 0.95, 0.96],
+            tail_length_au: None,
+            ring: None,
+        },
+        // Haumea
+        MinorBody {
+            elements: OrbitalElements {
+                semi_major_axis: 43.18,
+                eccentricity: 0.195,
+                inclination: 28.2,
+                longitude_of_ascending_node: 122.2,
+                argument_of_perihelion: 239.0,
+                perihelion_time: 2_500_212.5,
+            },
+            radius_km: 816.0,
+            artistic_radius: 0.3,
+            base_color: [0.8, 0.8, 0.82],
+            detail_color: [0.6, 0.45, 0.45],
+            tail_length_au: None,
+            // Found in a stellar occultation in 2017, it lies in the equatorial plane of
+            // Haumea, close to the orbit of its moon Hi'iaka
+            ring: Some(ring::RingParameters {
+                name: "haumea",
+                texture: include_bytes!("../assets/textures/haumea_ring.png"),
+                inner_radius: 2.6,
+                outer_radius: 3.0,
+                pole: [285.1, -10.6],
+                forward_scattering: 0.3,
+                particle_density: 1.0,
+            }),
+        },
+        // Makemake
+        MinorBody {
+            elements: OrbitalElements {
+                semi_major_axis: 45.43,
+                eccentricity: 0.161,
+                inclination: 28.98,
+                longitude_of_ascending_node: 79.62,
+                argument_of_perihelion: 294.8,
+                perihelion_time: 2_408_081.5,
+            },
+            radius_km: 715.0,
+            artistic_radius: 0.3,
+            base_color: [0.7, 0.5, 0.4],
+            detail_color: [0.85, 0.7, 0.6],
+            tail_length_au: None,
+            ring: None,
+        },
+        // Halley
+        MinorBody {
+            elements: OrbitalElements {
+                semi_major_axis: 17.834,
+                eccentricity: 0.96714,
+                inclination: 162.26,
+                longitude_of_ascending_node: 58.42,
+                argument_of_perihelion: 111.33,
+                perihelion_time: 2_446_470.96,
+            },
+            radius_km: 5.5,
+            artistic_radius: 0.1,
+            base_color: [0.1, 0.1, 0.1],
+            detail_color: [0.2, 0.19, 0.18],
+            tail_length_au: Some(0.6),
+            ring: None,
+        },
+        // Hale-Bopp
+        MinorBody {
+            elements: OrbitalElements {
+                semi_major_axis: 186.5,
+                eccentricity: 0.99507,
+                inclination: 89.43,
+                longitude_of_ascending_node: 282.47,
+                argument_of_perihelion: 130.59,
+                perihelion_time: 2_450_539.64,
+            },
+            radius_km: 30.0,
+            artistic_radius: 0.12,
+            base_color: [0.1, 0.1, 0.1],
+            detail_color: [0.2, 0.19, 0.18],
+            tail_length_au: Some(1.0),
+            ring: None,
+        },
+    ];
+
+    /// Must match the size of the styles array in `minor_body.wgsl`
+    const MAX_BODIES: usize = 8;
+    /// Same as for the planets, so that sizes can be compared
+    const SIZE_EXAGGERATION: f64 = 500.0;
+    const ROTATION_SPEED: f32 = 0.2;
+    /// Used to find the direction of motion, which bends the dust tails
+    const VELOCITY_DELTA_DAYS: f64 = 0.5;
+
+    pub fn new(
+        device: &wgpu::Device,
+        materials: &mut material::PipelineCache,
+        camera_container: &camera::CameraContainer,
+        sun: &sun::Sun,
+        sphere: &Sphere,
+    ) -> Self {
+        let instances = (0..Self::CATALOG.len())
+            .map(|i| {
+                Instance::new(
+                    glam::DVec3::ZERO,
+                    glam::Quat::IDENTITY,
+                    i as _,
+                    Self::CATALOG[i].artistic_radius,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let culling = culling::InstanceCulling::new(device, instances.len(), "Minor Bodies");
+
+        let mut styles = [BodyStyle::default(); Self::MAX_BODIES];
+        for (style, body) in styles.iter_mut().zip(&Self::CATALOG) {
+            *style = BodyStyle::new(body.base_color, body.detail_color);
+        }
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Minor Bodies Styles Buffer"),
+            contents: bytemuck::cast_slice(&styles),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group_layout = materials.bind_group_layout(
+            device,
+            "minor_bodies_bind_group_layout",
+            &[material::Binding::Uniform(wgpu::ShaderStages::FRAGMENT)],
+        );
+        let bind_group = material::create_bind_group(
+            device,
+            "minor_bodies_bind_group",
+            &bind_group_layout,
+            &[buffer.as_entire_binding()],
+        );
+
+        let material = materials.material(
+            device,
+            material::MaterialDescriptor {
+                name: "minor_bodies",
+                shader: wgpu::include_wgsl!("../shaders/minor_body.wgsl"),
+                bind_groups: &[
+                    (material::BindGroupSlot::Object, &bind_group_layout),
+                    (
+                        material::BindGroupSlot::Camera,
+                        &camera_container.camera_bind_group_layout,
+                    ),
+                    (
+                        material::BindGroupSlot::Light,
+                        &sun.light().bind_group_layout,
+                    ),
+                ],
+                vertex_layouts: &[sphere::SphereVertex::desc(), instance::InstanceRaw::desc()],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                blending: material::Blending::Opaque,
+            },
+        );
+
+        let comets_count = Self::CATALOG
+            .iter()
+            .filter(|body| body.tail_length_au.is_some())
+            .count();
+        let comet_tails = CometTails::new(device, materials, camera_container, comets_count);
+
+        Self {
+            instances,
+            culling,
+            bind_group,
+            material,
+            sphere: sphere.clone(),
+            comet_tails,
+        }
+    }
+
+    pub fn culling(&self) -> &culling::InstanceCulling {
+        &self.culling
+    }
+
+    pub fn comet_tails(&self) -> &CometTails {
+        &self.comet_tails
+    }
+
+    /// Bodies with rings, along with the parameters of their rings
+    pub fn rings(&self) -> impl Iterator<Item = (&Instance, &'static ring::RingParameters)> {
+        let catalog: &'static [MinorBody] = &Self::CATALOG;
+        self.instances
+            .iter()
+            .zip(catalog)
+            .filter_map(|(instance, body)| Some((instance, body.ring.as_ref()?)))
+    }
+
+    /// Heliocentric position in the scene, mapped with the current scale mode. Orbits of
+    /// comets span the whole system, so the artistic distance follows the current distance
+    /// from the sun rather than the mean one.
+    fn scene_position(
+        body: &MinorBody,
+        julian_date: f64,
+        system_scale: &scale::SystemScale,
+    ) -> (glam::DVec3, f64) {
+        let heliocentric = ephemeris::ecliptic_to_scene(body.elements.position(julian_date));
+        let distance_au = heliocentric.length();
+        let dimensions = scale::BodyDimensions {
+            artistic_distance: Planets::artistic_distance(distance_au),
+            artistic_radius: body.artistic_radius,
+            distance_au,
+            radius_km: body.radius_km,
+            exaggeration: Self::SIZE_EXAGGERATION,
+        };
+        let units_per_au = system_scale.distance(&dimensions, distance_au) / distance_au;
+        (heliocentric * units_per_au, units_per_au)
+    }
+
+    pub fn update(
+        &mut self,
+        total_time: Duration,
+        clock: &SimulationClock,
+        system_scale: &scale::SystemScale,
+        sun: &sun::Sun,
+    ) {
+        let julian_date = clock.julian_date();
+        let mut tail_sources = Vec::new();
+        for (body, instance) in Self::CATALOG.iter().zip(&mut self.instances) {
+            let (position, units_per_au) = Self::scene_position(body, julian_date, system_scale);
+            instance.position = position;
+            instance.set_scale(system_scale.radius(&scale::BodyDimensions {
+                artistic_distance: 0.0,
+                artistic_radius: body.artistic_radius,
+                distance_au: 0.0,
+                radius_km: body.radius_km,
+                exaggeration: Self::SIZE_EXAGGERATION,
+            }));
+            instance.rotation =
+                glam::Quat::from_rotation_y(total_time.as_secs_f32() * Self::ROTATION_SPEED);
+
+            if let Some(tail_length_au) = body.tail_length_au {
+                let (next_position, _) = Self::scene_position(
+                    body,
+                    julian_date + Self::VELOCITY_DELTA_DAYS,
+                    system_scale,
+                );
+                tail_sources.push(comet_tails::TailSource {
+                    nucleus: position,
+                    direction_of_motion: (next_position - position).normalize_or_zero().as_vec3(),
+                    distance_au: (position.length() / units_per_au) as f32,
+                    units_per_au: units_per_au as f32,
+                    tail_length_au,
+                });
+            }
+        }
+        self.comet_tails
+            .update(total_time, sun.position(), &tail_sources);
+    }
+
+    /// `origin` is the camera position, all positions are sent relative to it
+    pub fn sync_buffers(
+        &mut self,
+        queue: &wgpu::Queue,
+        origin: glam::DVec3,
+        frustum: &culling::Frustum,
+    ) {
+        self.culling
+            .sync_buffers(queue, &self.instances, origin, frustum);
+        self.comet_tails.sync_buffer(queue, origin);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BodyStyle {
+    base_color: [f32; 3],
+    _padding: f32,
+    detail_color: [f32; 3],
+    _padding2: f32,
+}
+
+impl BodyStyle {
+    fn new(base_color: [f32; 3], detail_color: [f32; 3]) -> Self {
+        Self {
+            base_color,
+            _padding: 0.0,
+            detail_color,
+            _padding2: 0.0,
+        }
+    }
+}
+
+impl material::Renderable for MinorBodies {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &material::SceneBindGroups) {
+        pass.set_material(&self.material, scene, &[&self.bind_group]);
+        self.culling.draw(pass, &self.sphere);
+    }
+}
@@ -0,0 +1,391 @@
+use crate::ephemeris::Ephemeris;
+
+/// Gravitational constant in AU³ / (solar mass · day²), the square of the Gaussian
+/// gravitational constant
+const G: f64 = 0.01720209895 * 0.01720209895;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Body {
+    /// In solar masses
+    pub mass: f64,
+    /// In AU, relative to the barycenter
+    pub position: glam::DVec3,
+    /// In AU per day
+    pub velocity: glam::DVec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrator {
+    /// Second order, symplectic, one force evaluation per step
+    VelocityVerlet,
+    /// Fourth order, not symplectic, so energy slowly drifts over long runs
+    RungeKutta4,
+    /// Second order drift-kick-drift scheme, symplectic
+    Leapfrog,
+}
+
+impl Integrator {
+    pub fn next(self) -> Self {
+        match self {
+            Self::VelocityVerlet => Self::RungeKutta4,
+            Self::RungeKutta4 => Self::Leapfrog,
+            Self::Leapfrog => Self::VelocityVerlet,
+        }
+    }
+}
+
+/// Modifications applied to the solar system before the simulation starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    Standard,
+    /// Jupiter is ten times heavier
+    HeavyJupiter,
+    /// A star of half a solar mass passes through the outer solar system
+    RogueStar,
+}
+
+impl Scenario {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Standard => Self::HeavyJupiter,
+            Self::HeavyJupiter => Self::RogueStar,
+            Self::RogueStar => Self::Standard,
+        }
+    }
+}
+
+/// Newtonian gravitational simulation of the sun and the planets, independent of the
+/// renderer. Units are AU, days and solar masses. The first body is always the sun, the
+/// following ones are the planets in the order used by [`crate::planets::Planets`], any
+/// other bodies (e.g. a rogue star) come after them.
+pub struct NBodySimulation {
+    bodies: Vec<Body>,
+    /// Accelerations at the current positions, reused by the next Verlet step. Only
+    /// maintained by the Verlet integrator, which is why the integrator can't be changed
+    /// during a run.
+    accelerations: Vec<glam::DVec3>,
+    planets_count: usize,
+    integrator: Integrator,
+    julian_date: f64,
+    initial_energy: f64,
+}
+
+impl NBodySimulation {
+    /// Fraction of the shortest dynamical timescale between two bodies used as the step
+    const STEP_FACTOR: f64 = 0.02;
+    const MAX_STEP: f64 = 2.0;
+    /// Keeps a single update from stalling the application when the clock runs very fast.
+    /// Steps get longer (and less accurate) instead.
+    const MAX_STEPS_PER_ADVANCE: usize = 5_000;
+    /// Used to derive velocities from an ephemeris, in days
+    const VELOCITY_DELTA: f64 = 0.01;
+
+    /// Initial state vectors are taken from `ephemeris` at `julian_date`
+    pub fn from_ephemeris(
+        ephemeris: &dyn Ephemeris,
+        sun_mass: f64,
+        planet_masses: &[f64],
+        julian_date: f64,
+        integrator: Integrator,
+    ) -> Self {
+        let mut bodies = vec![Body {
+            mass: sun_mass,
+            position: glam::DVec3::ZERO,
+            velocity: glam::DVec3::ZERO,
+        }];
+        for (i, &mass) in planet_masses.iter().enumerate() {
+            let position = ephemeris.position(i, julian_date);
+            let velocity = (ephemeris.position(i, julian_date + Self::VELOCITY_DELTA)
+                - ephemeris.position(i, julian_date - Self::VELOCITY_DELTA))
+                / (2.0 * Self::VELOCITY_DELTA);
+            bodies.push(Body {
+                mass,
+                position,
+                velocity,
+            });
+        }
+        let mut simulation = Self {
+            accelerations: Vec::new(),
+            planets_count: planet_masses.len(),
+            bodies,
+            integrator,
+            julian_date,
+            initial_energy: 0.0,
+        };
+        simulation.reset_derived_state();
+        simulation
+    }
+
+    pub fn apply_scenario(&mut self, scenario: Scenario, jupiter_index: usize) {
+        match scenario {
+            Scenario::Standard => {}
+            Scenario::HeavyJupiter => {
+                self.bodies[jupiter_index + 1].mass *= 10.0;
+                self.reset_derived_state();
+            }
+            // About 19 km/s, passing 45 AU from the sun after roughly 20 years
+            Scenario::RogueStar => self.add_body(Body {
+                mass: 0.5,
+                position: glam::DVec3::new(-42.0, 82.0, 0.0),
+                velocity: glam::DVec3::new(0.009, -0.006, 0.0),
+            }),
+        }
+    }
+
+    /// Adds a body with a heliocentric position and velocity
+    pub fn add_body(&mut self, mut body: Body) {
+        let sun = self.bodies[0];
+        body.position += sun.position;
+        body.velocity += sun.velocity;
+        self.bodies.push(body);
+        self.reset_derived_state();
+    }
+
+    pub fn integrator(&self) -> Integrator {
+        self.integrator
+    }
+
+    /// Moves to the barycentric frame and recomputes values that depend on all bodies
+    fn reset_derived_state(&mut self) {
+        let total_mass = self.bodies.iter().map(|body| body.mass).sum::<f64>();
+        let center = self
+            .bodies
+            .iter()
+            .map(|body| body.position * body.mass)
+            .sum::<glam::DVec3>()
+            / total_mass;
+        let momentum = self
+            .bodies
+            .iter()
+            .map(|body| body.velocity * body.mass)
+            .sum::<glam::DVec3>()
+            / total_mass;
+        for body in &mut self.bodies {
+            body.position -= center;
+            body.velocity -= momentum;
+        }
+        self.accelerations = Self::accelerations(&self.bodies);
+        self.initial_energy = self.total_energy();
+    }
+
+    /// Kinetic plus potential energy, constant in an exact solution
+    pub fn total_energy(&self) -> f64 {
+        let kinetic = self
+            .bodies
+            .iter()
+            .map(|body| 0.5 * body.mass * body.velocity.length_squared())
+            .sum::<f64>();
+        let mut potential = 0.0;
+        for (i, a) in self.bodies.iter().enumerate() {
+            for b in &self.bodies[i + 1..] {
+                potential -= G * a.mass * b.mass / a.position.distance(b.position);
+            }
+        }
+        kinetic + potential
+    }
+
+    /// Relative change of the total energy since the start, a measure of the integration
+    /// error
+    pub fn energy_drift(&self) -> f64 {
+        ((self.total_energy() - self.initial_energy) / self.initial_energy).abs()
+    }
+
+    fn accelerations(bodies: &[Body]) -> Vec<glam::DVec3> {
+        let positions = bodies.iter().map(|body| body.position).collect::<Vec<_>>();
+        Self::accelerations_at(bodies, &positions)
+    }
+
+    fn accelerations_at(bodies: &[Body], positions: &[glam::DVec3]) -> Vec<glam::DVec3> {
+        let mut accelerations = vec![glam::DVec3::ZERO; bodies.len()];
+        for i in 0..bodies.len() {
+            for j in i + 1..bodies.len() {
+                let offset = positions[j] - positions[i];
+                let distance_squared = offset.length_squared();
+                let force = offset * (G / (distance_squared * distance_squared.sqrt()));
+                accelerations[i] += force * bodies[j].mass;
+                accelerations[j] -= force * bodies[i].mass;
+            }
+        }
+        accelerations
+    }
+
+    /// Step size based on the shortest orbital timescale between any pair of bodies, so
+    /// close encounters are resolved with short steps
+    fn adaptive_step(&self) -> f64 {
+        let mut shortest = f64::INFINITY;
+        for (i, a) in self.bodies.iter().enumerate() {
+            for b in &self.bodies[i + 1..] {
+                let distance = a.position.distance(b.position);
+                let timescale = (distance.powi(3) / (G * (a.mass + b.mass))).sqrt();
+                shortest = shortest.min(timescale);
+            }
+        }
+        (shortest * Self::STEP_FACTOR).min(Self::MAX_STEP)
+    }
+
+    /// Integrates until `julian_date`. The simulation only moves forward in time.
+    pub fn advance_to(&mut self, julian_date: f64) {
+        let remaining = julian_date - self.julian_date;
+        if remaining <= 0.0 {
+            return;
+        }
+        let min_step = remaining / Self::MAX_STEPS_PER_ADVANCE as f64;
+        while self.julian_date < julian_date {
+            let step = self
+                .adaptive_step()
+                .max(min_step)
+                .min(julian_date - self.julian_date);
+            self.step(step);
+            self.julian_date += step;
+        }
+    }
+
+    pub fn step(&mut self, dt: f64) {
+        match self.integrator {
+            Integrator::VelocityVerlet => self.step_velocity_verlet(dt),
+            Integrator::RungeKutta4 => self.step_runge_kutta_4(dt),
+            Integrator::Leapfrog => self.step_leapfrog(dt),
+        }
+    }
+
+    fn step_velocity_verlet(&mut self, dt: f64) {
+        for (body, acceleration) in self.bodies.iter_mut().zip(&self.accelerations) {
+            body.position += body.velocity * dt + *acceleration * (0.5 * dt * dt);
+        }
+        let new_accelerations = Self::accelerations(&self.bodies);
+        for ((body, old), new) in self
+            .bodies
+            .iter_mut()
+            .zip(&self.accelerations)
+            .zip(&new_accelerations)
+        {
+            body.velocity += (*old + *new) * (0.5 * dt);
+        }
+        self.accelerations = new_accelerations;
+    }
+
+    fn step_leapfrog(&mut self, dt: f64) {
+        for body in &mut self.bodies {
+            body.position += body.velocity * (0.5 * dt);
+        }
+        let accelerations = Self::accelerations(&self.bodies);
+        for (body, acceleration) in self.bodies.iter_mut().zip(&accelerations) {
+            body.velocity += *acceleration * dt;
+            body.position += body.velocity * (0.5 * dt);
+        }
+    }
+
+    fn step_runge_kutta_4(&mut self, dt: f64) {
+        let bodies = &self.bodies;
+        let positions = bodies.iter().map(|body| body.position).collect::<Vec<_>>();
+        let velocities = bodies.iter().map(|body| body.velocity).collect::<Vec<_>>();
+        let offset = |base: &[glam::DVec3], delta: &[glam::DVec3], factor: f64| {
+            base.iter()
+                .zip(delta)
+                .map(|(b, d)| *b + *d * factor)
+                .collect::<Vec<_>>()
+        };
+
+        let k1_v = Self::accelerations_at(bodies, &positions);
+        let k1_x = velocities.clone();
+
+        let k2_x = offset(&velocities, &k1_v, 0.5 * dt);
+        let k2_v = Self::accelerations_at(bodies, &offset(&positions, &k1_x, 0.5 * dt));
+
+        let k3_x = offset(&velocities, &k2_v, 0.5 * dt);
+        let k3_v = Self::accelerations_at(bodies, &offset(&positions, &k2_x, 0.5 * dt));
+
+        let k4_x = offset(&velocities, &k3_v, dt);
+        let k4_v = Self::accelerations_at(bodies, &offset(&positions, &k3_x, dt));
+
+        for (i, body) in self.bodies.iter_mut().enumerate() {
+            body.position += (k1_x[i] + 2.0 * k2_x[i] + 2.0 * k3_x[i] + k4_x[i]) * (dt / 6.0);
+            body.velocity += (k1_v[i] + 2.0 * k2_v[i] + 2.0 * k3_v[i] + k4_v[i]) * (dt / 6.0);
+        }
+    }
+}
+
+impl Ephemeris for NBodySimulation {
+    fn name(&self) -> &'static str {
+        "N-body simulation"
+    }
+
+    /// Positions are only known at the current time of the simulation, so `julian_date` is
+    /// ignored, call [`NBodySimulation::advance_to`] first
+    fn position(&self, planet: usize, _julian_date: f64) -> glam::DVec3 {
+        debug_assert!(planet < self.planets_count);
+        self.bodies[planet + 1].position - self.bodies[0].position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::SimulationClock, ephemeris::KeplerOrbits, planets::Planets};
+
+    const YEARS: f64 = 1000.0;
+    /// Largest relative energy drift accepted over [`YEARS`]
+    const MAX_DRIFT: f64 = 2e-5;
+
+    fn standard_system(integrator: Integrator) -> NBodySimulation {
+        NBodySimulation::from_ephemeris(
+            &KeplerOrbits,
+            1.0,
+            &Planets::MASS,
+            SimulationClock::J2000,
+            integrator,
+        )
+    }
+
+    /// Fixed steps of a day, a tenth of the orbital period of Mercury
+    fn fixed_step_drift(integrator: Integrator) -> f64 {
+        let mut simulation = standard_system(integrator);
+        let steps = (YEARS * 365.25) as usize;
+        for _ in 0..steps {
+            simulation.step(1.0);
+        }
+        simulation.energy_drift()
+    }
+
+    #[test]
+    fn velocity_verlet_conserves_energy() {
+        let drift = fixed_step_drift(Integrator::VelocityVerlet);
+        assert!(drift < MAX_DRIFT, "Energy drift {drift:e}");
+    }
+
+    #[test]
+    fn runge_kutta_4_conserves_energy() {
+        let drift = fixed_step_drift(Integrator::RungeKutta4);
+        assert!(drift < MAX_DRIFT, "Energy drift {drift:e}");
+    }
+
+    #[test]
+    fn leapfrog_conserves_energy() {
+        let drift = fixed_step_drift(Integrator::Leapfrog);
+        assert!(drift < MAX_DRIFT, "Energy drift {drift:e}");
+    }
+
+    #[test]
+    fn adaptive_steps_conserve_energy() {
+        let mut simulation = standard_system(Integrator::Leapfrog);
+        // Advances by a month at a time, like frames at the default clock rate
+        let end = SimulationClock::J2000 + YEARS * 365.25;
+        let mut julian_date = SimulationClock::J2000;
+        while julian_date < end {
+            julian_date += 30.0;
+            simulation.advance_to(julian_date);
+        }
+        let drift = simulation.energy_drift();
+        assert!(drift < MAX_DRIFT, "Energy drift {drift:e}");
+    }
+
+    #[test]
+    fn starts_at_the_ephemeris_positions() {
+        let simulation = standard_system(Integrator::Leapfrog);
+        for planet in 0..Planets::MASS.len() {
+            let expected = KeplerOrbits.position(planet, SimulationClock::J2000);
+            let position = simulation.position(planet, SimulationClock::J2000);
+            assert!(position.distance(expected) < 1e-9);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use crate::texture;
+
 #[allow(clippy::too_many_arguments)]
 pub fn create_render_pipeline(
     device: &wgpu::Device,
@@ -45,7 +47,7 @@ pub fn create_render_pipeline(
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
             depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::LessEqual,
+            depth_compare: texture::Texture::DEPTH_COMPARE,
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
@@ -59,14 +61,72 @@ pub fn create_render_pipeline(
     })
 }
 
+/// Pipeline for semi-transparent or glowing geometry. It blends using the given `blend`
+/// state and tests against the depth buffer without writing to it, so it has to be drawn
+/// after all opaque objects.
+#[allow(clippy::too_many_arguments)]
+pub fn create_transparent_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    topology: wgpu::PrimitiveTopology,
+    blend: wgpu::BlendState,
+    shader: wgpu::ShaderModuleDescriptor,
+    label: Option<&str>,
+) -> wgpu::RenderPipeline {
+    transparent_render_pipeline(
+        device,
+        layout,
+        color_format,
+        depth_format,
+        vertex_layouts,
+        topology,
+        blend,
+        Some(wgpu::Face::Back),
+        shader,
+        label,
+    )
+}
+
+/// Like [`create_transparent_render_pipeline`], for flat geometry seen from both sides
+#[allow(clippy::too_many_arguments)]
+pub fn create_transparent_render_pipeline_without_culling(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    topology: wgpu::PrimitiveTopology,
+    blend: wgpu::BlendState,
+    shader: wgpu::ShaderModuleDescriptor,
+    label: Option<&str>,
+) -> wgpu::RenderPipeline {
+    transparent_render_pipeline(
+        device,
+        layout,
+        color_format,
+        depth_format,
+        vertex_layouts,
+        topology,
+        blend,
+        None,
+        shader,
+        label,
+    )
+}
+
 #[allow(clippy::too_many_arguments)]
-pub fn create_render_pipeline_without_culling(
+fn transparent_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
     color_format: wgpu::TextureFormat,
     depth_format: Option<wgpu::TextureFormat>,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     topology: wgpu::PrimitiveTopology,
+    blend: wgpu::BlendState,
+    cull_mode: Option<wgpu::Face>,
     shader: wgpu::ShaderModuleDescriptor,
     label: Option<&str>,
 ) -> wgpu::RenderPipeline {
@@ -86,7 +146,7 @@ pub fn create_render_pipeline_without_culling(
             entry_point: Some("fs_main"),
             targets: &[Some(wgpu::ColorTargetState {
                 format: color_format,
-                blend: Some(wgpu::BlendState::REPLACE),
+                blend: Some(blend),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
             compilation_options: wgpu::PipelineCompilationOptions::default(),
@@ -95,7 +155,7 @@ pub fn create_render_pipeline_without_culling(
             topology,
             strip_index_format: None,
             front_face: wgpu::FrontFace::Ccw,
-            cull_mode: None,
+            cull_mode,
             // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
             polygon_mode: wgpu::PolygonMode::Fill,
             // Requires Features::DEPTH_CLIP_CONTROL
@@ -105,8 +165,8 @@ pub fn create_render_pipeline_without_culling(
         },
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::LessEqual,
+            depth_write_enabled: false,
+            depth_compare: texture::Texture::DEPTH_COMPARE,
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
@@ -119,3 +179,35 @@ pub fn create_render_pipeline_without_culling(
         cache: None,
     })
 }
+
+/// Adds the output of the fragment shader to the render target
+pub const ADDITIVE_BLENDING: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+pub fn create_compute_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: wgpu::ShaderModuleDescriptor,
+    label: Option<&str>,
+) -> wgpu::ComputePipeline {
+    let shader = device.create_shader_module(shader);
+
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label,
+        layout: Some(layout),
+        module: &shader,
+        entry_point: Some("cs_main"),
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
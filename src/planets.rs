@@ -1,25 +1,36 @@
 use core::f32;
-use std::time::Duration;
-
-use wgpu::util::DeviceExt;
 
 use crate::{
-    camera, hdr,
+    camera,
+    clock::SimulationClock,
+    clouds, culling,
+    ephemeris::{self, Ephemeris},
     instance::{self, Instance},
-    pipeline,
-    ring::{self, DrawRing},
-    sphere::{self, DrawSphere, Sphere},
-    sun,
-    texture::{self, SetTextureContainer},
+    material::{self, SetMaterial},
+    nbody, ring, scale,
+    sphere::{self, Sphere},
+    sun, terrain, texture,
     vertex::Vertex,
+    vsop87,
 };
 
 pub struct Planets {
     instances: Vec<instance::Instance>,
-    instance_buffer: wgpu::Buffer,
+    culling: culling::InstanceCulling,
     texture_container: texture::TextureContainer,
-    render_pipeline: wgpu::RenderPipeline,
-    ring: ring::Ring,
+    texture_loader: texture::PlanetTextureLoader,
+    material: material::Material,
+    sphere: Sphere,
+    clouds: clouds::Clouds,
+    terrain: terrain::Terrain,
+    /// Body drawn with the terrain instead of its sphere
+    terrain_index: Option<usize>,
+    ephemerides: Vec<Box<dyn Ephemeris>>,
+    ephemeris_index: usize,
+    /// Replaces the ephemerides while it is running
+    nbody: Option<nbody::NBodySimulation>,
+    nbody_scenario: nbody::Scenario,
+    nbody_integrator: nbody::Integrator,
 }
 
 impl Planets {
@@ -30,6 +41,7 @@ impl Planets {
 
     const PLANETS_SCALE: [f32; Self::PLANETS_COUNT] = [0.5, 0.7, 1.3, 1.0, 3.0, 2.5, 1.8, 1.8];
 
+    /// Angles on the circular orbits of the artistic layout
     const INITIAL_OFFSET: [f32; Self::PLANETS_COUNT] = [
         f32::consts::FRAC_PI_4 * 3.0,
         f32::consts::FRAC_PI_4 * 7.0,
@@ -41,145 +53,438 @@ impl Planets {
         f32::consts::FRAC_PI_4,
     ];
 
-    const SATURN_INDEX: usize = 5;
+    /// Layer of the texture array holding an emissive map shown on the night side of a body,
+    /// see [`texture::TextureContainer::initialize_plantes_texture_array_container`]
+    const NIGHT_TEXTURE_INDEX: [Option<u32>; Self::PLANETS_COUNT] =
+        [None, None, Some(8), None, None, None, None, None];
+
+    /// Width of the day/night transition. Bodies with thick atmospheres get a wider band.
+    const TWILIGHT_BAND: [f32; Self::PLANETS_COUNT] =
+        [0.04, 0.3, 0.2, 0.08, 0.15, 0.15, 0.15, 0.15];
+
+    /// Layers of the surface maps array holding tangent space normal maps and specular maps,
+    /// see [`texture::TextureContainer::initialize_plantes_texture_array_container`]
+    const NORMAL_MAP_INDEX: [Option<u32>; Self::PLANETS_COUNT] =
+        [Some(1), None, None, Some(0), None, None, None, None];
+    const SPECULAR_MAP_INDEX: [Option<u32>; Self::PLANETS_COUNT] =
+        [None, None, Some(2), None, None, None, None, None];
+
+    /// Ring profiles are based on the radial structure measured by Voyager and Galileo, the
+    /// poles are the IAU rotation poles of the planets
+    const RINGS: [Option<ring::RingParameters>; Self::PLANETS_COUNT] = [
+        None,
+        None,
+        None,
+        None,
+        Some(ring::RingParameters {
+            name: "jupiter",
+            texture: include_bytes!("../assets/textures/jupiter_ring.png"),
+            inner_radius: 1.25,
+            outer_radius: 3.2,
+            pole: [268.057, 64.495],
+            forward_scattering: 0.9,
+            particle_density: 1.0,
+        }),
+        Some(ring::RingParameters {
+            name: "saturn",
+            texture: include_bytes!("../assets/textures/saturn_ring.png"),
+            inner_radius: 1.2,
+            outer_radius: 2.5,
+            pole: [40.589, 83.537],
+            forward_scattering: 0.2,
+            particle_density: 1.0,
+        }),
+        Some(ring::RingParameters {
+            name: "uranus",
+            texture: include_bytes!("../assets/textures/uranus_ring.png"),
+            inner_radius: 1.6,
+            outer_radius: 2.05,
+            pole: [257.311, -15.175],
+            forward_scattering: 0.3,
+            particle_density: 1.0,
+        }),
+        Some(ring::RingParameters {
+            name: "neptune",
+            texture: include_bytes!("../assets/textures/neptune_ring.png"),
+            inner_radius: 1.65,
+            outer_radius: 2.6,
+            pole: [299.36, 43.46],
+            forward_scattering: 0.7,
+            particle_density: 1.0,
+        }),
+    ];
+
+    /// Bodies with a solid surface which can be seen from up close. The elevations span the
+    /// MESSENGER and MOLA global topography, relative to the mean radius.
+    const TERRAINS: [Option<terrain::TerrainParameters>; Self::PLANETS_COUNT] = [
+        Some(terrain::TerrainParameters {
+            name: "mercury",
+            elevation_km: [-5.4, 4.5],
+        }),
+        None,
+        None,
+        Some(terrain::TerrainParameters {
+            name: "mars",
+            elevation_km: [-8.2, 21.2],
+        }),
+        None,
+        None,
+        None,
+        None,
+    ];
+
+    /// Real mean distances from the sun and mean radii, used by [`scale::SystemScale`]
+    const SEMI_MAJOR_AXIS_AU: [f64; Self::PLANETS_COUNT] =
+        [0.387, 0.723, 1.0, 1.524, 5.203, 9.537, 19.19, 30.07];
+    const RADIUS_KM: [f64; Self::PLANETS_COUNT] = [
+        2439.7, 6051.8, 6371.0, 3389.5, 69911.0, 58232.0, 25362.0, 24622.0,
+    ];
+    /// In solar masses, Earth includes the Moon
+    pub const MASS: [f64; Self::PLANETS_COUNT] = [
+        1.6601e-7, 2.4478e-6, 3.0404e-6, 3.2272e-7, 9.5479e-4, 2.8589e-4, 4.3662e-5, 5.1514e-5,
+    ];
+
+    /// At true scale even Jupiter is smaller than a pixel for most of the views
+    const SIZE_EXAGGERATION: f64 = 500.0;
+
+    const EARTH_INDEX: usize = 2;
+    const JUPITER_INDEX: usize = 4;
 
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        hdr: &hdr::HdrPipeline,
+        materials: &mut material::PipelineCache,
         camera_container: &camera::CameraContainer,
         sun: &sun::Sun,
+        sphere: &Sphere,
     ) -> Self {
         let instances = (0..Self::PLANETS_COUNT)
             .map(|i| {
                 let initial_offset = Self::INITIAL_OFFSET[i];
                 let radius = Self::PLANETS_RADIUS[i];
-                let position = glam::Vec3::new(
-                    radius * initial_offset.cos(),
+                let position = glam::DVec3::new(
+                    radius as f64 * (initial_offset as f64).cos(),
                     0.0,
-                    radius * initial_offset.sin(),
+                    radius as f64 * (initial_offset as f64).sin(),
                 );
                 let rotation = glam::Quat::from_rotation_y(0.0);
                 Instance::new(position, rotation, i as _, Self::PLANETS_SCALE[i])
+                    .with_night_side(Self::NIGHT_TEXTURE_INDEX[i], Self::TWILIGHT_BAND[i])
+                    .with_surface_maps(Self::NORMAL_MAP_INDEX[i], Self::SPECULAR_MAP_INDEX[i])
             })
             .collect::<Vec<_>>();
 
-        let instance_data = instances
-            .iter()
-            .map(instance::InstanceRaw::from)
-            .collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
+        let culling = culling::InstanceCulling::new(device, instances.len(), "Planets");
 
-        let texture_container =
-            texture::TextureContainer::initialize_plantes_texture_array_container(device, queue);
+        let (texture_container, texture_loader) =
+            texture::TextureContainer::initialize_plantes_texture_array_container(
+                device, queue, materials,
+            );
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_container.bind_group_layout,
-                    &camera_container.camera_bind_group_layout,
-                    &sun.light().bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-        let shader = wgpu::include_wgsl!("../shaders/planet.wgsl");
-        let render_pipeline = pipeline::create_render_pipeline(
+        let clouds = clouds::Clouds::new(
             device,
-            &render_pipeline_layout,
-            hdr.format(),
-            Some(texture::Texture::DEPTH_FORMAT),
-            &[sphere::SphereVertex::desc(), instance::InstanceRaw::desc()],
-            wgpu::PrimitiveTopology::TriangleList,
-            shader,
-            Some("render_pipeline_planets"),
+            materials,
+            camera_container,
+            sun,
+            sphere,
+            &instances[Self::EARTH_INDEX],
         );
 
-        let ring = ring::Ring::new(device, queue, hdr, camera_container, sun);
+        let terrain = terrain::Terrain::new(
+            device,
+            queue,
+            materials,
+            camera_container,
+            sun,
+            &texture_container,
+            Self::TERRAINS.iter().flatten(),
+        );
+
+        let material = materials.material(
+            device,
+            material::MaterialDescriptor {
+                name: "planets",
+                shader: wgpu::include_wgsl!("../shaders/planet.wgsl"),
+                bind_groups: &[
+                    (
+                        material::BindGroupSlot::Object,
+                        &texture_container.bind_group_layout,
+                    ),
+                    (
+                        material::BindGroupSlot::Camera,
+                        &camera_container.camera_bind_group_layout,
+                    ),
+                    (
+                        material::BindGroupSlot::Light,
+                        &sun.light().bind_group_layout,
+                    ),
+                    (material::BindGroupSlot::Object, &clouds.bind_group_layout),
+                ],
+                vertex_layouts: &[sphere::SphereVertex::desc(), instance::InstanceRaw::desc()],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                blending: material::Blending::Opaque,
+            },
+        );
+
+        let mut ephemerides: Vec<Box<dyn Ephemeris>> = vec![
+            Box::new(Self::artistic_orbits()),
+            Box::new(ephemeris::KeplerOrbits),
+        ];
+        match vsop87::Vsop87::from_env() {
+            Ok(vsop87) => ephemerides.push(Box::new(vsop87)),
+            Err(e) => log::warn!("VSOP87 ephemeris is not available: {e:#}"),
+        }
 
         Planets {
             instances,
-            instance_buffer,
+            culling,
             texture_container,
-            render_pipeline,
-            ring,
+            texture_loader,
+            material,
+            sphere: sphere.clone(),
+            clouds,
+            terrain,
+            terrain_index: None,
+            ephemerides,
+            ephemeris_index: 0,
+            nbody: None,
+            nbody_scenario: nbody::Scenario::Standard,
+            nbody_integrator: nbody::Integrator::Leapfrog,
         }
     }
 
-    pub fn update(&mut self, total_time: Duration) {
-        let t = total_time.as_secs_f32();
-        for (i, instance) in self.instances.iter_mut().enumerate() {
-            let radius = Self::PLANETS_RADIUS[i];
-            let offset = Self::INITIAL_OFFSET[i];
-            let is_saturn = i == Self::SATURN_INDEX;
-            let i = i as f32;
-
-            let movement_speed = 0.15 - 0.015 * i - 0.0002 * i * i;
-            let movement_angle = t * movement_speed + offset;
-            instance.position = glam::Vec3::new(
-                radius * movement_angle.cos(),
-                0.0,
-                radius * movement_angle.sin(),
+    /// Swaps in the textures and height maps decoded since the last frame
+    pub fn load_textures(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.texture_loader
+            .update(device, queue, &mut self.texture_container);
+        self.terrain.load_heightmaps(device, queue);
+    }
+
+    /// Texture layers and height maps which are still being decoded
+    pub fn pending_textures(&self) -> usize {
+        self.texture_loader.pending() + self.terrain.pending_heightmaps()
+    }
+
+    /// Speeds are chosen so that at the default clock rate the planets move at a pace
+    /// that is comfortable to watch
+    fn artistic_orbits() -> ephemeris::CircularOrbits {
+        let orbits = (0..Self::PLANETS_COUNT)
+            .map(|i| {
+                let i_f = i as f64;
+                let speed_per_second = 0.15 - 0.015 * i_f - 0.0002 * i_f * i_f;
+                ephemeris::CircularOrbit {
+                    radius_au: Self::SEMI_MAJOR_AXIS_AU[i],
+                    initial_angle: Self::INITIAL_OFFSET[i] as f64,
+                    angular_speed: speed_per_second / SimulationClock::DEFAULT_DAYS_PER_SECOND,
+                }
+            })
+            .collect();
+        ephemeris::CircularOrbits::new(orbits)
+    }
+
+    /// Cycles through the ephemerides, followed by the N-body simulation
+    pub fn switch_ephemeris(&mut self, julian_date: f64) {
+        if self.nbody.take().is_some() {
+            self.ephemeris_index = 0;
+        } else if self.ephemeris_index + 1 < self.ephemerides.len() {
+            self.ephemeris_index += 1;
+        } else {
+            self.start_nbody(julian_date);
+        }
+    }
+
+    pub fn ephemeris_name(&self) -> String {
+        match &self.nbody {
+            Some(nbody) => format!(
+                "{} ({:?}, {:?})",
+                nbody.name(),
+                self.nbody_scenario,
+                nbody.integrator()
+            ),
+            None => self.ephemerides[self.ephemeris_index].name().to_owned(),
+        }
+    }
+
+    /// The simulation starts from the Kepler orbits at `julian_date`
+    fn start_nbody(&mut self, julian_date: f64) {
+        let mut simulation = nbody::NBodySimulation::from_ephemeris(
+            &ephemeris::KeplerOrbits,
+            1.0,
+            &Self::MASS,
+            julian_date,
+            self.nbody_integrator,
+        );
+        simulation.apply_scenario(self.nbody_scenario, Self::JUPITER_INDEX);
+        self.nbody = Some(simulation);
+    }
+
+    /// Restarts the N-body simulation with the next scenario, or keeps it for the next start
+    /// when another ephemeris is in use
+    pub fn next_nbody_scenario(&mut self, julian_date: f64) {
+        self.nbody_scenario = self.nbody_scenario.next();
+        self.restart_nbody(julian_date);
+    }
+
+    /// Restarts the N-body simulation with the next integrator, so that integrators can be
+    /// compared starting from the same state. Like the scenario, it is kept for the next start
+    /// when another ephemeris is in use.
+    pub fn next_nbody_integrator(&mut self, julian_date: f64) {
+        if let Some(nbody) = &self.nbody {
+            log::info!(
+                "Relative energy drift with {:?}: {:e}",
+                nbody.integrator(),
+                nbody.energy_drift()
             );
+        }
+        self.nbody_integrator = self.nbody_integrator.next();
+        self.restart_nbody(julian_date);
+    }
 
-            if !is_saturn {
-                let rotation_speed = 0.5 - 0.05 * i;
-                let rotation_angle = t * rotation_speed;
-                instance.rotation = glam::Quat::from_rotation_y(rotation_angle);
-            }
+    fn restart_nbody(&mut self, julian_date: f64) {
+        if self.nbody.is_some() {
+            self.start_nbody(julian_date);
+        } else {
+            log::info!(
+                "N-body simulation will start with {:?}, {:?}",
+                self.nbody_scenario,
+                self.nbody_integrator
+            );
         }
     }
 
-    pub fn sync_instance_buffer(&self, queue: &wgpu::Queue) {
-        let instance_saturn = &self.instances[Self::SATURN_INDEX];
-        self.ring.update_instance(instance_saturn, queue);
-        let instance_data = self
-            .instances
+    pub fn culling(&self) -> &culling::InstanceCulling {
+        &self.culling
+    }
+
+    pub fn terrain(&self) -> &terrain::Terrain {
+        &self.terrain
+    }
+
+    pub fn clouds(&self) -> &clouds::Clouds {
+        &self.clouds
+    }
+
+    /// Planets with rings, along with the parameters of their rings
+    pub fn rings(&self) -> impl Iterator<Item = (&Instance, &'static ring::RingParameters)> {
+        let rings: &'static [Option<ring::RingParameters>] = &Self::RINGS;
+        self.instances
             .iter()
-            .map(instance::InstanceRaw::from)
-            .collect::<Vec<_>>();
-        queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&instance_data),
-        );
+            .zip(rings)
+            .filter_map(|(instance, ring)| Some((instance, ring.as_ref()?)))
     }
-}
 
-pub trait DrawPlanets<'a> {
-    fn draw_planets(
-        &mut self,
-        planets: &'a Planets,
-        sphere: &'a Sphere,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    );
-}
+    /// Distance from the sun in the artistic layout of a body `distance_au` away from the
+    /// sun, interpolated between the orbits of the planets, so that bodies which are not
+    /// planets keep their place relative to them
+    pub fn artistic_distance(distance_au: f64) -> f32 {
+        let au = &Self::SEMI_MAJOR_AXIS_AU;
+        let radius = &Self::PLANETS_RADIUS;
+        if distance_au <= au[0] {
+            return (radius[0] as f64 * distance_au / au[0]) as f32;
+        }
+        // Beyond Neptune the spacing between the last two orbits is extrapolated
+        let segment = (1..Self::PLANETS_COUNT)
+            .find(|&i| distance_au <= au[i])
+            .unwrap_or(Self::PLANETS_COUNT - 1);
+        let t = (distance_au - au[segment - 1]) / (au[segment] - au[segment - 1]);
+        (radius[segment - 1] as f64 + (radius[segment] - radius[segment - 1]) as f64 * t) as f32
+    }
+
+    fn dimensions(i: usize) -> scale::BodyDimensions {
+        scale::BodyDimensions {
+            artistic_distance: Self::PLANETS_RADIUS[i],
+            artistic_radius: Self::PLANETS_SCALE[i],
+            distance_au: Self::SEMI_MAJOR_AXIS_AU[i],
+            radius_km: Self::RADIUS_KM[i],
+            exaggeration: Self::SIZE_EXAGGERATION,
+        }
+    }
+
+    /// Spins follow the clock like the orbits, so they stop when it is paused
+    pub fn update(&mut self, clock: &SimulationClock, system_scale: &scale::SystemScale) {
+        let t = clock.animation_time();
+        if let Some(nbody) = &mut self.nbody {
+            nbody.advance_to(clock.julian_date());
+        }
+        let ephemeris: &dyn Ephemeris = match &self.nbody {
+            Some(nbody) => nbody,
+            None => self.ephemerides[self.ephemeris_index].as_ref(),
+        };
+        for (i, instance) in self.instances.iter_mut().enumerate() {
+            let dimensions = Self::dimensions(i);
+            let heliocentric =
+                ephemeris::ecliptic_to_scene(ephemeris.position(i, clock.julian_date()));
+            let distance_au = heliocentric.length();
+            instance.position =
+                heliocentric * (system_scale.distance(&dimensions, distance_au) / distance_au);
+            instance.set_scale(system_scale.radius(&dimensions));
 
-impl<'a, 'b> DrawPlanets<'b> for wgpu::RenderPass<'a>
-where
-    'b: 'a,
-{
-    fn draw_planets(
+            let rotation_speed = 0.5 - 0.05 * i as f32;
+            let rotation_angle = t * rotation_speed;
+            instance.rotation = glam::Quat::from_rotation_y(rotation_angle);
+        }
+        self.clouds.update(t, &self.instances[Self::EARTH_INDEX]);
+    }
+
+    /// `origin` is the camera position, all positions are sent relative to it
+    pub fn sync_instance_buffer(
         &mut self,
-        planets: &'b Planets,
-        sphere: &'b Sphere,
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
+        queue: &wgpu::Queue,
+        origin: glam::DVec3,
+        frustum: &culling::Frustum,
     ) {
-        self.set_pipeline(&planets.render_pipeline);
-        self.set_texture_array_container(&planets.texture_container);
-        self.set_vertex_buffer(1, planets.instance_buffer.slice(..));
-        self.draw_sphere_instanced(
-            sphere,
-            0..planets.instances.len() as _,
-            camera_bind_group,
-            light_bind_group,
+        let earth = &self.instances[Self::EARTH_INDEX];
+        self.clouds.sync_buffers(
+            queue,
+            earth,
+            origin,
+            Sphere::instance_lod(earth, origin, frustum),
         );
-        self.draw_ring(&planets.ring, camera_bind_group, light_bind_group);
+
+        // The terrain is drawn for the largest body on the screen which has one
+        self.terrain_index = self
+            .instances
+            .iter()
+            .enumerate()
+            .filter(|&(i, instance)| {
+                Self::TERRAINS[i].is_some()
+                    && terrain::Terrain::is_needed(instance, origin, frustum)
+            })
+            .min_by(|(_, a), (_, b)| {
+                let distance = |instance: &Instance| {
+                    instance.position.distance(origin) / instance.scale() as f64
+                };
+                distance(a).total_cmp(&distance(b))
+            })
+            .map(|(i, _)| i);
+        let terrain_body = self.terrain_index.map(|i| terrain::TerrainBody {
+            instance: &self.instances[i],
+            parameters: Self::TERRAINS[i].as_ref().unwrap(),
+            radius_km: Self::RADIUS_KM[i],
+        });
+        self.terrain
+            .sync_buffers(queue, terrain_body, origin, frustum);
+
+        let terrain_index = self.terrain_index;
+        self.culling.sync_buffers(
+            queue,
+            self.instances
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| Some(i) != terrain_index)
+                .map(|(_, instance)| instance),
+            origin,
+            frustum,
+        );
+    }
+}
+
+/// The terrain is drawn along with the planets, the clouds are transparent and drawn later
+impl material::Renderable for Planets {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &material::SceneBindGroups) {
+        let textures = &self.texture_container.bind_group;
+        pass.set_material(&self.material, scene, &[textures, &self.clouds.bind_group]);
+        self.culling.draw(pass, &self.sphere);
+        self.terrain.draw(pass, scene, textures);
     }
 }
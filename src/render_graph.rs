@@ -0,0 +1,399 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Result, bail};
+
+use crate::texture;
+
+/// Data passed between the passes of a frame. Textures are allocated by the graph, the
+/// other resources only order the passes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resource {
+    /// Instances of the bodies, animated and culled on the GPU
+    Bodies,
+    /// The scene before tonemapping
+    HdrColor,
+    Depth,
+    /// Texture of the window, provided every frame. Passes writing to it are the outputs of
+    /// the graph.
+    Surface,
+}
+
+/// Texture owned by the graph, it follows the size of the surface
+#[derive(Debug, Clone, Copy)]
+pub struct TextureDescriptor {
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    /// The size of the surface is divided by it, e.g. 2 for the first level of a bloom chain
+    pub downscale: u32,
+}
+
+/// Creates the bind group of a pass from the textures of the graph
+type BindGroupFactory = Box<dyn Fn(&wgpu::Device, &Textures) -> wgpu::BindGroup>;
+
+struct PassNode<P> {
+    pass: P,
+    reads: Vec<Resource>,
+    writes: Vec<Resource>,
+    enabled: bool,
+    bind_group: Option<(BindGroupFactory, wgpu::BindGroup)>,
+}
+
+impl<P> PassNode<P> {
+    /// Whether `self` has to run before `other`. A pass writing a resource runs before the
+    /// passes reading it. Passes which both read and write a resource (e.g. drawing over the
+    /// HDR texture) run after the passes only writing it and in the order they were added.
+    fn produces_for(&self, index: usize, other: &Self, other_index: usize) -> bool {
+        other.reads.iter().any(|read| {
+            self.writes.contains(read)
+                && (!self.reads.contains(read)
+                    || !other.writes.contains(read)
+                    || index < other_index)
+        })
+    }
+}
+
+/// Orders the passes of a frame, identified by `P`, and owns the textures they render to.
+/// Passes run after the passes writing what they read, those reading something no pass
+/// writes (e.g. because it is disabled) and those whose results are never read are skipped.
+pub struct RenderGraph<P> {
+    passes: Vec<PassNode<P>>,
+    descriptors: HashMap<Resource, TextureDescriptor>,
+    textures: Textures,
+    width: u32,
+    height: u32,
+}
+
+impl<P: Copy + PartialEq + std::fmt::Debug> RenderGraph<P> {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            passes: Vec::new(),
+            descriptors: HashMap::new(),
+            textures: Textures(HashMap::new()),
+            width,
+            height,
+        }
+    }
+
+    pub fn add_texture(
+        &mut self,
+        device: &wgpu::Device,
+        resource: Resource,
+        descriptor: TextureDescriptor,
+    ) {
+        let texture = Self::create_texture(device, resource, &descriptor, self.width, self.height);
+        self.descriptors.insert(resource, descriptor);
+        self.textures.0.insert(resource, texture);
+    }
+
+    /// Fails if the pass depends on its own results through other passes
+    pub fn add_pass(&mut self, pass: P, reads: &[Resource], writes: &[Resource]) -> Result<()> {
+        self.passes.push(PassNode {
+            pass,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            enabled: true,
+            bind_group: None,
+        });
+        let all: Vec<usize> = (0..self.passes.len()).collect();
+        if let Err(e) = self.sort(&all) {
+            self.passes.pop();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Sets the bind group `pass` gets in [`FrameResources::bind_group`], `create` is called
+    /// again whenever the textures are reallocated
+    pub fn set_bind_group(
+        &mut self,
+        device: &wgpu::Device,
+        pass: P,
+        create: impl Fn(&wgpu::Device, &Textures) -> wgpu::BindGroup + 'static,
+    ) {
+        let bind_group = create(device, &self.textures);
+        self.node_mut(pass).bind_group = Some((Box::new(create), bind_group));
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        resource: Resource,
+        descriptor: &TextureDescriptor,
+        width: u32,
+        height: u32,
+    ) -> texture::Texture {
+        texture::Texture::create_2d_texture(
+            device,
+            (width / descriptor.downscale).max(1),
+            (height / descriptor.downscale).max(1),
+            descriptor.format,
+            descriptor.usage,
+            wgpu::FilterMode::Nearest,
+            Some(&format!("RenderGraph::{resource:?}")),
+        )
+    }
+
+    /// Reallocates every texture and recreates the bind groups of the passes
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        for (resource, descriptor) in &self.descriptors {
+            let texture = Self::create_texture(device, *resource, descriptor, width, height);
+            self.textures.0.insert(*resource, texture);
+        }
+        for node in &mut self.passes {
+            if let Some((create, bind_group)) = &mut node.bind_group {
+                *bind_group = create(device, &self.textures);
+            }
+        }
+    }
+
+    fn node_mut(&mut self, pass: P) -> &mut PassNode<P> {
+        self.passes
+            .iter_mut()
+            .find(|node| node.pass == pass)
+            .unwrap_or_else(|| panic!("{pass:?} is not a pass of the render graph"))
+    }
+
+    pub fn enabled(&self, pass: P) -> bool {
+        self.passes
+            .iter()
+            .any(|node| node.pass == pass && node.enabled)
+    }
+
+    pub fn set_enabled(&mut self, pass: P, enabled: bool) {
+        self.node_mut(pass).enabled = enabled;
+    }
+
+    /// Indices of `nodes` ordered so that every pass runs after the passes it depends on,
+    /// ties are kept in the order the passes were added
+    fn sort(&self, nodes: &[usize]) -> Result<Vec<usize>> {
+        let depends_on = |node: usize| {
+            nodes.iter().copied().filter(move |&other| {
+                other != node && self.passes[other].produces_for(other, &self.passes[node], node)
+            })
+        };
+        let mut sorted = Vec::with_capacity(nodes.len());
+        while sorted.len() < nodes.len() {
+            let next = nodes.iter().copied().find(|&node| {
+                !sorted.contains(&node) && depends_on(node).all(|other| sorted.contains(&other))
+            });
+            match next {
+                Some(node) => sorted.push(node),
+                None => {
+                    let cycle: Vec<P> = nodes
+                        .iter()
+                        .filter(|node| !sorted.contains(node))
+                        .map(|&node| self.passes[node].pass)
+                        .collect();
+                    bail!("The passes {cycle:?} depend on each other");
+                }
+            }
+        }
+        Ok(sorted)
+    }
+
+    /// Passes which run this frame, in order
+    pub fn schedule(&self) -> Vec<P> {
+        // Drops the passes reading something no remaining pass writes, until none is left
+        let mut runnable: Vec<usize> = (0..self.passes.len())
+            .filter(|&node| self.passes[node].enabled)
+            .collect();
+        loop {
+            let count = runnable.len();
+            let remaining = runnable.clone();
+            runnable.retain(|&node| {
+                let pass = &self.passes[node];
+                pass.reads.iter().all(|read| {
+                    remaining.iter().any(|&other| {
+                        other != node
+                            && self.passes[other].writes.contains(read)
+                            && self.passes[other].produces_for(other, pass, node)
+                    })
+                })
+            });
+            if runnable.len() == count {
+                break;
+            }
+        }
+        let sorted = self
+            .sort(&runnable)
+            .expect("Cycles are rejected when the passes are added");
+
+        // Walks back from the surface, keeping only the passes something depends on
+        let mut needed = HashSet::from([Resource::Surface]);
+        let mut scheduled: Vec<P> = sorted
+            .into_iter()
+            .rev()
+            .map(|node| &self.passes[node])
+            .filter(|node| {
+                let used = node.writes.iter().any(|write| needed.contains(write));
+                if used {
+                    needed.extend(node.reads.iter().copied());
+                }
+                used
+            })
+            .map(|node| node.pass)
+            .collect();
+        scheduled.reverse();
+        scheduled
+    }
+
+    /// Runs the scheduled passes, `run` records the commands of a pass
+    pub fn execute(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        surface: &wgpu::TextureView,
+        mut run: impl FnMut(P, &mut wgpu::CommandEncoder, &FrameResources),
+    ) {
+        for pass in self.schedule() {
+            let resources = FrameResources {
+                textures: &self.textures,
+                surface,
+                bind_group: self
+                    .passes
+                    .iter()
+                    .find(|node| node.pass == pass)
+                    .and_then(|node| node.bind_group.as_ref())
+                    .map(|(_, bind_group)| bind_group),
+            };
+            run(pass, encoder, &resources);
+        }
+    }
+}
+
+/// Textures allocated by the graph
+pub struct Textures(HashMap<Resource, texture::Texture>);
+
+impl Textures {
+    /// Panics if `resource` is not a texture of the graph
+    pub fn get(&self, resource: Resource) -> &texture::Texture {
+        self.0
+            .get(&resource)
+            .unwrap_or_else(|| panic!("{resource:?} is not a texture of the render graph"))
+    }
+}
+
+/// Textures of the graph and the surface for the current frame
+pub struct FrameResources<'a> {
+    textures: &'a Textures,
+    surface: &'a wgpu::TextureView,
+    bind_group: Option<&'a wgpu::BindGroup>,
+}
+
+impl FrameResources<'_> {
+    /// Panics if `resource` is not a texture
+    pub fn view(&self, resource: Resource) -> &wgpu::TextureView {
+        match resource {
+            Resource::Surface => self.surface,
+            _ => &self.textures.get(resource).view,
+        }
+    }
+
+    /// Panics if the pass has no bind group, see [`RenderGraph::set_bind_group`]
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        self.bind_group
+            .expect("The pass has no bind group in the render graph")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Pass {
+        Simulation,
+        Scene,
+        LensFlare,
+        Bloom,
+        Tonemapping,
+    }
+
+    /// The passes of the app, added in reverse
+    fn graph() -> RenderGraph<Pass> {
+        let mut graph = RenderGraph::new(1, 1);
+        graph
+            .add_pass(
+                Pass::Tonemapping,
+                &[Resource::HdrColor],
+                &[Resource::Surface],
+            )
+            .unwrap();
+        graph
+            .add_pass(
+                Pass::LensFlare,
+                &[Resource::HdrColor, Resource::Depth],
+                &[Resource::HdrColor],
+            )
+            .unwrap();
+        graph
+            .add_pass(
+                Pass::Scene,
+                &[Resource::Bodies],
+                &[Resource::HdrColor, Resource::Depth],
+            )
+            .unwrap();
+        graph
+            .add_pass(Pass::Simulation, &[], &[Resource::Bodies])
+            .unwrap();
+        graph
+    }
+
+    #[test]
+    fn passes_run_after_what_they_read() {
+        assert_eq!(
+            graph().schedule(),
+            [
+                Pass::Simulation,
+                Pass::Scene,
+                Pass::LensFlare,
+                Pass::Tonemapping
+            ]
+        );
+    }
+
+    #[test]
+    fn passes_drawing_over_a_texture_keep_their_order() {
+        let mut graph = graph();
+        graph
+            .add_pass(Pass::Bloom, &[Resource::HdrColor], &[Resource::HdrColor])
+            .unwrap();
+        assert_eq!(
+            graph.schedule(),
+            [
+                Pass::Simulation,
+                Pass::Scene,
+                Pass::LensFlare,
+                Pass::Bloom,
+                Pass::Tonemapping
+            ]
+        );
+    }
+
+    #[test]
+    fn disabled_and_unused_passes_are_skipped() {
+        let mut graph = graph();
+        graph.set_enabled(Pass::LensFlare, false);
+        assert_eq!(
+            graph.schedule(),
+            [Pass::Simulation, Pass::Scene, Pass::Tonemapping]
+        );
+
+        graph.set_enabled(Pass::Simulation, false);
+        assert!(graph.schedule().is_empty());
+
+        graph.set_enabled(Pass::Simulation, true);
+        graph.set_enabled(Pass::Tonemapping, false);
+        assert!(graph.schedule().is_empty());
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let mut graph = graph();
+        graph
+            .add_pass(Pass::Bloom, &[Resource::Surface], &[Resource::Bodies])
+            .unwrap_err();
+        // The rejected pass is not kept
+        assert_eq!(graph.schedule().len(), 4);
+    }
+}
@@ -1,31 +1,78 @@
-use std::mem;
+use std::{io::Cursor, mem};
 
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
 use wgpu::util::DeviceExt;
 
 use crate::{
-    camera, hdr, instance, pipeline, sun,
-    texture::{self, SetTextureContainer},
+    background::BackgroundLoader,
+    camera, ephemeris, instance,
+    material::{self, SetMaterial},
+    sun, texture,
     vertex::Vertex,
 };
 
-pub struct Ring {
+/// Describes the rings of a body, radii are relative to the radius of the body
+pub struct RingParameters {
+    /// Identifies the texture of the rings, has to be unique
+    pub name: &'static str,
+    /// Radial profile, from the inner edge (left) to the outer edge (right). Rows follow the
+    /// longitude, so that incomplete rings (like the arcs of Neptune) can be drawn.
+    pub texture: &'static [u8],
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    /// Right ascension and declination (J2000, in degrees) of the pole of the ring plane,
+    /// which sets the tilt of the rings independently of the rotation of the body
+    pub pole: [f64; 2],
+    /// Share of the light scattered forward, i.e. towards the camera when the rings are
+    /// back-lit. Fine dust scatters mostly forward, large icy particles mostly back.
+    pub forward_scattering: f32,
+    /// Scales the optical depth of the texture, values above 1.0 make the rings more opaque
+    pub particle_density: f32,
+}
+
+impl RingParameters {
+    /// Rotation from the plane of the ring mesh (XZ) to the ring plane in the scene
+    fn tilt(&self) -> glam::Quat {
+        let [right_ascension, declination] = self.pole.map(f64::to_radians);
+        let (sin_ra, cos_ra) = right_ascension.sin_cos();
+        let (sin_dec, cos_dec) = declination.sin_cos();
+        let equatorial = glam::DVec3::new(cos_dec * cos_ra, cos_dec * sin_ra, sin_dec);
+        let pole =
+            ephemeris::ecliptic_to_scene(ephemeris::equatorial_to_ecliptic(equatorial)).as_vec3();
+        glam::Quat::from_rotation_arc(glam::Vec3::Y, pole.normalize())
+    }
+}
+
+/// Rings of all bodies, drawn with a single instanced draw call. Every ring has its own
+/// layer in a texture array, radii and scattering are sent with the instances.
+pub struct RingSystem {
+    /// Names of the rings, in the order of the texture layers
+    names: Vec<&'static str>,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     num_elements: u32,
-    render_pipeline: wgpu::RenderPipeline,
+    material: material::Material,
     texture_container: texture::TextureContainer,
+    /// Layer and image of every ring texture, decoded and resized in the background
+    texture_loader: BackgroundLoader<(u32, Result<image::RgbaImage>)>,
     instance_buffer: wgpu::Buffer,
+    instances_count: u32,
 }
 
-impl Ring {
-    pub fn new(
+impl RingSystem {
+    const SEGMENTS: usize = 128;
+
+    pub fn new<'p>(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        hdr: &hdr::HdrPipeline,
+        materials: &mut material::PipelineCache,
         camera_container: &camera::CameraContainer,
         sun: &sun::Sun,
-    ) -> Self {
-        let (vertices, indices) = Self::generate_ring_vertices(1.2, 2.5, 128);
+        rings: impl IntoIterator<Item = &'p RingParameters>,
+    ) -> Result<Self> {
+        let rings = rings.into_iter().collect::<Vec<_>>();
+        let (vertices, indices) = Self::generate_ring_vertices(Self::SEGMENTS);
 
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("sphere_vertex_buffer"),
@@ -38,107 +85,165 @@ impl Ring {
             usage: wgpu::BufferUsages::INDEX,
         });
 
-        let texture_bytes = include_bytes!("../assets/textures/saturn_ring.png");
-        let texture =
-            texture::Texture::from_bytes(device, queue, texture_bytes, "saturn ring texture")
-                .unwrap();
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-        let texture_bind_group: wgpu::BindGroup =
-            device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                    },
-                ],
-                label: Some("diffuse_bind_group"),
+        // Layers of a texture array must have the same size, so all textures are resized
+        // to the largest one. Only the headers are read here, the layers stay transparent
+        // until the images are decoded.
+        let sizes = rings
+            .iter()
+            .map(|ring| {
+                image::ImageReader::new(Cursor::new(ring.texture))
+                    .with_guessed_format()?
+                    .into_dimensions()
+                    .with_context(|| format!("Failed to load the {} ring texture", ring.name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let width = sizes.iter().map(|(width, _)| *width).max().unwrap_or(1);
+        let height = sizes.iter().map(|(_, height)| *height).max().unwrap_or(1);
+        let placeholders = vec![image::RgbaImage::new(width, height); rings.len()];
+        let texture = texture::Texture::create_texture_array_from_images(
+            device,
+            queue,
+            &placeholders,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            "rings-textures",
+        );
+        let mut texture_loader = BackgroundLoader::new();
+        for (layer, ring) in rings.iter().enumerate() {
+            let (name, bytes) = (ring.name, ring.texture);
+            texture_loader.spawn(move || {
+                let image = image::load_from_memory(bytes)
+                    .with_context(|| format!("Failed to load the {name} ring texture"))
+                    .map(|image| {
+                        image::imageops::resize(&image, width, height, FilterType::Triangle)
+                    });
+                (layer as u32, image)
             });
+        }
+        let texture_bind_group_layout = materials.bind_group_layout(
+            device,
+            "texture_array_bind_group_layout",
+            &[
+                material::Binding::fragment_texture(wgpu::TextureViewDimension::D2Array),
+                material::Binding::FRAGMENT_SAMPLER,
+            ],
+        );
+        let texture_bind_group = material::create_bind_group(
+            device,
+            "texture_array_bind_group",
+            &texture_bind_group_layout,
+            &[
+                wgpu::BindingResource::TextureView(&texture.view),
+                wgpu::BindingResource::Sampler(&texture.sampler),
+            ],
+        );
         let texture_container =
             texture::TextureContainer::new(texture, texture_bind_group, texture_bind_group_layout);
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_container.bind_group_layout,
-                    &camera_container.camera_bind_group_layout,
-                    &sun.light().bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-        let shader = wgpu::include_wgsl!("../shaders/ring.wgsl");
-        let render_pipeline = pipeline::create_render_pipeline_without_culling(
+        let material = materials.material(
             device,
-            &render_pipeline_layout,
-            hdr.format(),
-            Some(texture::Texture::DEPTH_FORMAT),
-            &[RingVertex::desc(), instance::InstanceRaw::desc()],
-            wgpu::PrimitiveTopology::TriangleList,
-            shader,
-            Some("render_pipeline_ring"),
+            material::MaterialDescriptor {
+                name: "ring",
+                shader: wgpu::include_wgsl!("../shaders/ring.wgsl"),
+                bind_groups: &[
+                    (
+                        material::BindGroupSlot::Object,
+                        &texture_container.bind_group_layout,
+                    ),
+                    (
+                        material::BindGroupSlot::Camera,
+                        &camera_container.camera_bind_group_layout,
+                    ),
+                    (
+                        material::BindGroupSlot::Light,
+                        &sun.light().bind_group_layout,
+                    ),
+                ],
+                vertex_layouts: &[RingVertex::desc(), RingInstanceRaw::desc()],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                // Rings are seen from both sides and are partly transparent (e.g. the
+                // Cassini division)
+                blending: material::Blending::Transparent {
+                    blend: wgpu::BlendState::ALPHA_BLENDING,
+                    cull_mode: None,
+                },
+            },
         );
 
-        let instance = instance::Instance::default();
-
-        let instance_data = vec![instance::InstanceRaw::from(&instance)];
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Ring Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
+            size: (rings.len().max(1) * mem::size_of::<RingInstanceRaw>()) as wgpu::BufferAddress,
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
-        Ring {
+        Ok(Self {
+            names: rings.iter().map(|ring| ring.name).collect(),
             vertex_buffer,
             index_buffer,
             num_elements: indices.len() as _,
             texture_container,
-            render_pipeline,
+            texture_loader,
+            material,
             instance_buffer,
+            instances_count: 0,
+        })
+    }
+
+    /// Writes the ring textures decoded since the last frame, rings which failed to load
+    /// stay transparent
+    pub fn load_textures(&mut self, queue: &wgpu::Queue) {
+        for (layer, image) in self.texture_loader.poll() {
+            match image {
+                Ok(image) => self
+                    .texture_container
+                    .texture()
+                    .write_layer(queue, layer, &image),
+                Err(e) => log::error!("{e:#}"),
+            }
         }
     }
 
-    pub fn update_instance(&self, instance: &instance::Instance, queue: &wgpu::Queue) {
-        let instance_data = vec![instance::InstanceRaw::from(instance)];
+    /// Ring textures which are still being decoded
+    pub fn pending_textures(&self) -> usize {
+        self.texture_loader.pending()
+    }
+
+    /// Rings are given along with the bodies they belong to, all of them must have been
+    /// passed to [`RingSystem::new`]. `origin` is the camera position, all positions are sent
+    /// relative to it.
+    pub fn sync_buffers<'i, 'p>(
+        &mut self,
+        queue: &wgpu::Queue,
+        rings: impl IntoIterator<Item = (&'i instance::Instance, &'p RingParameters)>,
+        origin: glam::DVec3,
+    ) {
+        let mut instances = rings
+            .into_iter()
+            .map(|(body, ring)| {
+                let texture_index = self
+                    .names
+                    .iter()
+                    .position(|&name| name == ring.name)
+                    .expect("Rings have to be registered in `RingSystem::new`");
+                let distance = body.position.distance_squared(origin);
+                let raw = RingInstanceRaw::new(body, ring, texture_index as u32, origin);
+                (distance, raw)
+            })
+            .collect::<Vec<_>>();
+        // Rings are blended, so the farther ones have to be drawn first
+        instances.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        let instance_data = instances.iter().map(|(_, raw)| *raw).collect::<Vec<_>>();
         queue.write_buffer(
             &self.instance_buffer,
             0,
             bytemuck::cast_slice(&instance_data),
         );
+        self.instances_count = instance_data.len() as _;
     }
 
-    fn generate_ring_vertices(
-        inner_radius: f32,
-        outer_radius: f32,
-        segments: usize,
-    ) -> (Vec<RingVertex>, Vec<u16>) {
+    /// Annulus between the unit circle and itself, the vertex shader moves the vertices to
+    /// the inner (`tex_coords.x == 0`) and outer (`tex_coords.x == 1`) radius of each ring
+    fn generate_ring_vertices(segments: usize) -> (Vec<RingVertex>, Vec<u16>) {
         let mut vertices = Vec::with_capacity(segments * 2);
         let mut indices = Vec::with_capacity(segments * 6);
 
@@ -147,21 +252,20 @@ impl Ring {
         for i in 0..=segments {
             let theta = (i as f32) / (segments as f32) * std::f32::consts::TAU;
             let (sin, cos) = theta.sin_cos();
+            let position = [cos, 0.0, sin];
 
             // Inner vertex
-            let inner_pos = [inner_radius * cos, 0.0, inner_radius * sin];
             let inner_uv = [0.0, i as f32 / segments as f32];
             vertices.push(RingVertex {
-                position: inner_pos,
+                position,
                 normal,
                 tex_coords: inner_uv,
             });
 
             // Outer vertex
-            let outer_pos = [outer_radius * cos, 0.0, outer_radius * sin];
             let outer_uv = [1.0, i as f32 / segments as f32];
             vertices.push(RingVertex {
-                position: outer_pos,
+                position,
                 normal,
                 tex_coords: outer_uv,
             });
@@ -222,32 +326,88 @@ impl Vertex for RingVertex {
     }
 }
 
-pub trait DrawRing<'a> {
-    fn draw_ring(
-        &mut self,
-        ring: &'a Ring,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    );
+/// The rings follow the position and size of their body, but not its rotation
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct RingInstanceRaw {
+    model_matrix: [[f32; 4]; 4],
+    radii: [f32; 2],
+    forward_scattering: f32,
+    particle_density: f32,
+    texture_index: u32,
 }
 
-impl<'a, 'b> DrawRing<'b> for wgpu::RenderPass<'a>
-where
-    'b: 'a,
-{
-    fn draw_ring(
-        &mut self,
-        ring: &'b Ring,
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    ) {
-        self.set_pipeline(&ring.render_pipeline);
-        self.set_texture_container(&ring.texture_container);
-        self.set_vertex_buffer(0, ring.vertex_buffer.slice(..));
-        self.set_vertex_buffer(1, ring.instance_buffer.slice(..));
-        self.set_index_buffer(ring.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        self.set_bind_group(1, camera_bind_group, &[]);
-        self.set_bind_group(2, light_bind_group, &[]);
-        self.draw_indexed(0..ring.num_elements, 0, 0..1);
+impl RingInstanceRaw {
+    fn new(
+        body: &instance::Instance,
+        ring: &RingParameters,
+        texture_index: u32,
+        origin: glam::DVec3,
+    ) -> Self {
+        let model_matrix = glam::Mat4::from_scale_rotation_translation(
+            glam::Vec3::splat(body.scale()),
+            ring.tilt(),
+            (body.position - origin).as_vec3(),
+        );
+        Self {
+            model_matrix: model_matrix.to_cols_array_2d(),
+            radii: [ring.inner_radius, ring.outer_radius],
+            forward_scattering: ring.forward_scattering,
+            particle_density: ring.particle_density,
+            texture_index,
+        }
+    }
+}
+
+impl Vertex for RingInstanceRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<RingInstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Radii, forward scattering and particle density
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
+}
+
+/// Rings are transparent, they have to be drawn after all opaque objects
+impl material::Renderable for RingSystem {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &material::SceneBindGroups) {
+        pass.set_material(&self.material, scene, &[&self.texture_container.bind_group]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        pass.draw_indexed(0..self.num_elements, 0, 0..self.instances_count);
     }
 }
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+/// How distances and sizes of the bodies are mapped to the scene
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// Hand-picked layout, where every body is easy to see
+    Artistic,
+    /// Real distances and real sizes
+    True,
+    /// Real distances, with bodies enlarged so that they stay visible
+    ExaggeratedSizes,
+    /// Distances compressed logarithmically, with artistic sizes
+    Logarithmic,
+}
+
+impl ScaleMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Artistic => Self::True,
+            Self::True => Self::ExaggeratedSizes,
+            Self::ExaggeratedSizes => Self::Logarithmic,
+            Self::Logarithmic => Self::Artistic,
+        }
+    }
+}
+
+/// Real and artistic dimensions of a single body
+pub struct BodyDimensions {
+    /// Distance from the sun in the artistic layout, in scene units
+    pub artistic_distance: f32,
+    /// Radius in the artistic layout, in scene units
+    pub artistic_radius: f32,
+    /// Mean distance from the sun
+    pub distance_au: f64,
+    pub radius_km: f64,
+    /// Enlargement used by [`ScaleMode::ExaggeratedSizes`]
+    pub exaggeration: f64,
+}
+
+/// Current scale mode, with an animated transition from the previous one
+pub struct SystemScale {
+    mode: ScaleMode,
+    previous_mode: ScaleMode,
+    /// Goes from 0.0 (previous mode) to 1.0 (current mode)
+    transition: f32,
+}
+
+impl SystemScale {
+    /// Scene units per astronomical unit, chosen so that Earth stays at the same distance
+    /// in the artistic and the true layouts
+    const UNITS_PER_AU: f64 = 25.0;
+    const KM_PER_AU: f64 = 149_597_870.7;
+    const TRANSITION_DURATION: Duration = Duration::from_secs(3);
+
+    pub fn new(mode: ScaleMode) -> Self {
+        Self {
+            mode,
+            previous_mode: mode,
+            transition: 1.0,
+        }
+    }
+
+    pub fn mode(&self) -> ScaleMode {
+        self.mode
+    }
+
+    /// Starts a transition to the next mode. Switching in the middle of a transition starts
+    /// from the mode the transition was heading to.
+    pub fn switch_to_next_mode(&mut self) {
+        self.previous_mode = self.mode;
+        self.mode = self.mode.next();
+        self.transition = 0.0;
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        self.transition =
+            (self.transition + dt.as_secs_f32() / Self::TRANSITION_DURATION.as_secs_f32()).min(1.0);
+    }
+
+    /// Distance from the sun in scene units, for a body currently `distance_au` away from
+    /// the sun (orbits are not circular)
+    pub fn distance(&self, body: &BodyDimensions, distance_au: f64) -> f64 {
+        self.interpolate(|mode| Self::distance_in_mode(mode, body, distance_au))
+    }
+
+    /// Radius, in scene units
+    pub fn radius(&self, body: &BodyDimensions) -> f32 {
+        self.interpolate(|mode| Self::radius_in_mode(mode, body)) as f32
+    }
+
+    fn distance_in_mode(mode: ScaleMode, body: &BodyDimensions, distance_au: f64) -> f64 {
+        match mode {
+            ScaleMode::Artistic => body.artistic_distance as f64 * distance_au / body.distance_au,
+            ScaleMode::True | ScaleMode::ExaggeratedSizes => distance_au * Self::UNITS_PER_AU,
+            // Keeps the ratio between neighbouring orbits, so that the outer planets fit
+            // on screen together with the inner ones
+            ScaleMode::Logarithmic => (1.0 + distance_au).log2() * Self::UNITS_PER_AU,
+        }
+    }
+
+    fn radius_in_mode(mode: ScaleMode, body: &BodyDimensions) -> f64 {
+        let true_radius = body.radius_km / Self::KM_PER_AU * Self::UNITS_PER_AU;
+        match mode {
+            ScaleMode::Artistic | ScaleMode::Logarithmic => body.artistic_radius as f64,
+            ScaleMode::True => true_radius,
+            ScaleMode::ExaggeratedSizes => true_radius * body.exaggeration,
+        }
+    }
+
+    /// Values differ by orders of magnitude between modes, so they are interpolated
+    /// geometrically, which makes the transition look uniform
+    fn interpolate(&self, value_in_mode: impl Fn(ScaleMode) -> f64) -> f64 {
+        let to = value_in_mode(self.mode);
+        if self.transition >= 1.0 {
+            return to;
+        }
+        let from = value_in_mode(self.previous_mode);
+        let t = self.transition as f64;
+        let t = t * t * (3.0 - 2.0 * t);
+        if from > 0.0 && to > 0.0 {
+            (from.ln() + (to.ln() - from.ln()) * t).exp()
+        } else {
+            from + (to - from) * t
+        }
+    }
+}
@@ -1,110 +1,457 @@
-use anyhow::Result;
+use std::path::{Path, PathBuf};
 
-use crate::{camera, hdr, pipeline, texture};
+use anyhow::{Context, Result, anyhow};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    background::BackgroundLoader,
+    camera, ephemeris, hdr,
+    material::{self, SetMaterial},
+    texture,
+};
+
+/// Image the skybox is made from
+#[derive(Debug, Clone)]
+pub enum SkyboxSource {
+    /// `assets/textures/skybox.hdr`, embedded in the executable
+    Bundled,
+    /// Equirectangular panorama (HDR, EXR or any other supported format)
+    Equirectangular(PathBuf),
+    /// Directory with six images named `px`, `nx`, `py`, `ny`, `pz` and `nz` (with any
+    /// extension), the faces of a cubemap
+    CubeFaces(PathBuf),
+}
+
+impl SkyboxSource {
+    /// A directory is read as cubemap faces, anything else as a panorama
+    pub fn from_path(path: &Path) -> Self {
+        if path.is_dir() {
+            Self::CubeFaces(path.to_owned())
+        } else {
+            Self::Equirectangular(path.to_owned())
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::Bundled => "bundled".to_owned(),
+            Self::Equirectangular(path) | Self::CubeFaces(path) => path.display().to_string(),
+        }
+    }
+
+    /// Reads and decodes the image, on any thread
+    fn decode(&self, size: u32) -> Result<SkyboxImage> {
+        match self {
+            Self::Bundled => Ok(SkyboxImage::Equirectangular(
+                hdr::HdrLoader::decode_equirectangular(include_bytes!(
+                    "../assets/textures/skybox.hdr"
+                ))?,
+            )),
+            Self::Equirectangular(path) => {
+                let bytes = std::fs::read(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                Ok(SkyboxImage::Equirectangular(
+                    hdr::HdrLoader::decode_equirectangular(&bytes)?,
+                ))
+            }
+            Self::CubeFaces(directory) => {
+                let faces = texture::CubeTexture::FACE_NAMES
+                    .map(|name| texture::CubeTexture::find_face(directory, name))
+                    .into_iter()
+                    .map(|path| {
+                        let path = path?;
+                        std::fs::read(&path)
+                            .with_context(|| format!("Failed to read {}", path.display()))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let faces: [&[u8]; 6] = std::array::from_fn(|i| faces[i].as_slice());
+                Ok(SkyboxImage::CubeFaces(hdr::HdrLoader::decode_cube_faces(
+                    faces, size,
+                )?))
+            }
+        }
+    }
+}
+
+/// Decoded image of a skybox, ready to be uploaded
+enum SkyboxImage {
+    Equirectangular(image::Rgba32FImage),
+    CubeFaces([image::Rgba32FImage; 6]),
+}
+
+impl SkyboxImage {
+    /// Black sky shown until the first skybox is decoded
+    fn placeholder() -> Self {
+        Self::Equirectangular(image::Rgba32FImage::from_pixel(
+            1,
+            1,
+            image::Rgba([0.0, 0.0, 0.0, 1.0]),
+        ))
+    }
+
+    fn upload(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        loader: &hdr::HdrLoader,
+        size: u32,
+    ) -> texture::CubeTexture {
+        match self {
+            Self::Equirectangular(image) => {
+                loader.equirectangular(device, queue, image, size, Some("Skybox"))
+            }
+            Self::CubeFaces(faces) => loader.cube_faces(device, queue, faces, Some("Skybox")),
+        }
+    }
+}
+
+/// Celestial frame the skybox image is drawn in. Its longitude 0 is at the center of a
+/// panorama (or the `px` face) and it increases to the left, as on sky maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkyboxFrame {
+    Ecliptic,
+    Equatorial,
+    /// Most Milky Way panoramas, with the galactic center in the middle
+    Galactic,
+}
+
+impl SkyboxFrame {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Ecliptic => Self::Equatorial,
+            Self::Equatorial => Self::Galactic,
+            Self::Galactic => Self::Ecliptic,
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ecliptic" => Some(Self::Ecliptic),
+            "equatorial" => Some(Self::Equatorial),
+            "galactic" => Some(Self::Galactic),
+            _ => None,
+        }
+    }
+
+    /// Converts a direction of the ecliptic frame to this frame
+    fn convert_ecliptic(self, direction: glam::DVec3) -> glam::DVec3 {
+        match self {
+            Self::Ecliptic => direction,
+            Self::Equatorial => ephemeris::ecliptic_to_equatorial(direction),
+            Self::Galactic => {
+                ephemeris::equatorial_to_galactic(ephemeris::ecliptic_to_equatorial(direction))
+            }
+        }
+    }
+}
 
 pub struct Skybox {
+    loader: hdr::HdrLoader,
+    /// Decodes the images of the skyboxes, along with their index in `sources`
+    image_loader: BackgroundLoader<(usize, Result<SkyboxImage>)>,
+    sources: Vec<SkyboxSource>,
+    current: usize,
+    /// Skybox which will replace the current one once it is decoded
+    requested: usize,
+    size: u32,
+    frame: SkyboxFrame,
+    /// Around the pole of the frame, in degrees
+    rotation: f64,
+    brightness: f32,
     _cubemap: texture::CubeTexture,
+    environment: hdr::EnvironmentMaps,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
-    render_pipeline: wgpu::RenderPipeline,
+    material: material::Material,
 }
 
 impl Skybox {
-    const DST_SIZE: u32 = 1080;
+    /// Environment variable with the image (or directory of faces) used at startup
+    const PATH_VARIABLE: &str = "SOLAR_SYSTEM_SKYBOX";
+    /// Environment variable with a directory of additional skyboxes to switch between
+    const DIRECTORY_VARIABLE: &str = "SOLAR_SYSTEM_SKYBOX_DIR";
+    const DEFAULT_DIRECTORY: &str = "assets/skyboxes";
+    const SIZE_VARIABLE: &str = "SOLAR_SYSTEM_SKYBOX_SIZE";
+    const DEFAULT_SIZE: u32 = 1080;
+    const FRAME_VARIABLE: &str = "SOLAR_SYSTEM_SKYBOX_FRAME";
+    const ROTATION_VARIABLE: &str = "SOLAR_SYSTEM_SKYBOX_ROTATION";
+    const BRIGHTNESS_VARIABLE: &str = "SOLAR_SYSTEM_SKYBOX_BRIGHTNESS";
+    const PANORAMA_EXTENSIONS: [&str; 5] = ["hdr", "exr", "png", "jpg", "jpeg"];
 
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        hdr: &hdr::HdrPipeline,
+        materials: &mut material::PipelineCache,
         camera_container: &camera::CameraContainer,
     ) -> Result<Self> {
-        let hdr_loader = hdr::HdrLoader::new(device);
-        let skybox_bytes = include_bytes!("../assets/textures/skybox.hdr");
-        let skybox_texture = hdr_loader.equirectangular_bytes(
+        let sources = Self::sources_from_env();
+        let size = Self::env_or(Self::SIZE_VARIABLE, Self::DEFAULT_SIZE);
+        let frame = match std::env::var(Self::FRAME_VARIABLE) {
+            Ok(name) => {
+                SkyboxFrame::parse(&name).ok_or_else(|| anyhow!("Unknown skybox frame {}", name))?
+            }
+            // The bundled skybox is a Milky Way panorama
+            Err(_) => SkyboxFrame::Galactic,
+        };
+        let rotation = Self::env_or(Self::ROTATION_VARIABLE, 0.0);
+        let brightness = Self::env_or(Self::BRIGHTNESS_VARIABLE, 1.0);
+
+        let loader = hdr::HdrLoader::new(device);
+        let cubemap = SkyboxImage::placeholder().upload(device, queue, &loader, size);
+        let environment = loader.environment_maps(device, queue, &cubemap);
+        let mut image_loader = BackgroundLoader::new();
+        let source = sources[0].clone();
+        image_loader.spawn(move || (0, source.decode(size)));
+
+        let uniform = SkyboxUniform::new(frame, rotation, brightness);
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = materials.bind_group_layout(
             device,
-            queue,
-            skybox_bytes,
-            Self::DST_SIZE,
-            Some("Skybox"),
-        )?;
-
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("environment_layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
+            "environment_layout",
+            &[
+                material::Binding::Texture {
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        view_dimension: wgpu::TextureViewDimension::Cube,
-                        multisampled: false,
-                    },
-                    count: None,
+                    dimension: wgpu::TextureViewDimension::Cube,
+                    filterable: false,
                 },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
+                material::Binding::Sampler {
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                    count: None,
-                },
-            ],
-        });
-
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("environment_bind_group"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(skybox_texture.view()),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(skybox_texture.sampler()),
+                    filtering: false,
                 },
+                material::Binding::Uniform(wgpu::ShaderStages::FRAGMENT),
             ],
-        });
+        );
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &cubemap, &buffer);
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Sky Pipeline Layout"),
-                bind_group_layouts: &[
-                    &camera_container.camera_bind_group_layout,
-                    &bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-        let shader = wgpu::include_wgsl!("../shaders/skybox.wgsl");
-        let render_pipeline = pipeline::create_render_pipeline(
+        let material = materials.material(
             device,
-            &render_pipeline_layout,
-            hdr.format(),
-            Some(texture::Texture::DEPTH_FORMAT),
-            &[],
-            wgpu::PrimitiveTopology::TriangleList,
-            shader,
-            Some("render_pipeline_skybox"),
+            material::MaterialDescriptor {
+                name: "skybox",
+                shader: wgpu::include_wgsl!("../shaders/skybox.wgsl"),
+                bind_groups: &[
+                    (
+                        material::BindGroupSlot::Camera,
+                        &camera_container.camera_bind_group_layout,
+                    ),
+                    (material::BindGroupSlot::Object, &bind_group_layout),
+                ],
+                vertex_layouts: &[],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                blending: material::Blending::Opaque,
+            },
         );
 
         Ok(Skybox {
-            _cubemap: skybox_texture,
+            loader,
+            image_loader,
+            sources,
+            current: 0,
+            requested: 0,
+            size,
+            frame,
+            rotation,
+            brightness,
+            _cubemap: cubemap,
+            environment,
+            buffer,
+            bind_group_layout,
             bind_group,
-            render_pipeline,
+            material,
         })
     }
+
+    /// `SOLAR_SYSTEM_SKYBOX` first if it is set, then the bundled skybox and everything
+    /// found in `SOLAR_SYSTEM_SKYBOX_DIR` (or `assets/skyboxes`)
+    fn sources_from_env() -> Vec<SkyboxSource> {
+        let mut sources = Vec::new();
+        if let Ok(path) = std::env::var(Self::PATH_VARIABLE) {
+            sources.push(SkyboxSource::from_path(Path::new(&path)));
+        }
+        sources.push(SkyboxSource::Bundled);
+
+        let directory = std::env::var(Self::DIRECTORY_VARIABLE)
+            .unwrap_or_else(|_| Self::DEFAULT_DIRECTORY.to_owned());
+        if let Ok(entries) = std::fs::read_dir(&directory) {
+            let mut paths: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.is_dir()
+                        || path.extension().is_some_and(|extension| {
+                            Self::PANORAMA_EXTENSIONS
+                                .iter()
+                                .any(|e| extension.eq_ignore_ascii_case(e))
+                        })
+                })
+                .collect();
+            paths.sort();
+            sources.extend(paths.iter().map(|path| SkyboxSource::from_path(path)));
+        }
+        sources
+    }
+
+    fn env_or<T: std::str::FromStr>(variable: &str, default: T) -> T {
+        std::env::var(variable)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        cubemap: &texture::CubeTexture,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        material::create_bind_group(
+            device,
+            "environment_bind_group",
+            layout,
+            &[
+                wgpu::BindingResource::TextureView(cubemap.view()),
+                wgpu::BindingResource::Sampler(cubemap.sampler()),
+                buffer.as_entire_binding(),
+            ],
+        )
+    }
+
+    /// Starts decoding the next skybox, the current one is shown until it is ready (and kept
+    /// if it fails)
+    pub fn next_source(&mut self) {
+        let next = (self.requested + 1) % self.sources.len();
+        let source = self.sources[next].clone();
+        let size = self.size;
+        self.image_loader.spawn(move || (next, source.decode(size)));
+        self.requested = next;
+    }
+
+    /// Swaps in the requested skybox once it is decoded. Returns whether it changed, the
+    /// lighting from the environment has to be updated then.
+    pub fn load_image(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        let mut changed = false;
+        for (index, image) in self.image_loader.poll() {
+            // Skyboxes skipped by switching quickly are dropped
+            if index != self.requested {
+                continue;
+            }
+            match image {
+                Ok(image) => {
+                    let cubemap = image.upload(device, queue, &self.loader, self.size);
+                    self.bind_group = Self::create_bind_group(
+                        device,
+                        &self.bind_group_layout,
+                        &cubemap,
+                        &self.buffer,
+                    );
+                    self.environment = self.loader.environment_maps(device, queue, &cubemap);
+                    self._cubemap = cubemap;
+                    self.current = index;
+                    changed = true;
+                    log::info!("Skybox: {}", self.source_name());
+                }
+                Err(e) => log::error!(
+                    "Failed to load skybox {}: {e:#}",
+                    self.sources[index].name()
+                ),
+            }
+        }
+        changed
+    }
+
+    /// Skyboxes which are still being decoded
+    pub fn pending_images(&self) -> usize {
+        self.image_loader.pending()
+    }
+
+    /// Lighting of the bodies by the skybox, in the frame of the image like the skybox
+    pub fn environment(&self) -> &hdr::EnvironmentMaps {
+        &self.environment
+    }
+
+    /// From the scene frame to the frame of the image
+    pub fn image_rotation(&self) -> glam::Mat4 {
+        image_rotation(self.frame, self.rotation)
+    }
+
+    pub fn source_name(&self) -> String {
+        self.sources[self.current].name()
+    }
+
+    pub fn frame(&self) -> SkyboxFrame {
+        self.frame
+    }
+
+    pub fn set_frame(&mut self, frame: SkyboxFrame) {
+        self.frame = frame;
+    }
+
+    pub fn rotation(&self) -> f64 {
+        self.rotation
+    }
+
+    pub fn set_rotation(&mut self, rotation: f64) {
+        self.rotation = rotation.rem_euclid(360.0);
+    }
+
+    pub fn brightness(&self) -> f32 {
+        self.brightness
+    }
+
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = brightness.max(0.0);
+    }
+
+    pub fn sync_buffer(&self, queue: &wgpu::Queue) {
+        let uniform = SkyboxUniform::new(self.frame, self.rotation, self.brightness);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyboxUniform {
+    /// From the scene frame to the frame of the image
+    rotation: [[f32; 4]; 4],
+    brightness: f32,
+    _padding: [f32; 3],
+}
+
+impl SkyboxUniform {
+    fn new(frame: SkyboxFrame, rotation: f64, brightness: f32) -> Self {
+        Self {
+            rotation: image_rotation(frame, rotation).to_cols_array_2d(),
+            brightness,
+            _padding: [0.0; 3],
+        }
+    }
 }
 
-pub trait DrawSkybox<'a> {
-    fn draw_skybox(&mut self, skybox: &'a Skybox, camera_bind_group: &'a wgpu::BindGroup);
+fn image_rotation(frame: SkyboxFrame, rotation: f64) -> glam::Mat4 {
+    // The image is laid out in its frame the same way the scene is laid out in the
+    // ecliptic frame, so directions go back to the ecliptic frame, are converted and come
+    // back with the same axes
+    let spin = glam::DMat3::from_rotation_z(-rotation.to_radians());
+    let to_image = |scene: glam::DVec3| {
+        let ecliptic = glam::DVec3::new(scene.x, -scene.z, scene.y);
+        ephemeris::ecliptic_to_scene(spin * frame.convert_ecliptic(ecliptic))
+    };
+    let matrix = glam::DMat3::from_cols(
+        to_image(glam::DVec3::X),
+        to_image(glam::DVec3::Y),
+        to_image(glam::DVec3::Z),
+    );
+    glam::Mat4::from_mat3(matrix.as_mat3())
 }
 
-impl<'a, 'b> DrawSkybox<'b> for wgpu::RenderPass<'a>
-where
-    'b: 'a,
-{
-    fn draw_skybox(&mut self, skybox: &'a Skybox, camera_bind_group: &'a wgpu::BindGroup) {
-        self.set_pipeline(&skybox.render_pipeline);
-        self.set_bind_group(0, camera_bind_group, &[]);
-        self.set_bind_group(1, &skybox.bind_group, &[]);
-        self.draw(0..3, 0..1);
+/// Full screen triangle generated in the shader
+impl material::Renderable for Skybox {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &material::SceneBindGroups) {
+        pass.set_material(&self.material, scene, &[&self.bind_group]);
+        pass.draw(0..3, 0..1);
     }
 }
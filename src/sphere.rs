@@ -2,17 +2,34 @@ use std::{mem, ops::Range};
 
 use wgpu::util::DeviceExt;
 
-use crate::vertex::Vertex;
+use crate::{culling, instance, vertex::Vertex};
 
+/// UV spheres of decreasing detail, stored one after another in the same buffers. Clones
+/// share the buffers.
+#[derive(Clone)]
 pub struct Sphere {
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
-    num_elements: u32,
 }
 
 impl Sphere {
+    pub const LOD_COUNT: usize = 5;
+    /// Longitude and latitude segments of every level of detail, from the finest one
+    const LOD_SEGMENTS: [u16; Self::LOD_COUNT] = [128, 64, 32, 16, 8];
+    /// Largest distance between the silhouette of a mesh and of the real sphere
+    const MAX_ERROR_PIXELS: f32 = 0.25;
+
     pub fn new(device: &wgpu::Device) -> Self {
-        let (vertices, indices) = Self::generate_sphere_vertices(128, 128);
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for segments in Self::LOD_SEGMENTS {
+            let (level_vertices, level_indices) =
+                Self::generate_sphere_vertices(segments, segments);
+            // All levels together still fit in 16 bit indices
+            let base_vertex = vertices.len() as u16;
+            vertices.extend(level_vertices);
+            indices.extend(level_indices.iter().map(|index| base_vertex + index));
+        }
 
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("sphere_vertex_buffer"),
@@ -28,10 +45,41 @@ impl Sphere {
         Sphere {
             vertex_buffer,
             index_buffer,
-            num_elements: indices.len() as _,
         }
     }
 
+    /// Indices of a level of detail in the index buffer, levels are stored from the finest one
+    pub fn lod_indices(lod: usize) -> Range<u32> {
+        let index_count = |segments: u16| segments as u32 * segments as u32 * 6;
+        let first_index = Self::LOD_SEGMENTS[..lod]
+            .iter()
+            .map(|&segments| index_count(segments))
+            .sum();
+        first_index..first_index + index_count(Self::LOD_SEGMENTS[lod])
+    }
+
+    /// The coarsest level of detail which keeps the silhouette of a sphere of the given
+    /// radius (in pixels) within [`Self::MAX_ERROR_PIXELS`]. A polygon with `n` sides
+    /// deviates from its circle by `r * (1 - cos(PI / n))`, roughly `r * PI^2 / (2 * n^2)`.
+    pub fn lod(projected_radius: f32) -> usize {
+        let segments =
+            std::f32::consts::PI * (projected_radius / (2.0 * Self::MAX_ERROR_PIXELS)).sqrt();
+        Self::LOD_SEGMENTS
+            .iter()
+            .rposition(|&level_segments| level_segments as f32 >= segments)
+            .unwrap_or(0)
+    }
+
+    /// Level of detail of a body, `origin` is the camera position
+    pub fn instance_lod(
+        instance: &instance::Instance,
+        origin: glam::DVec3,
+        frustum: &culling::Frustum,
+    ) -> usize {
+        let distance = instance.position.distance(origin) as f32;
+        Self::lod(frustum.projected_radius(distance, instance.scale()))
+    }
+
     fn generate_sphere_vertices(
         longitude_segments: u16,
         latitude_segments: u16,
@@ -58,10 +106,15 @@ impl Sphere {
                 // For a unit sphere, the normal is the same as the position
                 let normal = position;
 
+                // Derivative of the position with respect to `phi`, so the tangent follows
+                // the `u` texture coordinate. It doesn't degenerate at the poles.
+                let tangent = [-phi.sin(), 0.0, phi.cos()];
+
                 vertices.push(SphereVertex {
                     position,
                     tex_coords,
                     normal,
+                    tangent,
                 });
             }
         }
@@ -91,6 +144,7 @@ pub struct SphereVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    pub tangent: [f32; 3],
 }
 
 impl Vertex for SphereVertex {
@@ -114,36 +168,67 @@ impl Vertex for SphereVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
 }
 
-pub trait DrawSphere<'a> {
-    fn draw_sphere_instanced(
-        &mut self,
-        sphere: &'a Sphere,
-        instances: Range<u32>,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    );
+/// Instances grouped by level of detail, every group is drawn with its own instanced draw
+#[derive(Default)]
+pub struct LodBatches {
+    ranges: [Range<u32>; Sphere::LOD_COUNT],
+}
+
+impl LodBatches {
+    /// Orders the instances by their level of detail, the result has to be written to the
+    /// instance buffer
+    pub fn sort(
+        mut instances: Vec<(usize, instance::InstanceRaw)>,
+    ) -> (Self, Vec<instance::InstanceRaw>) {
+        instances.sort_by_key(|(lod, _)| *lod);
+        let mut batches = Self::default();
+        let mut start = 0;
+        for (lod, range) in batches.ranges.iter_mut().enumerate() {
+            let count = instances.iter().filter(|(level, _)| *level == lod).count() as u32;
+            *range = start..start + count;
+            start += count;
+        }
+        let instances = instances.into_iter().map(|(_, raw)| raw).collect();
+        (batches, instances)
+    }
 }
 
-impl<'a, 'b> DrawSphere<'b> for wgpu::RenderPass<'a>
-where
-    'b: 'a,
-{
-    fn draw_sphere_instanced(
-        &mut self,
-        sphere: &'b Sphere,
-        instances: Range<u32>,
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
+impl Sphere {
+    /// The pipeline and the bind groups have to be set, along with the instance buffer
+    pub fn draw_instanced(&self, pass: &mut wgpu::RenderPass, lod: usize, instances: Range<u32>) {
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        pass.draw_indexed(Self::lod_indices(lod), 0, instances);
+    }
+
+    /// One instanced draw for every level of detail which is in use
+    pub fn draw_batches(&self, pass: &mut wgpu::RenderPass, batches: &LodBatches) {
+        for (lod, instances) in batches.ranges.iter().enumerate() {
+            if !instances.is_empty() {
+                self.draw_instanced(pass, lod, instances.clone());
+            }
+        }
+    }
+
+    /// Instanced draw of a level of detail with the arguments read from `indirect_buffer`
+    pub fn draw_indirect(
+        &self,
+        pass: &mut wgpu::RenderPass,
+        indirect_buffer: &wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
     ) {
-        self.set_vertex_buffer(0, sphere.vertex_buffer.slice(..));
-        self.set_index_buffer(sphere.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        self.set_bind_group(1, camera_bind_group, &[]);
-        self.set_bind_group(2, light_bind_group, &[]);
-        self.draw_indexed(0..sphere.num_elements, 0, instances);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        pass.draw_indexed_indirect(indirect_buffer, indirect_offset);
     }
 }
@@ -0,0 +1,636 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result, anyhow};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera, ephemeris,
+    material::{self, SetMaterial},
+    pipeline,
+};
+
+/// A star of the catalog, as seen from the solar system
+struct Star {
+    name: String,
+    /// Unit vector in the scene frame
+    direction: glam::Vec3,
+    magnitude: f32,
+    /// Linear RGB, brightest channel is 1.0
+    color: [f32; 3],
+}
+
+/// Stars with their constellation figures. Stars are so far away that only their
+/// direction matters, it doesn't change when moving around the solar system.
+struct StarCatalog {
+    stars: Vec<Star>,
+    /// Pairs of star indices
+    lines: Vec<(usize, usize)>,
+    /// Name of every constellation, with the stars of its figure
+    constellations: Vec<(String, Vec<usize>)>,
+}
+
+impl StarCatalog {
+    /// Environment variable with the path to a catalog replacing the bundled one
+    const PATH_VARIABLE: &str = "SOLAR_SYSTEM_STAR_CATALOG";
+
+    const BUNDLED_STARS: &str = include_str!("../assets/stars/bright_stars.csv");
+
+    /// Loads the catalog from `SOLAR_SYSTEM_STAR_CATALOG`, or the bundled bright stars if
+    /// it is not set or can't be loaded. Constellations are always the bundled ones, figures
+    /// through stars missing from the catalog are skipped.
+    fn from_env() -> Result<Self> {
+        let user_stars = std::env::var(Self::PATH_VARIABLE).ok().and_then(|path| {
+            match Self::load_stars(Path::new(&path)) {
+                Ok(stars) => Some(stars),
+                Err(e) => {
+                    log::warn!("Using the bundled star catalog: {e:#}");
+                    None
+                }
+            }
+        });
+        let stars = match user_stars {
+            Some(stars) => stars,
+            None => Self::parse_stars(Self::BUNDLED_STARS)?,
+        };
+        let mut catalog = Self {
+            stars,
+            lines: Vec::new(),
+            constellations: Vec::new(),
+        };
+        catalog.parse_constellations(include_str!("../assets/stars/constellations.txt"))?;
+        Ok(catalog)
+    }
+
+    fn load_stars(path: &Path) -> Result<Vec<Star>> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse_stars(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Every line is `name, right ascension (hh:mm:ss), declination (dd:mm:ss), visual
+    /// magnitude, B-V color index`, in the equatorial frame of J2000
+    fn parse_stars(contents: &str) -> Result<Vec<Star>> {
+        Self::records(contents)
+            .map(|(number, line)| {
+                let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+                let [name, right_ascension, declination, magnitude, color_index] = fields[..]
+                else {
+                    return Err(anyhow!("Line {}: expected 5 fields", number));
+                };
+                let right_ascension = Self::parse_sexagesimal(right_ascension)
+                    .with_context(|| format!("Line {}: invalid right ascension", number))?;
+                let declination = Self::parse_sexagesimal(declination)
+                    .with_context(|| format!("Line {}: invalid declination", number))?;
+                let magnitude: f32 = magnitude
+                    .parse()
+                    .with_context(|| format!("Line {}: invalid magnitude", number))?;
+                let color_index: f32 = color_index
+                    .parse()
+                    .with_context(|| format!("Line {}: invalid color index", number))?;
+                Ok(Star {
+                    name: name.to_owned(),
+                    direction: Self::equatorial_to_scene(right_ascension * 15.0, declination),
+                    magnitude,
+                    color: Self::color_from_index(color_index),
+                })
+            })
+            .collect()
+    }
+
+    /// Every line is `constellation: star, star, ...`, a polyline through the given stars
+    fn parse_constellations(&mut self, contents: &str) -> Result<()> {
+        let indices: HashMap<&str, usize> = self
+            .stars
+            .iter()
+            .enumerate()
+            .map(|(i, star)| (star.name.as_str(), i))
+            .collect();
+        let mut constellations: Vec<(String, Vec<usize>)> = Vec::new();
+        for (number, line) in Self::records(contents) {
+            let (name, figure) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Line {}: expected constellation name", number))?;
+            let figure: Option<Vec<usize>> = figure
+                .split(',')
+                .map(|star| indices.get(star.trim()).copied())
+                .collect();
+            let Some(figure) = figure else {
+                log::warn!(
+                    "Skipping figure of {}, stars missing from the catalog",
+                    name
+                );
+                continue;
+            };
+            self.lines
+                .extend(figure.windows(2).map(|pair| (pair[0], pair[1])));
+            match constellations.iter_mut().find(|(n, _)| n == name) {
+                Some((_, stars)) => stars.extend(figure),
+                None => constellations.push((name.to_owned(), figure)),
+            }
+        }
+        self.constellations = constellations;
+        Ok(())
+    }
+
+    /// Lines that are not empty or comments, with their line numbers
+    fn records(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+        contents
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+    }
+
+    /// `hh:mm:ss` or `dd:mm:ss` with an optional sign
+    fn parse_sexagesimal(value: &str) -> Result<f64> {
+        let (sign, value) = match value.strip_prefix('-') {
+            Some(value) => (-1.0, value),
+            None => (1.0, value.trim_start_matches('+')),
+        };
+        let mut result = 0.0;
+        for (i, part) in value.split(':').enumerate() {
+            let part: f64 = part.parse()?;
+            result += part / 60f64.powi(i as i32);
+        }
+        Ok(sign * result)
+    }
+
+    /// Angles in degrees
+    fn equatorial_to_scene(right_ascension: f64, declination: f64) -> glam::Vec3 {
+        let (sin_ra, cos_ra) = right_ascension.to_radians().sin_cos();
+        let (sin_dec, cos_dec) = declination.to_radians().sin_cos();
+        let equatorial = glam::DVec3::new(cos_dec * cos_ra, cos_dec * sin_ra, sin_dec);
+        let ecliptic = ephemeris::equatorial_to_ecliptic(equatorial);
+        ephemeris::ecliptic_to_scene(ecliptic).normalize().as_vec3()
+    }
+
+    /// Temperature from the B-V index (Ballesteros, 2012), then the color of a black body at
+    /// that temperature (Tanner Helland's fit), converted to linear RGB
+    fn color_from_index(color_index: f32) -> [f32; 3] {
+        let bv = color_index.clamp(-0.4, 2.0);
+        let temperature = 4600.0 * (1.0 / (0.92 * bv + 1.7) + 1.0 / (0.92 * bv + 0.62));
+        let t = temperature / 100.0;
+        let red = if t <= 66.0 {
+            255.0
+        } else {
+            329.698_73 * (t - 60.0).powf(-0.133_204_76)
+        };
+        let green = if t <= 66.0 {
+            99.470_8 * t.ln() - 161.119_57
+        } else {
+            288.122_16 * (t - 60.0).powf(-0.075_514_85)
+        };
+        let blue = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.517_73 * (t - 10.0).ln() - 305.044_8
+        };
+        let color = [red, green, blue].map(|c| (c.clamp(0.0, 255.0) / 255.0).powf(2.2));
+        let max = color.iter().copied().fold(f32::EPSILON, f32::max);
+        color.map(|c| c / max)
+    }
+}
+
+/// Stars of a catalog drawn as point sprites on top of the skybox, with optional
+/// constellation figures and names. Sprites have a constant size in pixels, so the stars
+/// stay sharp at any field of view.
+pub struct Starfield {
+    enabled: bool,
+    constellations_enabled: bool,
+    uniform: StarfieldUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    stars_buffer: wgpu::Buffer,
+    stars_count: u32,
+    lines_buffer: wgpu::Buffer,
+    lines_count: u32,
+    glyphs_buffer: wgpu::Buffer,
+    glyphs_count: u32,
+    stars_material: material::Material,
+    lines_material: material::Material,
+    labels_material: material::Material,
+}
+
+impl Starfield {
+    /// Brightness of a star of magnitude 0
+    const BRIGHTNESS: f32 = 4.0;
+    /// Radius of the sprite of the faintest stars, in pixels
+    const MIN_SIZE: f32 = 1.5;
+    /// Growth of the sprite with the flux of the star, in pixels
+    const SIZE_SCALE: f32 = 2.0;
+    /// Size of a pixel of the font, in screen pixels
+    const GLYPH_SCALE: f32 = 2.0;
+    /// Distance between the start of two characters, in font pixels
+    const GLYPH_ADVANCE: f32 = 6.0;
+
+    pub fn new(
+        device: &wgpu::Device,
+        materials: &mut material::PipelineCache,
+        camera_container: &camera::CameraContainer,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let catalog = StarCatalog::from_env()?;
+        log::info!(
+            "Loaded {} stars and {} constellations",
+            catalog.stars.len(),
+            catalog.constellations.len()
+        );
+
+        let stars: Vec<StarInstance> = catalog
+            .stars
+            .iter()
+            .map(|star| {
+                let flux = 10f32.powf(-0.4 * star.magnitude);
+                StarInstance {
+                    direction: star.direction.into(),
+                    size: Self::MIN_SIZE + Self::SIZE_SCALE * flux.sqrt(),
+                    color: star.color.map(|c| c * Self::BRIGHTNESS * flux),
+                    _padding: 0.0,
+                }
+            })
+            .collect();
+        let lines: Vec<LineVertex> = catalog
+            .lines
+            .iter()
+            .flat_map(|&(a, b)| [a, b])
+            .map(|i| LineVertex {
+                direction: catalog.stars[i].direction.into(),
+            })
+            .collect();
+        let glyphs: Vec<GlyphInstance> = catalog
+            .constellations
+            .iter()
+            .flat_map(|(name, stars)| {
+                let center = stars
+                    .iter()
+                    .map(|&i| catalog.stars[i].direction)
+                    .sum::<glam::Vec3>()
+                    .normalize();
+                Self::label(name, center)
+            })
+            .collect();
+
+        let uniform = StarfieldUniform {
+            viewport_size: [width as f32, height as f32],
+            glyph_scale: Self::GLYPH_SCALE,
+            _padding: 0.0,
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Starfield Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = materials.bind_group_layout(
+            device,
+            "starfield_bind_group_layout",
+            &[material::Binding::Uniform(wgpu::ShaderStages::VERTEX)],
+        );
+        let bind_group = material::create_bind_group(
+            device,
+            "starfield_bind_group",
+            &bind_group_layout,
+            &[buffer.as_entire_binding()],
+        );
+
+        let stars_buffer = Self::create_vertex_buffer(device, &stars, "Stars Buffer");
+        let lines_buffer = Self::create_vertex_buffer(device, &lines, "Constellation Lines Buffer");
+        let glyphs_buffer =
+            Self::create_vertex_buffer(device, &glyphs, "Constellation Labels Buffer");
+
+        let bind_groups = [
+            (
+                material::BindGroupSlot::Camera,
+                &camera_container.camera_bind_group_layout,
+            ),
+            (material::BindGroupSlot::Object, &bind_group_layout),
+        ];
+        let mut create_material = |name, shader, vertex_layout, topology| {
+            materials.material(
+                device,
+                material::MaterialDescriptor {
+                    name,
+                    shader,
+                    bind_groups: &bind_groups,
+                    vertex_layouts: &[vertex_layout],
+                    topology,
+                    blending: material::Blending::Transparent {
+                        blend: pipeline::ADDITIVE_BLENDING,
+                        cull_mode: Some(wgpu::Face::Back),
+                    },
+                },
+            )
+        };
+        let stars_material = create_material(
+            "stars",
+            wgpu::include_wgsl!("../shaders/stars.wgsl"),
+            StarInstance::desc(),
+            wgpu::PrimitiveTopology::TriangleList,
+        );
+        let lines_material = create_material(
+            "constellation_lines",
+            wgpu::include_wgsl!("../shaders/constellation_lines.wgsl"),
+            LineVertex::desc(),
+            wgpu::PrimitiveTopology::LineList,
+        );
+        let labels_material = create_material(
+            "constellation_labels",
+            wgpu::include_wgsl!("../shaders/constellation_labels.wgsl"),
+            GlyphInstance::desc(),
+            wgpu::PrimitiveTopology::TriangleList,
+        );
+
+        Ok(Self {
+            enabled: true,
+            constellations_enabled: false,
+            uniform,
+            buffer,
+            bind_group,
+            stars_buffer,
+            stars_count: stars.len() as u32,
+            lines_buffer,
+            lines_count: lines.len() as u32,
+            glyphs_buffer,
+            glyphs_count: glyphs.len() as u32,
+            stars_material,
+            lines_material,
+            labels_material,
+        })
+    }
+
+    fn create_vertex_buffer<T: bytemuck::Pod>(
+        device: &wgpu::Device,
+        data: &[T],
+        label: &str,
+    ) -> wgpu::Buffer {
+        // Buffers cannot be empty, a catalog without constellations still gets one element
+        let zeroed = [T::zeroed()];
+        let contents = if data.is_empty() { &zeroed[..] } else { data };
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: bytemuck::cast_slice(contents),
+            usage: wgpu::BufferUsages::VERTEX,
+        })
+    }
+
+    /// One glyph per letter of the upper-cased name, centered on `direction`
+    fn label(name: &str, direction: glam::Vec3) -> Vec<GlyphInstance> {
+        let name = name.to_ascii_uppercase();
+        let width = name.len() as f32 * Self::GLYPH_ADVANCE - 1.0;
+        name.chars()
+            .enumerate()
+            .filter_map(|(i, c)| {
+                let bitmap = font::bitmap(c)?;
+                Some(GlyphInstance {
+                    direction: direction.into(),
+                    offset: [
+                        i as f32 * Self::GLYPH_ADVANCE - width / 2.0,
+                        -(font::HEIGHT as f32) / 2.0,
+                    ],
+                    bitmap,
+                })
+            })
+            .collect()
+    }
+
+    pub fn resize(&mut self, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.uniform.viewport_size = [width as f32, height as f32];
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn swap_enabled(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    pub fn constellations_enabled(&self) -> bool {
+        self.constellations_enabled
+    }
+
+    pub fn swap_constellations_enabled(&mut self) {
+        self.constellations_enabled = !self.constellations_enabled;
+    }
+}
+
+/// Minimal 5x7 bitmap font for the constellation names, there is no text rendering
+/// otherwise
+mod font {
+    pub const WIDTH: u32 = 5;
+    pub const HEIGHT: u32 = 7;
+
+    /// Rows from the top, the highest of the 5 bits is the leftmost pixel
+    const LETTERS: [[u8; HEIGHT as usize]; 26] = [
+        [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ], // A
+        [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ], // B
+        [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ], // C
+        [
+            0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110,
+        ], // D
+        [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ], // E
+        [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ], // F
+        [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ], // G
+        [
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ], // H
+        [
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ], // I
+        [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ], // J
+        [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ], // K
+        [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ], // L
+        [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ], // M
+        [
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ], // N
+        [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ], // O
+        [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ], // P
+        [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ], // Q
+        [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ], // R
+        [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ], // S
+        [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ], // T
+        [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ], // U
+        [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ], // V
+        [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ], // W
+        [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ], // X
+        [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100,
+        ], // Y
+        [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ], // Z
+    ];
+
+    /// Pixel `(column, row)` is bit `row * WIDTH + column` of the pair, `None` for
+    /// characters without a glyph, such as spaces
+    pub fn bitmap(c: char) -> Option<[u32; 2]> {
+        let index = (c as u32).checked_sub('A' as u32)? as usize;
+        let rows = LETTERS.get(index)?;
+        let mut bits = 0u64;
+        for (row, pixels) in rows.iter().enumerate() {
+            for column in 0..WIDTH {
+                if pixels >> (WIDTH - 1 - column) & 1 == 1 {
+                    bits |= 1 << (row as u32 * WIDTH + column);
+                }
+            }
+        }
+        Some([bits as u32, (bits >> 32) as u32])
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct StarfieldUniform {
+    viewport_size: [f32; 2],
+    glyph_scale: f32,
+    _padding: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct StarInstance {
+    direction: [f32; 3],
+    /// Radius of the sprite, in pixels
+    size: f32,
+    /// Scaled by the brightness of the star
+    color: [f32; 3],
+    _padding: f32,
+}
+
+impl StarInstance {
+    const ATTRIBS: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32, 2 => Float32x3];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineVertex {
+    direction: [f32; 3],
+}
+
+impl LineVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlyphInstance {
+    /// Direction of the label the glyph belongs to
+    direction: [f32; 3],
+    /// Bottom left corner of the glyph relative to the label, in font pixels
+    offset: [f32; 2],
+    /// See `font::bitmap`
+    bitmap: [u32; 2],
+}
+
+impl GlyphInstance {
+    const ATTRIBS: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Uint32x2];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+/// Stars are at infinity, behind everything else, and are blended over the skybox
+impl material::Renderable for Starfield {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &material::SceneBindGroups) {
+        if !self.enabled {
+            return;
+        }
+        if self.constellations_enabled {
+            pass.set_material(&self.lines_material, scene, &[&self.bind_group]);
+            pass.set_vertex_buffer(0, self.lines_buffer.slice(..));
+            pass.draw(0..self.lines_count, 0..1);
+
+            pass.set_material(&self.labels_material, scene, &[&self.bind_group]);
+            pass.set_vertex_buffer(0, self.glyphs_buffer.slice(..));
+            pass.draw(0..6, 0..self.glyphs_count);
+        }
+
+        pass.set_material(&self.stars_material, scene, &[&self.bind_group]);
+        pass.set_vertex_buffer(0, self.stars_buffer.slice(..));
+        pass.draw(0..6, 0..self.stars_count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_catalog_parses() {
+        let stars = StarCatalog::parse_stars(StarCatalog::BUNDLED_STARS).unwrap();
+        assert!(!stars.is_empty());
+    }
+
+    #[test]
+    fn malformed_catalog_is_rejected() {
+        assert!(StarCatalog::parse_stars("Sirius, 06:45:08.9, -16:42:58\n").is_err());
+        assert!(StarCatalog::load_stars(Path::new("missing_catalog.csv")).is_err());
+    }
+}
@@ -1,11 +1,14 @@
-use std::time::Duration;
-
 use wgpu::util::DeviceExt;
 
 use crate::{
-    camera, hdr, instance, light, pipeline,
-    sphere::{self, DrawSphere, Sphere},
-    texture::{self, SetTextureContainer},
+    background::BackgroundLoader,
+    camera,
+    clock::SimulationClock,
+    culling, hdr, instance, light,
+    material::{self, SetMaterial},
+    pipeline, scale,
+    sphere::{self, Sphere},
+    texture,
     vertex::Vertex,
 };
 
@@ -14,147 +17,331 @@ pub struct Sun {
     instance: instance::Instance,
     instance_buffer: wgpu::Buffer,
     texture_container: texture::TextureContainer,
-    render_pipeline: wgpu::RenderPipeline,
+    texture_loader: BackgroundLoader<anyhow::Result<image::DynamicImage>>,
+    material: material::Material,
+    sphere: Sphere,
+    /// See [`SimulationClock::animation_time`], drives the animation of the surface
+    time: f32,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    corona: Corona,
+    lod: usize,
 }
 
 impl Sun {
+    const SCALE: f32 = 6.5;
+    const RADIUS_KM: f64 = 695_700.0;
+    /// Smaller than for the planets, otherwise the sun would swallow the inner orbits
+    const SIZE_EXAGGERATION: f64 = 20.0;
+    const ROTATION_SPEED: f32 = 0.12;
+    /// Emissive intensity of the surface, values above 1.0 are handled by tonemapping
+    const INTENSITY: f32 = 3.0;
+    /// Size of the corona billboard, relative to the radius of the sun
+    const CORONA_SCALE: f32 = 4.0;
+    const CORONA_INTENSITY: f32 = 0.8;
+    /// Average color of the texture, shown until it is decoded
+    const PLACEHOLDER_COLOR: [u8; 4] = [250, 150, 40, 255];
+
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        hdr: &hdr::HdrPipeline,
+        materials: &mut material::PipelineCache,
         camera_container: &camera::CameraContainer,
+        environment: &hdr::EnvironmentMaps,
+        sphere: &Sphere,
     ) -> Self {
-        let position = [0.0, 0.0, 0.0];
-        let light = light::Light::new(device, position, [1.0, 1.0, 1.0]);
+        let position = glam::DVec3::ZERO;
+        let origin = camera_container.camera.position();
+        let light = light::Light::new(
+            device,
+            (position - origin).as_vec3().into(),
+            [1.0, 1.0, 1.0],
+            environment,
+        );
 
         let instance =
-            instance::Instance::new(position.into(), glam::Quat::from_rotation_y(0.0), 0, 6.5);
+            instance::Instance::new(position, glam::Quat::from_rotation_y(0.0), 0, Self::SCALE);
 
-        let instance_data = vec![instance::InstanceRaw::from(&instance)];
+        let instance_data = vec![instance::InstanceRaw::new(&instance, origin)];
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Sun Instance Buffer"),
             contents: bytemuck::cast_slice(&instance_data),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
 
-        let texture_bytes = include_bytes!("../assets/textures/sun.jpg");
-        let texture =
-            texture::Texture::from_bytes(device, queue, texture_bytes, "sun texture").unwrap();
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-        let texture_bind_group: wgpu::BindGroup =
-            device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                    },
-                ],
-                label: Some("diffuse_bind_group"),
-            });
+        // The texture is decoded in the background, the surface starts with a flat color
+        let placeholder = image::RgbaImage::from_pixel(1, 1, image::Rgba(Self::PLACEHOLDER_COLOR));
+        let texture = texture::Texture::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(placeholder),
+            Some("sun texture"),
+        )
+        .unwrap();
+        let mut texture_loader = BackgroundLoader::new();
+        texture_loader.spawn(|| {
+            image::load_from_memory(include_bytes!("../assets/textures/sun.jpg"))
+                .map_err(anyhow::Error::from)
+        });
+        let texture_bind_group_layout = materials.bind_group_layout(
+            device,
+            "texture_bind_group_layout",
+            &[
+                material::Binding::fragment_texture(wgpu::TextureViewDimension::D2),
+                material::Binding::FRAGMENT_SAMPLER,
+            ],
+        );
+        let texture_bind_group =
+            Self::create_texture_bind_group(device, &texture_bind_group_layout, &texture);
         let texture_container =
             texture::TextureContainer::new(texture, texture_bind_group, texture_bind_group_layout);
 
-        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Sun Pipeline Layout"),
-            bind_group_layouts: &[
-                &texture_container.bind_group_layout,
-                &camera_container.camera_bind_group_layout,
-                &light.bind_group_layout,
-            ],
-            push_constant_ranges: &[],
+        let uniform = SunUniform::new(
+            &instance,
+            origin,
+            0.0,
+            Self::INTENSITY,
+            Self::CORONA_SCALE,
+            Self::CORONA_INTENSITY,
+        );
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sun Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
-        let shader = wgpu::include_wgsl!("../shaders/sun.wgsl");
-        let render_pipeline = pipeline::create_render_pipeline(
+        let bind_group_layout = materials.bind_group_layout(
+            device,
+            "sun_bind_group_layout",
+            &[material::Binding::Uniform(
+                wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            )],
+        );
+        let bind_group = material::create_bind_group(
             device,
-            &layout,
-            hdr.format(),
-            Some(texture::Texture::DEPTH_FORMAT),
-            &[sphere::SphereVertex::desc(), instance::InstanceRaw::desc()],
-            wgpu::PrimitiveTopology::TriangleList,
-            shader,
-            Some("render_pipelie_sun"),
+            "sun_bind_group",
+            &bind_group_layout,
+            &[buffer.as_entire_binding()],
+        );
+
+        let material = materials.material(
+            device,
+            material::MaterialDescriptor {
+                name: "sun",
+                shader: wgpu::include_wgsl!("../shaders/sun.wgsl"),
+                bind_groups: &[
+                    (
+                        material::BindGroupSlot::Object,
+                        &texture_container.bind_group_layout,
+                    ),
+                    (
+                        material::BindGroupSlot::Camera,
+                        &camera_container.camera_bind_group_layout,
+                    ),
+                    (material::BindGroupSlot::Light, &light.bind_group_layout),
+                    (material::BindGroupSlot::Object, &bind_group_layout),
+                ],
+                vertex_layouts: &[sphere::SphereVertex::desc(), instance::InstanceRaw::desc()],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                blending: material::Blending::Opaque,
+            },
         );
 
+        let corona = Corona {
+            material: materials.material(
+                device,
+                material::MaterialDescriptor {
+                    name: "corona",
+                    shader: wgpu::include_wgsl!("../shaders/corona.wgsl"),
+                    bind_groups: &[
+                        (material::BindGroupSlot::Object, &bind_group_layout),
+                        (
+                            material::BindGroupSlot::Camera,
+                            &camera_container.camera_bind_group_layout,
+                        ),
+                    ],
+                    // Billboard vertices are generated in the shader
+                    vertex_layouts: &[],
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    blending: material::Blending::Transparent {
+                        blend: pipeline::ADDITIVE_BLENDING,
+                        cull_mode: Some(wgpu::Face::Back),
+                    },
+                },
+            ),
+            bind_group: bind_group.clone(),
+        };
+
         Self {
             light,
             instance,
             instance_buffer,
             texture_container,
-            render_pipeline,
+            texture_loader,
+            material,
+            sphere: sphere.clone(),
+            time: 0.0,
+            buffer,
+            bind_group,
+            corona,
+            lod: 0,
+        }
+    }
+
+    fn create_texture_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        material::create_bind_group(
+            device,
+            "diffuse_bind_group",
+            layout,
+            &[
+                wgpu::BindingResource::TextureView(&texture.view),
+                wgpu::BindingResource::Sampler(&texture.sampler),
+            ],
+        )
+    }
+
+    /// Swaps in the texture once it is decoded
+    pub fn load_texture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        for image in self.texture_loader.poll() {
+            let texture = image.and_then(|image| {
+                texture::Texture::from_image(device, queue, &image, Some("sun texture"))
+            });
+            match texture {
+                Ok(texture) => {
+                    let bind_group = Self::create_texture_bind_group(
+                        device,
+                        &self.texture_container.bind_group_layout,
+                        &texture,
+                    );
+                    self.texture_container.replace_texture(texture, bind_group);
+                }
+                Err(e) => log::error!("Failed to load the sun texture: {e:#}"),
+            }
         }
     }
 
+    pub fn pending_textures(&self) -> usize {
+        self.texture_loader.pending()
+    }
+
+    /// Glow around the sun, it is drawn on its own after all opaque objects
+    pub fn corona(&self) -> &Corona {
+        &self.corona
+    }
+
     pub fn light(&self) -> &light::Light {
         &self.light
     }
 
-    pub fn update(&mut self, total_time: Duration) {
-        let t = total_time.as_secs_f32();
-        let rotation_speed = 0.12;
-        let rotation_angle = t * rotation_speed;
+    pub fn light_mut(&mut self) -> &mut light::Light {
+        &mut self.light
+    }
+
+    pub fn position(&self) -> glam::DVec3 {
+        self.instance.position
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.instance.scale()
+    }
+
+    pub fn update(&mut self, clock: &SimulationClock, system_scale: &scale::SystemScale) {
+        self.instance
+            .set_scale(system_scale.radius(&scale::BodyDimensions {
+                artistic_distance: 0.0,
+                artistic_radius: Self::SCALE,
+                distance_au: 0.0,
+                radius_km: Self::RADIUS_KM,
+                exaggeration: Self::SIZE_EXAGGERATION,
+            }));
+        self.time = clock.animation_time();
+        let rotation_angle = self.time * Self::ROTATION_SPEED;
         self.instance.rotation = glam::Quat::from_rotation_y(rotation_angle);
     }
 
-    pub fn sync_instance_buffer(&self, queue: &wgpu::Queue) {
-        let instance_data = vec![instance::InstanceRaw::from(&self.instance)];
+    /// `origin` is the camera position, all positions are sent relative to it
+    pub fn sync_buffers(
+        &mut self,
+        queue: &wgpu::Queue,
+        origin: glam::DVec3,
+        frustum: &culling::Frustum,
+    ) {
+        self.lod = Sphere::instance_lod(&self.instance, origin, frustum);
+        let instance_data = vec![instance::InstanceRaw::new(&self.instance, origin)];
         queue.write_buffer(
             &self.instance_buffer,
             0,
             bytemuck::cast_slice(&instance_data),
         );
+        let uniform = SunUniform::new(
+            &self.instance,
+            origin,
+            self.time,
+            Self::INTENSITY,
+            Self::CORONA_SCALE,
+            Self::CORONA_INTENSITY,
+        );
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+        self.light
+            .sync_buffer(queue, (self.position() - origin).as_vec3().into());
     }
 }
 
-pub trait DrawSun<'a> {
-    fn draw_sun(
-        &mut self,
-        sun: &'a Sun,
-        sphere: &'a Sphere,
-        camera_bind_group: &'a wgpu::BindGroup,
-    );
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SunUniform {
+    center: [f32; 3],
+    radius: f32,
+    time: f32,
+    intensity: f32,
+    corona_scale: f32,
+    corona_intensity: f32,
 }
 
-impl<'a, 'b> DrawSun<'b> for wgpu::RenderPass<'a>
-where
-    'b: 'a,
-{
-    fn draw_sun(
-        &mut self,
-        sun: &'a Sun,
-        sphere: &'a Sphere,
-        camera_bind_group: &'a wgpu::BindGroup,
-    ) {
-        self.set_pipeline(&sun.render_pipeline);
-        self.set_texture_container(&sun.texture_container);
-        self.set_vertex_buffer(1, sun.instance_buffer.slice(..));
-        self.draw_sphere_instanced(sphere, 0..1, camera_bind_group, &sun.light.bind_group);
+impl SunUniform {
+    pub fn new(
+        sun: &instance::Instance,
+        origin: glam::DVec3,
+        time: f32,
+        intensity: f32,
+        corona_scale: f32,
+        corona_intensity: f32,
+    ) -> Self {
+        Self {
+            center: (sun.position - origin).as_vec3().into(),
+            radius: sun.scale(),
+            time,
+            intensity,
+            corona_scale,
+            corona_intensity,
+        }
+    }
+}
+
+impl material::Renderable for Sun {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &material::SceneBindGroups) {
+        pass.set_material(
+            &self.material,
+            scene,
+            &[&self.texture_container.bind_group, &self.bind_group],
+        );
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        self.sphere.draw_instanced(pass, self.lod, 0..1);
+    }
+}
+
+/// Billboard sharing the uniform of the sun, see [`Sun::corona`]
+pub struct Corona {
+    material: material::Material,
+    bind_group: wgpu::BindGroup,
+}
+
+impl material::Renderable for Corona {
+    fn draw(&self, pass: &mut wgpu::RenderPass, scene: &material::SceneBindGroups) {
+        pass.set_material(&self.material, scene, &[&self.bind_group]);
+        pass.draw(0..6, 0..1);
     }
 }
@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use anyhow::Result;
+use image::{Luma, Rgba};
+
+/// Source of a single layer of the planets surface maps array. Layers are stored in a
+/// linear (non-sRGB) format, as they hold data rather than colors.
+#[derive(Debug, Clone, Copy)]
+pub enum SurfaceMapSource {
+    /// Tangent space normal map computed from a height map. We don't ship elevation data,
+    /// so the luminance of the image is used as the height, which is good enough to make
+    /// the terrain read in relief near the terminator.
+    NormalFromHeight { path: &'static str, strength: f32 },
+    /// Specular (red channel) and roughness (green channel) computed from the oceans of a
+    /// diffuse texture, so that water glints while land stays matte.
+    OceanGloss(&'static str),
+}
+
+impl SurfaceMapSource {
+    /// Flat surface with the properties of land, used until the map is generated
+    pub fn placeholder(&self) -> [u8; 4] {
+        match self {
+            Self::NormalFromHeight { .. } => [128, 128, 255, 255],
+            Self::OceanGloss(_) => [10, 217, 0, 255],
+        }
+    }
+
+    /// The image the map is computed from
+    pub fn path(&self) -> &'static str {
+        match self {
+            Self::NormalFromHeight { path, .. } | Self::OceanGloss(path) => path,
+        }
+    }
+
+    pub fn generate(&self) -> Result<image::RgbaImage> {
+        self.generate_from(Path::new(self.path()))
+    }
+
+    /// Computes the map from another version of the image, e.g. a thumbnail
+    pub fn generate_from(&self, path: &Path) -> Result<image::RgbaImage> {
+        match self {
+            Self::NormalFromHeight { strength, .. } => Ok(normal_from_height(
+                &image::open(path)?.to_luma32f(),
+                *strength,
+            )),
+            Self::OceanGloss(_) => Ok(ocean_gloss(&image::open(path)?.to_rgba8())),
+        }
+    }
+}
+
+/// Uses the Sobel operator to compute the gradient of the height map. The `x` axis of the
+/// result follows the `u` texture coordinate and the `y` axis follows the `v` coordinate.
+fn normal_from_height(
+    height_map: &image::ImageBuffer<Luma<f32>, Vec<f32>>,
+    strength: f32,
+) -> image::RgbaImage {
+    let (width, height) = height_map.dimensions();
+    let sample = |x: i64, y: i64| {
+        // Equirectangular maps wrap around the meridian, but not around the poles
+        let x = x.rem_euclid(width as i64) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        height_map.get_pixel(x, y).0[0]
+    };
+
+    image::RgbaImage::from_fn(width, height, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let dx = (sample(x + 1, y - 1) + 2.0 * sample(x + 1, y) + sample(x + 1, y + 1))
+            - (sample(x - 1, y - 1) + 2.0 * sample(x - 1, y) + sample(x - 1, y + 1));
+        let dy = (sample(x - 1, y + 1) + 2.0 * sample(x, y + 1) + sample(x + 1, y + 1))
+            - (sample(x - 1, y - 1) + 2.0 * sample(x, y - 1) + sample(x + 1, y - 1));
+
+        let normal = glam::Vec3::new(-dx * strength, -dy * strength, 1.0).normalize();
+        let encoded = (normal * 0.5 + 0.5) * 255.0;
+        Rgba([
+            encoded.x.round() as u8,
+            encoded.y.round() as u8,
+            encoded.z.round() as u8,
+            255,
+        ])
+    })
+}
+
+fn ocean_gloss(diffuse: &image::RgbaImage) -> image::RgbaImage {
+    image::RgbaImage::from_fn(diffuse.width(), diffuse.height(), |x, y| {
+        let [r, g, b, _] = diffuse.get_pixel(x, y).0.map(|c| c as f32 / 255.0);
+
+        // Oceans are the only areas where blue clearly dominates
+        let t = ((b - r.max(g) - 0.02) / 0.1).clamp(0.0, 1.0);
+        let ocean = t * t * (3.0 - 2.0 * t);
+
+        let specular = 0.04 + 0.6 * ocean;
+        let roughness = 0.85 - 0.6 * ocean;
+        Rgba([
+            (specular * 255.0).round() as u8,
+            (roughness * 255.0).round() as u8,
+            0,
+            255,
+        ])
+    })
+}
@@ -0,0 +1,635 @@
+use std::{mem, path::Path};
+
+use anyhow::Result;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    background::BackgroundLoader,
+    camera, culling,
+    instance::{self, Instance},
+    material::{self, SetMaterial},
+    sun, texture,
+    vertex::Vertex,
+};
+
+/// Describes the surface of a body which can be seen from up close
+pub struct TerrainParameters {
+    /// Name of the height map, `<name>.png` in the height maps directory
+    pub name: &'static str,
+    /// Elevations of the darkest and brightest texels of the height map, relative to the
+    /// mean radius of the body
+    pub elevation_km: [f64; 2],
+}
+
+/// Body the terrain is drawn for
+pub struct TerrainBody<'a> {
+    pub instance: &'a Instance,
+    pub parameters: &'static TerrainParameters,
+    pub radius_km: f64,
+}
+
+/// Close-up surface of a body, drawn instead of its sphere once it gets large on the screen.
+/// The body is a cube with its faces projected on a sphere, every face is a quadtree of
+/// chunks which are split near the camera, so the detail follows the distance down to the
+/// ground. All chunks share one grid mesh, positioned and displaced by the height map of the
+/// body in the vertex shader. Chunks of different sizes don't share their edge vertices, so
+/// every chunk has a skirt hanging below its edges which covers the cracks between them.
+type Heightmap = image::ImageBuffer<image::Luma<f32>, Vec<f32>>;
+
+pub struct Terrain {
+    /// Names of the bodies with a height map, the index is the layer of `heightmaps`
+    heightmap_names: Vec<&'static str>,
+    /// Height maps are decoded in the background, the terrain is flat until all of them are
+    heightmap_loader: BackgroundLoader<(&'static str, Result<Heightmap>)>,
+    loaded_heightmaps: Vec<(&'static str, Heightmap)>,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+    chunk_buffer: wgpu::Buffer,
+    chunk_count: u32,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    material: material::Material,
+    /// Distance from the camera to the surface relative to the radius, when the terrain is
+    /// drawn
+    altitude: Option<f64>,
+}
+
+impl Terrain {
+    /// Quads along the edge of a chunk
+    const GRID_SIZE: u16 = 32;
+    const MAX_DEPTH: u32 = 14;
+    const MAX_CHUNKS: usize = 1024;
+    /// Chunks larger than this on the screen (radius of their bounding sphere, in pixels) are
+    /// split in four
+    const SPLIT_RADIUS_PIXELS: f32 = 192.0;
+    /// The terrain replaces the sphere of a body larger than this on the screen (in pixels)
+    const MIN_PROJECTED_RADIUS: f32 = 256.0;
+    /// The camera slows down near the ground, down to this fraction of its speed
+    const MIN_SPEED_FACTOR: f32 = 1e-4;
+    /// Height maps larger than this are downsampled, they are read by the vertex shader
+    const MAX_HEIGHTMAP_SIZE: [u32; 2] = [4096, 2048];
+    const DIRECTORY_VARIABLE: &str = "SOLAR_SYSTEM_HEIGHTMAP_DIR";
+    const DEFAULT_DIRECTORY: &str = "assets/heightmaps";
+
+    pub fn new<'p>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        materials: &mut material::PipelineCache,
+        camera_container: &camera::CameraContainer,
+        sun: &sun::Sun,
+        texture_container: &texture::TextureContainer,
+        bodies: impl IntoIterator<Item = &'p TerrainParameters>,
+    ) -> Self {
+        let directory = std::env::var(Self::DIRECTORY_VARIABLE)
+            .unwrap_or_else(|_| Self::DEFAULT_DIRECTORY.to_string());
+        let mut heightmap_loader = BackgroundLoader::new();
+        for body in bodies {
+            let (name, directory) = (body.name, directory.clone());
+            heightmap_loader
+                .spawn(move || (name, Self::load_heightmap(Path::new(&directory), name)));
+        }
+        let heightmaps_view = Self::create_heightmaps(device, queue, &[]);
+
+        let (vertices, indices) = Self::generate_grid();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("terrain_vertex_buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("terrain_index_buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let chunk_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Terrain Chunk Buffer"),
+            size: (mem::size_of::<ChunkRaw>() * Self::MAX_CHUNKS) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Terrain Uniform Buffer"),
+            size: mem::size_of::<TerrainUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = materials.bind_group_layout(
+            device,
+            "terrain_bind_group_layout",
+            &[
+                material::Binding::Uniform(
+                    wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ),
+                material::Binding::Texture {
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    dimension: wgpu::TextureViewDimension::D2Array,
+                    filterable: false,
+                },
+            ],
+        );
+        let bind_group = material::create_bind_group(
+            device,
+            "terrain_bind_group",
+            &bind_group_layout,
+            &[
+                uniform_buffer.as_entire_binding(),
+                wgpu::BindingResource::TextureView(&heightmaps_view),
+            ],
+        );
+
+        let material = materials.material(
+            device,
+            material::MaterialDescriptor {
+                name: "terrain",
+                shader: wgpu::include_wgsl!("../shaders/terrain.wgsl"),
+                bind_groups: &[
+                    (
+                        material::BindGroupSlot::Object,
+                        &texture_container.bind_group_layout,
+                    ),
+                    (
+                        material::BindGroupSlot::Camera,
+                        &camera_container.camera_bind_group_layout,
+                    ),
+                    (
+                        material::BindGroupSlot::Light,
+                        &sun.light().bind_group_layout,
+                    ),
+                    (material::BindGroupSlot::Object, &bind_group_layout),
+                ],
+                vertex_layouts: &[GridVertex::desc(), ChunkRaw::desc()],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                blending: material::Blending::Opaque,
+            },
+        );
+
+        Self {
+            heightmap_names: Vec::new(),
+            heightmap_loader,
+            loaded_heightmaps: Vec::new(),
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+            chunk_buffer,
+            chunk_count: 0,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            material,
+            altitude: None,
+        }
+    }
+
+    /// Uploads the height maps once they are all decoded, bodies without one stay flat
+    pub fn load_heightmaps(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let results = self.heightmap_loader.poll();
+        if results.is_empty() {
+            return;
+        }
+        for (name, heightmap) in results {
+            match heightmap {
+                Ok(heightmap) => self.loaded_heightmaps.push((name, heightmap)),
+                Err(e) => log::info!("Terrain of {name} is flat: {e:#}"),
+            }
+        }
+        if self.heightmap_loader.pending() > 0 || self.loaded_heightmaps.is_empty() {
+            return;
+        }
+
+        let (names, heightmaps): (Vec<_>, Vec<_>) = std::mem::take(&mut self.loaded_heightmaps)
+            .into_iter()
+            .unzip();
+        let heightmaps_view = Self::create_heightmaps(device, queue, &heightmaps);
+        self.bind_group = material::create_bind_group(
+            device,
+            "terrain_bind_group",
+            &self.bind_group_layout,
+            &[
+                self.uniform_buffer.as_entire_binding(),
+                wgpu::BindingResource::TextureView(&heightmaps_view),
+            ],
+        );
+        self.heightmap_names = names;
+    }
+
+    /// Height maps which are still being decoded
+    pub fn pending_heightmaps(&self) -> usize {
+        self.heightmap_loader.pending()
+    }
+
+    /// Height maps are grayscale equirectangular images, like the textures of the bodies
+    fn load_heightmap(directory: &Path, name: &str) -> Result<Heightmap> {
+        let heightmap = image::open(directory.join(format!("{name}.png")))?.to_luma32f();
+        let [max_width, max_height] = Self::MAX_HEIGHTMAP_SIZE;
+        if heightmap.width() <= max_width && heightmap.height() <= max_height {
+            return Ok(heightmap);
+        }
+        Ok(image::imageops::resize(
+            &heightmap,
+            heightmap.width().min(max_width),
+            heightmap.height().min(max_height),
+            image::imageops::FilterType::Triangle,
+        ))
+    }
+
+    /// All height maps are resized to the largest one, as they share a texture array
+    fn create_heightmaps(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        heightmaps: &[Heightmap],
+    ) -> wgpu::TextureView {
+        let width = heightmaps.iter().map(|map| map.width()).max().unwrap_or(1);
+        let height = heightmaps.iter().map(|map| map.height()).max().unwrap_or(1);
+        // Flat layers stand in when there are no maps. The GL backend creates textures with a
+        // single layer as plain 2D textures, which can't be viewed as arrays.
+        let layers = heightmaps.len().max(2) as u32;
+        let mut data = Vec::with_capacity((width * height * layers) as usize);
+        for heightmap in heightmaps {
+            if heightmap.dimensions() == (width, height) {
+                data.extend_from_slice(heightmap.as_raw());
+            } else {
+                let resized = image::imageops::resize(
+                    heightmap,
+                    width,
+                    height,
+                    image::imageops::FilterType::Triangle,
+                );
+                data.extend_from_slice(resized.as_raw());
+            }
+        }
+        data.resize((width * height * layers) as usize, 0.0);
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: layers,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("terrain-heightmaps"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&data),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        })
+    }
+
+    /// A grid of `GRID_SIZE` quads with a skirt along each of its edges
+    fn generate_grid() -> (Vec<GridVertex>, Vec<u16>) {
+        let size = Self::GRID_SIZE;
+        let row = size + 1;
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        for y in 0..row {
+            for x in 0..row {
+                vertices.push(GridVertex {
+                    grid_position: [x as f32 / size as f32, y as f32 / size as f32],
+                    skirt: 0.0,
+                });
+            }
+        }
+        for y in 0..size {
+            for x in 0..size {
+                let i = y * row + x;
+                let next = i + row;
+                indices.extend([i, i + 1, next, i + 1, next + 1, next]);
+            }
+        }
+
+        let edges: [Vec<u16>; 4] = [
+            (0..row).collect(),
+            (0..row).map(|x| size * row + x).collect(),
+            (0..row).map(|y| y * row).collect(),
+            (0..row).map(|y| y * row + size).collect(),
+        ];
+        for edge in edges {
+            let first_skirt = vertices.len() as u16;
+            for &i in &edge {
+                vertices.push(GridVertex {
+                    skirt: 1.0,
+                    ..vertices[i as usize]
+                });
+            }
+            // Skirts are seen from both sides, depending on the edge, so both windings are
+            // emitted instead of working out the orientation of each edge
+            for (k, pair) in edge.windows(2).enumerate() {
+                let (top_a, top_b) = (pair[0], pair[1]);
+                let (skirt_a, skirt_b) = (first_skirt + k as u16, first_skirt + k as u16 + 1);
+                indices.extend([top_a, skirt_a, top_b, top_b, skirt_a, skirt_b]);
+                indices.extend([top_a, top_b, skirt_a, top_b, skirt_b, skirt_a]);
+            }
+        }
+
+        (vertices, indices)
+    }
+
+    /// Whether the terrain should replace the sphere of a body
+    pub fn is_needed(instance: &Instance, origin: glam::DVec3, frustum: &culling::Frustum) -> bool {
+        let distance = instance.position.distance(origin) as f32;
+        frustum.projected_radius(distance, instance.scale()) > Self::MIN_PROJECTED_RADIUS
+    }
+
+    /// Fraction of the speed of the camera, which slows down close to the ground
+    pub fn speed_factor(&self) -> f32 {
+        self.altitude
+            .map_or(1.0, |altitude| altitude as f32)
+            .clamp(Self::MIN_SPEED_FACTOR, 1.0)
+    }
+
+    /// `origin` is the camera position, all positions are sent relative to it. Nothing is
+    /// drawn without a body.
+    pub fn sync_buffers(
+        &mut self,
+        queue: &wgpu::Queue,
+        body: Option<TerrainBody>,
+        origin: glam::DVec3,
+        frustum: &culling::Frustum,
+    ) {
+        let Some(body) = body else {
+            self.chunk_count = 0;
+            self.altitude = None;
+            return;
+        };
+        let radius = body.instance.scale() as f64;
+        let km = radius / body.radius_km;
+        let [lowest_km, highest_km] = body.parameters.elevation_km;
+        let heightmap_index = self
+            .heightmap_names
+            .iter()
+            .position(|&name| name == body.parameters.name);
+        let (lowest, highest) = match heightmap_index {
+            Some(_) => (radius + lowest_km * km, radius + highest_km * km),
+            None => (radius, radius),
+        };
+
+        let center = body.instance.position - origin;
+        let rotation = body.instance.rotation.as_dquat();
+        self.altitude = Some((center.length() - radius) / radius);
+
+        let mut chunks = Vec::new();
+        let mut stack = (0..6)
+            .map(|face| Chunk {
+                face,
+                offset: glam::DVec2::ZERO,
+                size: 1.0,
+                depth: 0,
+            })
+            .collect::<Vec<_>>();
+        while let Some(chunk) = stack.pop() {
+            if chunks.len() == Self::MAX_CHUNKS {
+                log::warn!("Too many terrain chunks, some of them are not drawn");
+                break;
+            }
+            let bounds = chunk.bounds(rotation, lowest, highest);
+            if !bounds.above_horizon(-center, lowest, highest) {
+                continue;
+            }
+            let bounds_center = center + bounds.center;
+            if !frustum.contains_sphere(bounds_center.as_vec3(), bounds.radius as f32) {
+                continue;
+            }
+            let size =
+                frustum.projected_radius(bounds_center.length() as f32, bounds.radius as f32);
+            if size > Self::SPLIT_RADIUS_PIXELS && chunk.depth < Self::MAX_DEPTH {
+                stack.extend(chunk.split());
+            } else {
+                chunks.push(ChunkRaw::new(&chunk));
+            }
+        }
+        self.chunk_count = chunks.len() as u32;
+        queue.write_buffer(&self.chunk_buffer, 0, bytemuck::cast_slice(&chunks));
+
+        let uniform = TerrainUniform {
+            model_matrix: glam::Mat4::from_rotation_translation(
+                body.instance.rotation,
+                center.as_vec3(),
+            )
+            .to_cols_array_2d(),
+            radius: radius as f32,
+            lowest: (lowest - radius) as f32,
+            height_range: (highest - lowest) as f32,
+            texture_index: body.instance.texture_index(),
+            normal_map_index: body
+                .instance
+                .normal_map_index()
+                .unwrap_or(instance::InstanceRaw::NO_TEXTURE),
+            heightmap_index: heightmap_index
+                .map_or(instance::InstanceRaw::NO_TEXTURE, |index| index as u32),
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+/// Point on a face of the cube, must match `face_point` in `terrain.wgsl`. The `s` and `t`
+/// axes of every face make the grid wind counter-clockwise seen from outside.
+fn face_point(face: u32, st: glam::DVec2) -> glam::DVec3 {
+    let (a, b) = (st.x * 2.0 - 1.0, st.y * 2.0 - 1.0);
+    match face {
+        0 => glam::DVec3::new(1.0, b, -a),
+        1 => glam::DVec3::new(-1.0, b, a),
+        2 => glam::DVec3::new(a, 1.0, -b),
+        3 => glam::DVec3::new(a, -1.0, b),
+        4 => glam::DVec3::new(a, b, 1.0),
+        _ => glam::DVec3::new(-a, b, -1.0),
+    }
+}
+
+/// Spreads the points of the cube more evenly over the sphere than normalizing them, must
+/// match `cube_to_sphere` in `terrain.wgsl`
+fn cube_to_sphere(p: glam::DVec3) -> glam::DVec3 {
+    let p2 = p * p;
+    let (yzx, zxy) = (
+        glam::DVec3::new(p2.y, p2.z, p2.x),
+        glam::DVec3::new(p2.z, p2.x, p2.y),
+    );
+    p * (1.0 - yzx * 0.5 - zxy * 0.5 + yzx * zxy / 3.0).map(f64::sqrt)
+}
+
+struct Chunk {
+    face: u32,
+    offset: glam::DVec2,
+    size: f64,
+    depth: u32,
+}
+
+/// Bounding sphere of a chunk, relative to the center of the body
+struct ChunkBounds {
+    center: glam::DVec3,
+    radius: f64,
+    direction: glam::DVec3,
+    /// Angle between the center of the chunk and its farthest corner
+    angular_radius: f64,
+}
+
+impl Chunk {
+    fn split(&self) -> [Chunk; 4] {
+        let size = self.size * 0.5;
+        [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]].map(|[x, y]| Chunk {
+            face: self.face,
+            offset: self.offset + glam::DVec2::new(x, y) * size,
+            size,
+            depth: self.depth + 1,
+        })
+    }
+
+    fn bounds(&self, rotation: glam::DQuat, lowest: f64, highest: f64) -> ChunkBounds {
+        let direction_at = |s: f64, t: f64| {
+            let st = self.offset + glam::DVec2::new(s, t) * self.size;
+            rotation * cube_to_sphere(face_point(self.face, st))
+        };
+        let direction = direction_at(0.5, 0.5);
+        let angular_radius = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]]
+            .iter()
+            .map(|&[s, t]| direction.angle_between(direction_at(s, t)))
+            .fold(0.0, f64::max);
+        // Chord from the center to the farthest corner at the highest elevation, plus the
+        // spread of the elevations
+        let radius = 2.0 * highest * (angular_radius * 0.5).sin() + (highest - lowest);
+        ChunkBounds {
+            center: direction * (lowest + highest) * 0.5,
+            radius,
+            direction,
+            angular_radius,
+        }
+    }
+}
+
+impl ChunkBounds {
+    /// Whether any part of the chunk can be seen over the horizon from `camera` (relative to
+    /// the center of the body)
+    fn above_horizon(&self, camera: glam::DVec3, lowest: f64, highest: f64) -> bool {
+        let distance = camera.length();
+        if distance <= lowest {
+            return true;
+        }
+        // Horizon of the camera, and how far behind it the highest peaks stay visible
+        let horizon = (lowest / distance).acos() + (lowest / highest).acos();
+        self.direction.angle_between(camera) <= horizon + self.angular_radius
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridVertex {
+    grid_position: [f32; 2],
+    skirt: f32,
+}
+
+impl Vertex for GridVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<GridVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ChunkRaw {
+    offset: [f32; 2],
+    size: f32,
+    face: u32,
+}
+
+impl ChunkRaw {
+    fn new(chunk: &Chunk) -> Self {
+        Self {
+            offset: chunk.offset.as_vec2().into(),
+            size: chunk.size as f32,
+            face: chunk.face,
+        }
+    }
+}
+
+impl Vertex for ChunkRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ChunkRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
+}
+
+/// Must match `TerrainUniform` in `terrain.wgsl`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TerrainUniform {
+    model_matrix: [[f32; 4]; 4],
+    radius: f32,
+    lowest: f32,
+    height_range: f32,
+    texture_index: u32,
+    normal_map_index: u32,
+    heightmap_index: u32,
+    _padding: [u32; 2],
+}
+
+impl Terrain {
+    /// Shares the texture arrays of the planets, `textures` is their bind group
+    pub fn draw(
+        &self,
+        pass: &mut wgpu::RenderPass,
+        scene: &material::SceneBindGroups,
+        textures: &wgpu::BindGroup,
+    ) {
+        if self.chunk_count == 0 {
+            return;
+        }
+        pass.set_material(&self.material, scene, &[textures, &self.bind_group]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.chunk_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        pass.draw_indexed(0..self.index_count, 0, 0..self.chunk_count);
+    }
+}
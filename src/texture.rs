@@ -1,6 +1,55 @@
-use anyhow::Result;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
 use image::GenericImageView;
 
+use crate::{
+    background::BackgroundLoader,
+    ktx2::{CompressedFormat, Ktx2Cubemap},
+    material,
+    surface_maps::SurfaceMapSource,
+};
+
+/// Image of the surface of a body, which fills the six layers of one cubemap of an array
+pub enum CubeTextureSource {
+    /// Equirectangular map, converted to the faces of a cubemap on the GPU
+    Equirectangular(image::RgbaImage),
+    /// Faces in the order of the cubemap layers (+X, -X, +Y, -Y, +Z, -Z)
+    CubeFaces([image::RgbaImage; 6]),
+}
+
+impl CubeTextureSource {
+    /// A directory named like the image without its extension (e.g. `mars/` next to
+    /// `mars.jpg`), with the six faces of a cubemap, replaces the equirectangular image
+    pub fn load(path: &str) -> Result<Self> {
+        let path = Path::new(path);
+        let directory = path.with_extension("");
+        if !directory.is_dir() {
+            let image =
+                image::open(path).with_context(|| format!("Failed to load {}", path.display()))?;
+            return Ok(Self::Equirectangular(image.to_rgba8()));
+        }
+        let faces = CubeTexture::FACE_NAMES
+            .map(|name| CubeTexture::find_face(&directory, name))
+            .into_iter()
+            .map(|path| {
+                let path = path?;
+                Ok(image::open(&path)
+                    .with_context(|| format!("Failed to load {}", path.display()))?
+                    .to_rgba8())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let faces: [image::RgbaImage; 6] = faces
+            .try_into()
+            .map_err(|_| anyhow!("Expected six faces"))?;
+        Ok(Self::CubeFaces(faces))
+    }
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -9,16 +58,6 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn from_bytes(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        bytes: &[u8],
-        label: &str,
-    ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
-    }
-
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -80,70 +119,26 @@ impl Texture {
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// The depth buffer is reversed (near plane at 1.0, infinitely far at 0.0). Together
+    /// with a floating point format this spreads the precision evenly across distances,
+    /// so there is no z-fighting from right above a planet up to the edge of the system.
+    pub const DEPTH_CLEAR_VALUE: f32 = 0.0;
+    pub const DEPTH_COMPARE: wgpu::CompareFunction = wgpu::CompareFunction::GreaterEqual;
 
-    pub fn create_depth_texture(
-        device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
-        label: &str,
-    ) -> Self {
-        let size = wgpu::Extent3d {
-            width: config.width.max(1),
-            height: config.height.max(1),
-            depth_or_array_layers: 1,
-        };
-        let desc = wgpu::TextureDescriptor {
-            label: Some(label),
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
-        };
-        let texture = device.create_texture(&desc);
-
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual),
-            lod_min_clamp: 0.0,
-            lod_max_clamp: 100.0,
-            ..Default::default()
-        });
-
-        Self {
-            texture,
-            view,
-            sampler,
-            size,
-        }
-    }
-
-    /// IMPORTANT NOTE: each texture used in this array must be of same size
-    pub fn create_texture_array(
+    /// IMPORTANT NOTE: each image used in this array must be of same size
+    pub fn create_texture_array_from_images(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        image_paths: &[&str],
+        images: &[image::RgbaImage],
+        format: wgpu::TextureFormat,
         label: &str,
     ) -> Self {
-        let images: Vec<_> = image_paths
-            .iter()
-            .map(|path| image::open(path).expect("Failed to load image"))
-            .collect();
-
         let (width, height) = images[0].dimensions();
         let layer_count = images.len() as u32;
 
         let mut texture_data = Vec::with_capacity((width * height * 4 * layer_count) as usize);
-        for img in &images {
-            let rgba = img.to_rgba8();
-            texture_data.extend_from_slice(&rgba);
+        for img in images {
+            texture_data.extend_from_slice(img);
         }
 
         let texture_size = wgpu::Extent3d {
@@ -158,7 +153,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
@@ -198,6 +193,179 @@ impl Texture {
         }
     }
 
+    /// Replaces the layer `layer` of a texture made with
+    /// [`Self::create_texture_array_from_images`], `image` must have the size of the layers
+    pub fn write_layer(&self, queue: &wgpu::Queue, layer: u32, image: &image::RgbaImage) {
+        let (width, height) = image.dimensions();
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            image,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Array of `cubemaps` cubemaps, viewed as a cube array. Their faces are written with a
+    /// [`CubeFaceWriter`].
+    pub fn create_cube_texture_array(
+        device: &wgpu::Device,
+        cubemaps: u32,
+        face_size: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        // Storage textures can't be sRGB, so the faces are written as they are stored and
+        // only read through a view in `format`
+        let storage_format = format.remove_srgb_suffix();
+        let layers = CubeTexture::CUMBEMAP_LAYERS * cubemaps;
+        let size = wgpu::Extent3d {
+            width: face_size,
+            height: face_size,
+            depth_or_array_layers: layers,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: storage_format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[format],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(format),
+            dimension: Some(wgpu::TextureViewDimension::CubeArray),
+            array_layer_count: Some(layers),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            size,
+        }
+    }
+
+    /// Every cubemap fills six layers of the texture, which is viewed as an array of cubemaps.
+    /// They must all have the same format, size and number of mipmaps.
+    pub fn create_compressed_cube_texture_array(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cubemaps: &[Ktx2Cubemap],
+        label: &str,
+    ) -> Result<Self> {
+        let first = cubemaps.first().ok_or_else(|| anyhow!("No textures"))?;
+        if cubemaps.iter().any(|cubemap| {
+            cubemap.format != first.format
+                || cubemap.size != first.size
+                || cubemap.levels.len() != first.levels.len()
+        }) {
+            bail!("Textures have different formats, sizes or mipmaps");
+        }
+        let format = first.format.texture_format();
+        let size = wgpu::Extent3d {
+            width: first.size,
+            height: first.size,
+            depth_or_array_layers: CubeTexture::CUMBEMAP_LAYERS * cubemaps.len() as u32,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: first.levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None).unwrap_or(0);
+        for (i, cubemap) in cubemaps.iter().enumerate() {
+            for (level, data) in cubemap.levels.iter().enumerate() {
+                // Levels smaller than a block still take a whole block
+                let level_size = wgpu::Extent3d {
+                    width: (first.size >> level).max(1),
+                    height: (first.size >> level).max(1),
+                    depth_or_array_layers: CubeTexture::CUMBEMAP_LAYERS,
+                }
+                .physical_size(format);
+                queue.write_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        texture: &texture,
+                        mip_level: level as u32,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: i as u32 * CubeTexture::CUMBEMAP_LAYERS,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    data,
+                    wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(level_size.width / block_width * block_size),
+                        rows_per_image: Some(level_size.height / block_height),
+                    },
+                    level_size,
+                );
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::CubeArray),
+            array_layer_count: Some(size.depth_or_array_layers),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            size,
+        })
+    }
+
     pub fn create_2d_texture(
         device: &wgpu::Device,
         width: u32,
@@ -263,6 +431,201 @@ impl Texture {
     }
 }
 
+/// Fills single cubemaps of a texture made with [`Texture::create_cube_texture_array`], so
+/// they can be replaced one by one as their images are decoded. Faces are resized to the
+/// size of the array, equirectangular images are converted on the GPU.
+pub struct CubeFaceWriter {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    sampler: wgpu::Sampler,
+}
+
+impl CubeFaceWriter {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let module = device.create_shader_module(wgpu::include_wgsl!("../shaders/cube_faces.wgsl"));
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("cube_faces_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba8Unorm,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("equirect_to_cube_faces"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: Some("compute_equirect_to_cube_faces"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        // Equirectangular maps wrap around the meridian
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            layout,
+            pipeline,
+            sampler,
+        }
+    }
+
+    /// Writes the six layers of the cubemap `cubemap` of `texture`
+    pub fn write(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &Texture,
+        cubemap: u32,
+        source: &CubeTextureSource,
+    ) {
+        let face_size = texture.size.width;
+        let first_layer = cubemap * CubeTexture::CUMBEMAP_LAYERS;
+        match source {
+            CubeTextureSource::CubeFaces(faces) => {
+                for (layer, face) in faces.iter().enumerate() {
+                    let face = if face.dimensions() == (face_size, face_size) {
+                        face.clone()
+                    } else {
+                        image::imageops::resize(
+                            face,
+                            face_size,
+                            face_size,
+                            image::imageops::FilterType::Triangle,
+                        )
+                    };
+                    queue.write_texture(
+                        wgpu::TexelCopyTextureInfo {
+                            texture: &texture.texture,
+                            mip_level: 0,
+                            origin: wgpu::Origin3d {
+                                x: 0,
+                                y: 0,
+                                z: first_layer + layer as u32,
+                            },
+                            aspect: wgpu::TextureAspect::All,
+                        },
+                        &face,
+                        wgpu::TexelCopyBufferLayout {
+                            offset: 0,
+                            bytes_per_row: Some(4 * face_size),
+                            rows_per_image: Some(face_size),
+                        },
+                        wgpu::Extent3d {
+                            width: face_size,
+                            height: face_size,
+                            depth_or_array_layers: 1,
+                        },
+                    );
+                }
+            }
+            CubeTextureSource::Equirectangular(image) => {
+                let (width, height) = image.dimensions();
+                let src = Texture::create_2d_texture(
+                    device,
+                    width,
+                    height,
+                    texture.texture.format(),
+                    wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    wgpu::FilterMode::Linear,
+                    None,
+                );
+                queue.write_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        texture: &src.texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d::ZERO,
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    image,
+                    wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(4 * width),
+                        rows_per_image: Some(height),
+                    },
+                    src.size,
+                );
+
+                // Cubemap views can't be used with a `STORAGE_BINDING`, the six layers are
+                // written directly
+                let dst_view = texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2Array),
+                    base_array_layer: first_layer,
+                    array_layer_count: Some(CubeTexture::CUMBEMAP_LAYERS),
+                    ..Default::default()
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: None,
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&src.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&dst_view),
+                        },
+                    ],
+                });
+
+                let mut encoder = device.create_command_encoder(&Default::default());
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("equirect_to_cube_faces"),
+                    timestamp_writes: None,
+                });
+                let num_workgroups = face_size.div_ceil(16);
+                pass.set_pipeline(&self.pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.dispatch_workgroups(
+                    num_workgroups,
+                    num_workgroups,
+                    CubeTexture::CUMBEMAP_LAYERS,
+                );
+                drop(pass);
+                queue.submit([encoder.finish()]);
+            }
+        }
+    }
+}
+
 pub struct CubeTexture {
     texture: wgpu::Texture,
     sampler: wgpu::Sampler,
@@ -270,7 +633,18 @@ pub struct CubeTexture {
 }
 
 impl CubeTexture {
-    const CUMBEMAP_LAYERS: u32 = 6;
+    pub const CUMBEMAP_LAYERS: u32 = 6;
+    /// File names (without the extension) of the faces in a directory, in the order of the
+    /// cubemap layers
+    pub const FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+    /// Face of a cubemap in `directory`, with any extension
+    pub fn find_face(directory: &Path, name: &str) -> Result<PathBuf> {
+        std::fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .find(|path| path.is_file() && path.file_stem().is_some_and(|stem| stem == name))
+            .ok_or_else(|| anyhow!("Missing face {} in {}", name, directory.display()))
+    }
 
     #[allow(clippy::too_many_arguments)]
     pub fn create_2d(
@@ -337,115 +711,408 @@ impl CubeTexture {
 }
 
 pub struct TextureContainer {
-    _texture: Texture,
+    textures: Vec<Texture>,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
 }
 
 impl TextureContainer {
-    const TEXTURE_BIND_GROUP_ID: u32 = 0;
-    const TEXTURE_ARRAY_CONTAINER_ID: u32 = 0;
+    /// Planet textures are cubemaps, so they don't pinch at the poles. A quarter of the width
+    /// of the equirectangular textures keeps their resolution along the equator.
+    pub const PLANETS_FACE_SIZE: u32 = 512;
+    /// Layers of the planets texture array, see the texture indices of the planets
+    pub const PLANET_TEXTURES: [&str; 9] = [
+        "./assets/textures/mercury.jpg",
+        "./assets/textures/venus.jpg",
+        "./assets/textures/earth.jpg",
+        "./assets/textures/mars.jpg",
+        "./assets/textures/jupiter.jpg",
+        "./assets/textures/saturn.jpg",
+        "./assets/textures/uranus.jpg",
+        "./assets/textures/neptune.jpg",
+        "./assets/textures/earth_night.jpg",
+    ];
+    /// Average colors of the planet textures, shown until they are decoded
+    pub const PLANET_PLACEHOLDER_COLORS: [[u8; 4]; 9] = [
+        [151, 146, 140, 255],
+        [206, 176, 120, 255],
+        [38, 62, 100, 255],
+        [176, 108, 70, 255],
+        [186, 162, 136, 255],
+        [208, 188, 146, 255],
+        [172, 212, 222, 255],
+        [72, 110, 196, 255],
+        [4, 4, 6, 255],
+    ];
+    /// Size of the low resolution versions of the planet textures shown while they load,
+    /// see [`Self::thumbnail_path`]
+    pub const THUMBNAIL_SIZE: (u32, u32) = (128, 64);
+    /// Layers of the planets surface maps array
+    const PLANET_SURFACE_MAPS: [SurfaceMapSource; 3] = [
+        SurfaceMapSource::NormalFromHeight {
+            path: "./assets/textures/mars.jpg",
+            strength: 2.5,
+        },
+        SurfaceMapSource::NormalFromHeight {
+            path: "./assets/textures/mercury.jpg",
+            strength: 2.0,
+        },
+        SurfaceMapSource::OceanGloss("./assets/textures/earth.jpg"),
+    ];
 
     pub fn new(
         texture: Texture,
         bind_group: wgpu::BindGroup,
         bind_group_layout: wgpu::BindGroupLayout,
+    ) -> Self {
+        Self::from_textures(vec![texture], bind_group, bind_group_layout)
+    }
+
+    pub fn from_textures(
+        textures: Vec<Texture>,
+        bind_group: wgpu::BindGroup,
+        bind_group_layout: wgpu::BindGroupLayout,
     ) -> Self {
         Self {
-            _texture: texture,
+            textures,
             bind_group,
             bind_group_layout,
         }
     }
 
+    /// The only texture of a container made with [`Self::new`]
+    pub fn texture(&self) -> &Texture {
+        &self.textures[0]
+    }
+
+    /// Replaces the only texture of a container made with [`Self::new`]
+    pub fn replace_texture(&mut self, texture: Texture, bind_group: wgpu::BindGroup) {
+        self.textures = vec![texture];
+        self.bind_group = bind_group;
+    }
+
+    /// `thumbnails/<name>.png` next to the image, made by `convert-textures --thumbnails`
+    pub fn thumbnail_path(path: &str) -> PathBuf {
+        let path = Path::new(path);
+        let name = path.file_stem().unwrap_or_default();
+        path.with_file_name("thumbnails")
+            .join(name)
+            .with_extension("png")
+    }
+
+    /// Starts with flat colors (see [`Self::PLANET_PLACEHOLDER_COLORS`]), then every layer is
+    /// replaced by its thumbnail (see [`Self::thumbnail_path`]) and finally by the full
+    /// resolution image, or all the diffuse layers at once by the compressed textures. Images
+    /// are read and decoded on background threads and written by the returned loader as they
+    /// are ready.
     pub fn initialize_plantes_texture_array_container(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-    ) -> Self {
-        let diffuse_texture = Texture::create_texture_array(
+        materials: &mut material::PipelineCache,
+    ) -> (Self, PlanetTextureLoader) {
+        let mut loader = PlanetTextureLoader::new(device);
+
+        let diffuse_texture = Texture::create_cube_texture_array(
             device,
-            queue,
-            &[
-                "./assets/textures/mercury.jpg",
-                "./assets/textures/venus.jpg",
-                "./assets/textures/earth.jpg",
-                "./assets/textures/mars.jpg",
-                "./assets/textures/jupiter.jpg",
-                "./assets/textures/saturn.jpg",
-                "./assets/textures/uranus.jpg",
-                "./assets/textures/neptune.jpg",
-                "./assets/textures/earth_night.jpg",
-            ],
+            Self::PLANET_TEXTURES.len() as u32,
+            Self::PLANETS_FACE_SIZE,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
             "planets-textures",
         );
+        for (i, (path, color)) in Self::PLANET_TEXTURES
+            .into_iter()
+            .zip(Self::PLANET_PLACEHOLDER_COLORS)
+            .enumerate()
+        {
+            loader.write_placeholder(device, queue, &diffuse_texture, i as u32, color);
+            loader.load_thumbnail(PlanetTextureKind::Diffuse, i as u32, move || {
+                CubeTextureSource::load(&Self::thumbnail_path(path).to_string_lossy())
+            });
+        }
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2Array,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_array_bind_group_layout"),
+        // Layers referenced by the normal and specular map indices of the planets
+        let surface_maps_texture = Texture::create_cube_texture_array(
+            device,
+            Self::PLANET_SURFACE_MAPS.len() as u32,
+            Self::PLANETS_FACE_SIZE,
+            wgpu::TextureFormat::Rgba8Unorm,
+            "planets-surface-maps",
+        );
+        for (i, source) in Self::PLANET_SURFACE_MAPS.into_iter().enumerate() {
+            let placeholder = source.placeholder();
+            loader.write_placeholder(device, queue, &surface_maps_texture, i as u32, placeholder);
+            loader.load_thumbnail(PlanetTextureKind::SurfaceMaps, i as u32, move || {
+                let map = source
+                    .generate_from(&Self::thumbnail_path(source.path()))
+                    .context("Failed to generate surface map")?;
+                Ok(CubeTextureSource::Equirectangular(map))
             });
+        }
 
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: Some("texture_array_bind_group"),
-        });
+        // Queued after the thumbnails, so that they are ready first
+        match Self::compressed_planet_textures_format(device) {
+            Some(format) => loader.load_compressed(format),
+            None => loader.load_diffuse(),
+        }
+        for (i, source) in Self::PLANET_SURFACE_MAPS.into_iter().enumerate() {
+            loader.load(PlanetTextureKind::SurfaceMaps, i as u32, move || {
+                let map = source
+                    .generate()
+                    .context("Failed to generate surface map")?;
+                Ok(CubeTextureSource::Equirectangular(map))
+            });
+        }
 
-        Self::new(
-            diffuse_texture,
+        let cube_array = material::Binding::fragment_texture(wgpu::TextureViewDimension::CubeArray);
+        let texture_bind_group_layout = materials.bind_group_layout(
+            device,
+            "texture_array_bind_group_layout",
+            &[cube_array, material::Binding::FRAGMENT_SAMPLER, cube_array],
+        );
+        let diffuse_bind_group = Self::create_planets_bind_group(
+            device,
+            &texture_bind_group_layout,
+            &diffuse_texture,
+            &surface_maps_texture,
+        );
+
+        let container = Self::from_textures(
+            vec![diffuse_texture, surface_maps_texture],
             diffuse_bind_group,
             texture_bind_group_layout,
+        );
+        (container, loader)
+    }
+
+    fn create_planets_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        diffuse_texture: &Texture,
+        surface_maps_texture: &Texture,
+    ) -> wgpu::BindGroup {
+        material::create_bind_group(
+            device,
+            "texture_array_bind_group",
+            layout,
+            &[
+                wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                wgpu::BindingResource::TextureView(&surface_maps_texture.view),
+            ],
         )
     }
 }
 
-pub trait SetTextureContainer<'a> {
-    fn set_texture_container(&mut self, texture_container: &'a TextureContainer);
-    fn set_texture_array_container(&mut self, texture_container: &'a TextureContainer);
+impl TextureContainer {
+    /// The first format the device supports and all the compressed planet textures (made by
+    /// the `convert-textures` subcommand) exist for, or `None` to decode the images
+    fn compressed_planet_textures_format(device: &wgpu::Device) -> Option<CompressedFormat> {
+        let supported = CompressedFormat::supported(device);
+        let format = supported.iter().copied().find(|format| {
+            Self::PLANET_TEXTURES
+                .iter()
+                .all(|path| format.path_for(Path::new(path)).exists())
+        });
+        if format.is_none() {
+            let names: Vec<_> = supported.iter().map(|format| format.name()).collect();
+            log::info!(
+                "Planet textures are not compressed: no files in a supported format ({})",
+                names.join(", ")
+            );
+        }
+        format
+    }
 }
 
-impl<'a, 'b> SetTextureContainer<'b> for wgpu::RenderPass<'a>
-where
-    'b: 'a,
-{
-    fn set_texture_container(&mut self, texture_container: &'b TextureContainer) {
-        self.set_bind_group(
-            TextureContainer::TEXTURE_BIND_GROUP_ID,
-            &texture_container.bind_group,
-            &[],
-        );
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PlanetTextureKind {
+    Diffuse,
+    SurfaceMaps,
+}
+
+/// Cubemap of the planet textures decoded on a background thread
+struct PlanetTextureLayer {
+    kind: PlanetTextureKind,
+    cubemap: u32,
+    /// Written only until the full resolution layer is
+    thumbnail: bool,
+    source: Result<CubeTextureSource>,
+}
+
+/// Result of a background job of the [`PlanetTextureLoader`]
+enum PlanetTextureJob {
+    Layer(PlanetTextureLayer),
+    /// All the diffuse cubemaps, in the order of [`TextureContainer::PLANET_TEXTURES`]
+    Compressed(CompressedFormat, Result<Vec<Ktx2Cubemap>>),
+}
+
+/// Replaces the placeholders of the planet texture arrays with the images decoded on
+/// background threads
+pub struct PlanetTextureLoader {
+    loader: BackgroundLoader<PlanetTextureJob>,
+    writer: CubeFaceWriter,
+    /// Layers written at full resolution, which thumbnails finishing late must not replace
+    full_resolution: HashSet<(PlanetTextureKind, u32)>,
+    start: Instant,
+}
+
+impl PlanetTextureLoader {
+    fn new(device: &wgpu::Device) -> Self {
+        Self {
+            loader: BackgroundLoader::new(),
+            writer: CubeFaceWriter::new(device),
+            full_resolution: HashSet::new(),
+            start: Instant::now(),
+        }
     }
-    fn set_texture_array_container(&mut self, texture_container: &'b TextureContainer) {
-        self.set_bind_group(
-            TextureContainer::TEXTURE_ARRAY_CONTAINER_ID,
-            &texture_container.bind_group,
-            &[],
-        );
+
+    fn load(
+        &mut self,
+        kind: PlanetTextureKind,
+        cubemap: u32,
+        job: impl FnOnce() -> Result<CubeTextureSource> + Send + 'static,
+    ) {
+        self.spawn(kind, cubemap, false, job);
+    }
+
+    fn load_thumbnail(
+        &mut self,
+        kind: PlanetTextureKind,
+        cubemap: u32,
+        job: impl FnOnce() -> Result<CubeTextureSource> + Send + 'static,
+    ) {
+        self.spawn(kind, cubemap, true, job);
+    }
+
+    fn spawn(
+        &mut self,
+        kind: PlanetTextureKind,
+        cubemap: u32,
+        thumbnail: bool,
+        job: impl FnOnce() -> Result<CubeTextureSource> + Send + 'static,
+    ) {
+        self.loader.spawn(move || {
+            PlanetTextureJob::Layer(PlanetTextureLayer {
+                kind,
+                cubemap,
+                thumbnail,
+                source: job(),
+            })
+        });
+    }
+
+    /// Decodes the images of all the diffuse layers
+    fn load_diffuse(&mut self) {
+        for (i, path) in TextureContainer::PLANET_TEXTURES.into_iter().enumerate() {
+            self.load(PlanetTextureKind::Diffuse, i as u32, move || {
+                CubeTextureSource::load(path)
+            });
+        }
+    }
+
+    /// Reads the compressed diffuse textures, which replace the whole diffuse array at once
+    fn load_compressed(&mut self, format: CompressedFormat) {
+        self.loader.spawn(move || {
+            let cubemaps = TextureContainer::PLANET_TEXTURES
+                .iter()
+                .map(|path| Ktx2Cubemap::open(&format.path_for(Path::new(path))))
+                .collect();
+            PlanetTextureJob::Compressed(format, cubemaps)
+        });
+    }
+
+    fn write_placeholder(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &Texture,
+        cubemap: u32,
+        color: [u8; 4],
+    ) {
+        let image = image::RgbaImage::from_pixel(1, 1, image::Rgba(color));
+        let source = CubeTextureSource::Equirectangular(image);
+        self.writer.write(device, queue, texture, cubemap, &source);
+    }
+
+    /// Writes the layers decoded since the last call into `container`, layers which failed
+    /// to load keep their placeholder or thumbnail. If the compressed textures fail to load,
+    /// the images are decoded instead.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        container: &mut TextureContainer,
+    ) {
+        let was_loading = self.loader.pending() > 0;
+        for job in self.loader.poll() {
+            match job {
+                PlanetTextureJob::Layer(layer) => self.write_layer(device, queue, container, layer),
+                PlanetTextureJob::Compressed(format, cubemaps) => {
+                    let texture = cubemaps.and_then(|cubemaps| {
+                        Texture::create_compressed_cube_texture_array(
+                            device,
+                            queue,
+                            &cubemaps,
+                            "planets-textures",
+                        )
+                    });
+                    match texture {
+                        Ok(texture) => {
+                            log::info!("Planet textures are compressed with {}", format.name());
+                            container.bind_group = TextureContainer::create_planets_bind_group(
+                                device,
+                                &container.bind_group_layout,
+                                &texture,
+                                &container.textures[1],
+                            );
+                            container.textures[0] = texture;
+                            // The compressed texture can't be written by the CubeFaceWriter
+                            let layers = TextureContainer::PLANET_TEXTURES.len() as u32;
+                            self.full_resolution
+                                .extend((0..layers).map(|i| (PlanetTextureKind::Diffuse, i)));
+                        }
+                        Err(e) => {
+                            log::info!("Planet textures are not compressed: {e:#}");
+                            self.load_diffuse();
+                        }
+                    }
+                }
+            }
+        }
+        if was_loading && self.loader.pending() == 0 {
+            log::info!("Planet textures loaded in {:?}", self.start.elapsed());
+        }
+    }
+
+    fn write_layer(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        container: &TextureContainer,
+        layer: PlanetTextureLayer,
+    ) {
+        let key = (layer.kind, layer.cubemap);
+        if layer.thumbnail && self.full_resolution.contains(&key) {
+            return;
+        }
+        let texture = match layer.kind {
+            PlanetTextureKind::Diffuse => &container.textures[0],
+            PlanetTextureKind::SurfaceMaps => &container.textures[1],
+        };
+        match layer.source {
+            Ok(source) => {
+                self.writer
+                    .write(device, queue, texture, layer.cubemap, &source);
+                if !layer.thumbnail {
+                    self.full_resolution.insert(key);
+                }
+            }
+            Err(e) => log::error!("{:?} layer {}: {e:#}", layer.kind, layer.cubemap),
+        }
+    }
+
+    /// Layers which are still being decoded
+    pub fn pending(&self) -> usize {
+        self.loader.pending()
     }
 }
@@ -0,0 +1,194 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow, bail};
+
+use crate::{clock::SimulationClock, ephemeris::Ephemeris};
+
+/// Single periodic term, evaluated as `amplitude * cos(phase + frequency * t)`
+struct Term {
+    amplitude: f64,
+    phase: f64,
+    frequency: f64,
+}
+
+/// Series of a single coordinate, indexed by the power of time multiplying them
+#[derive(Default)]
+struct Coordinate {
+    series: Vec<Vec<Term>>,
+}
+
+impl Coordinate {
+    fn evaluate(&self, t: f64) -> f64 {
+        let mut result = 0.0;
+        let mut t_power = 1.0;
+        for terms in &self.series {
+            let sum = terms
+                .iter()
+                .map(|term| term.amplitude * (term.phase + term.frequency * t).cos())
+                .sum::<f64>();
+            result += sum * t_power;
+            t_power *= t;
+        }
+        result
+    }
+}
+
+/// Planetary theory VSOP87 (Bretagnon & Francou, 1988), version A: heliocentric
+/// rectangular coordinates in the ecliptic frame of J2000. The series are too large to be
+/// bundled, they are read from the original data files (`VSOP87A.mer` ... `VSOP87A.nep`),
+/// available from the IMCCE or the VizieR catalogue VI/81.
+pub struct Vsop87 {
+    /// X, Y and Z of every planet
+    planets: Vec<[Coordinate; 3]>,
+}
+
+impl Vsop87 {
+    const FILE_EXTENSIONS: [&str; 8] = ["mer", "ven", "ear", "mar", "jup", "sat", "ura", "nep"];
+    /// Environment variable with the directory holding the data files
+    const DIRECTORY_VARIABLE: &str = "SOLAR_SYSTEM_VSOP87_DIR";
+    const DEFAULT_DIRECTORY: &str = "assets/ephemeris";
+
+    /// Loads the data files from `SOLAR_SYSTEM_VSOP87_DIR`, or `assets/ephemeris` if it is
+    /// not set
+    pub fn from_env() -> Result<Self> {
+        let directory = std::env::var(Self::DIRECTORY_VARIABLE)
+            .unwrap_or_else(|_| Self::DEFAULT_DIRECTORY.to_owned());
+        Self::load(Path::new(&directory))
+    }
+
+    pub fn load(directory: &Path) -> Result<Self> {
+        let planets = Self::FILE_EXTENSIONS
+            .iter()
+            .map(|extension| {
+                let path = directory.join(format!("VSOP87A.{extension}"));
+                let contents = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                Self::parse(&contents).with_context(|| format!("Invalid {}", path.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { planets })
+    }
+
+    /// Every block of terms starts with a header like
+    /// ` VSOP87 VERSION A1    EARTH     VARIABLE 1 (XYZ)       *T**0   1007 TERMS ...`,
+    /// followed by lines of fixed width, ending with the amplitude, phase and frequency.
+    fn parse(contents: &str) -> Result<[Coordinate; 3]> {
+        let mut coordinates: [Coordinate; 3] = Default::default();
+        let mut current: Option<(usize, usize)> = None;
+
+        for (line_number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if line.trim_start().starts_with("VSOP87") {
+                current = Some(
+                    Self::parse_header(line)
+                        .with_context(|| format!("Invalid header at line {}", line_number + 1))?,
+                );
+                continue;
+            }
+
+            let (variable, power) =
+                current.ok_or_else(|| anyhow!("Terms before the first header"))?;
+            let values = line
+                .split_whitespace()
+                .rev()
+                .take(3)
+                .map(|value| value.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Invalid term at line {}", line_number + 1))?;
+            let [frequency, phase, amplitude] = values[..] else {
+                bail!("Invalid term at line {}", line_number + 1);
+            };
+
+            let series = &mut coordinates[variable].series;
+            if series.len() <= power {
+                series.resize_with(power + 1, Vec::new);
+            }
+            series[power].push(Term {
+                amplitude,
+                phase,
+                frequency,
+            });
+        }
+
+        Ok(coordinates)
+    }
+
+    /// Returns the index of the coordinate and the power of time
+    fn parse_header(line: &str) -> Result<(usize, usize)> {
+        let mut tokens = line.split_whitespace();
+        tokens
+            .by_ref()
+            .find(|token| *token == "VARIABLE")
+            .ok_or_else(|| anyhow!("Missing variable"))?;
+        let variable = tokens
+            .next()
+            .ok_or_else(|| anyhow!("Missing variable"))?
+            .parse::<usize>()?;
+        let power = tokens
+            .find_map(|token| token.strip_prefix("*T**"))
+            .ok_or_else(|| anyhow!("Missing power of time"))?
+            .parse::<usize>()?;
+        if !(1..=3).contains(&variable) {
+            bail!("Variable {variable} out of range");
+        }
+        Ok((variable - 1, power))
+    }
+}
+
+impl Ephemeris for Vsop87 {
+    fn name(&self) -> &'static str {
+        "VSOP87"
+    }
+
+    fn position(&self, planet: usize, julian_date: f64) -> glam::DVec3 {
+        // Time is measured in Julian millennia
+        let t = (julian_date - SimulationClock::J2000) / 365_250.0;
+        let [x, y, z] = &self.planets[planet];
+        glam::DVec3::new(x.evaluate(t), y.evaluate(t), z.evaluate(t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_series() {
+        let contents = "\
+ VSOP87 VERSION A1    EARTH     VARIABLE 1 (XYZ)       *T**0      2 TERMS    HIGHER ACCURACY
+ 1310    1  0  0  0  0  0  0  0  0  0  0  0  0     0.99 0.5 0.0 0.0
+ 1310    2  0  0  0  0  0  0  0  0  0  0  0  0     0.01 0.25 1.0 2.0
+ VSOP87 VERSION A1    EARTH     VARIABLE 3 (XYZ)       *T**1      1 TERMS    HIGHER ACCURACY
+ 1331    1  0  0  0  0  0  0  0  0  0  0  0  0     0.01 0.125 0.0 0.0
+";
+        let [x, y, z] = Vsop87::parse(contents).unwrap();
+        assert_eq!(x.evaluate(0.0), 0.5 + 0.25 * 1f64.cos());
+        assert_eq!(y.evaluate(1.0), 0.0);
+        assert_eq!(z.evaluate(2.0), 0.25);
+    }
+
+    #[test]
+    fn rejects_terms_without_header() {
+        assert!(Vsop87::parse(" 1310 1 0.5 0.0 0.0\n").is_err());
+    }
+
+    /// Run with `cargo test -- --ignored` once the data files are installed
+    #[test]
+    #[ignore = "needs the VSOP87A data files, see Vsop87::from_env"]
+    fn matches_check_values_at_j2000() {
+        let vsop87 = Vsop87::from_env().expect("VSOP87 data is not available");
+        // Check values published with the VSOP87A files (vsop87.chk)
+        let references = [
+            (2, [-0.177_135_458_6, 0.967_241_623_7, -0.000_003_900_0]),
+            (3, [1.390_715_926_4, -0.013_415_704_3, -0.034_467_796_7]),
+            (4, [4.001_174_026_8, 2.938_581_007_7, -0.101_783_750_1]),
+        ];
+        for (planet, reference) in references {
+            let position = vsop87.position(planet, SimulationClock::J2000);
+            let error = position.distance(glam::DVec3::from(reference));
+            assert!(error < 1e-5, "Planet {planet} is {error:e} AU away");
+        }
+    }
+}